@@ -0,0 +1,351 @@
+//! Post-game analysis grades every ply by how much worse it was than the
+//! engine's choice. Each ply is judged twice: with full information, and from
+//! the mover's fog view by averaging over boards consistent with what they
+//! could see. A move that only looks bad with full information was bad luck;
+//! one that looks bad from the fog view too was bad play.
+
+use crate::bot::{determinize, evaluate, AlphaBeta};
+use crate::{arena, pgn, Board, Game, Move, Player, State, Step};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+// Scores are capped at this many centipawns either way, so a lost king counts
+// as a large but comparable loss.
+const CAP: i32 = 1_000;
+
+/// Search budgets for analysis.
+#[derive(Clone, Debug)]
+pub struct AnalysisConfig {
+    /// Search depth in plies.
+    pub depth: u32,
+    /// Maximum time per search.
+    pub time: Duration,
+    /// Number of boards sampled from each fog view.
+    pub samples: u32,
+    /// Seeds the fog view samples.
+    pub seed: u64,
+}
+
+impl Default for AnalysisConfig {
+    fn default() -> Self {
+        AnalysisConfig {
+            depth: 3,
+            time: Duration::from_millis(100),
+            samples: 6,
+            seed: 0,
+        }
+    }
+}
+
+/// Grade classifies a ply by the centipawns it lost.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum Grade {
+    Good,
+    Inaccuracy,
+    Mistake,
+    Blunder,
+}
+
+impl Grade {
+    pub fn from_loss(loss: i32) -> Self {
+        match loss {
+            loss if loss >= 300 => Grade::Blunder,
+            loss if loss >= 100 => Grade::Mistake,
+            loss if loss >= 50 => Grade::Inaccuracy,
+            _ => Grade::Good,
+        }
+    }
+    /// Symbol is the conventional annotation, such as `??` for a blunder.
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Grade::Good => "",
+            Grade::Inaccuracy => "?!",
+            Grade::Mistake => "?",
+            Grade::Blunder => "??",
+        }
+    }
+}
+
+impl fmt::Display for Grade {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Grade::Good => "good",
+            Grade::Inaccuracy => "inaccuracy",
+            Grade::Mistake => "mistake",
+            Grade::Blunder => "blunder",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Judgement of a single ply from one point of view.
+#[derive(Clone, Debug)]
+pub struct Judgement {
+    pub grade: Grade,
+    /// Centipawns lost against the best move.
+    pub loss: i32,
+    /// Best move in standard algebraic notation, if the search found one.
+    pub best: Option<String>,
+}
+
+impl fmt::Display for Judgement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.grade)?;
+        if self.grade != Grade::Good {
+            write!(f, " -{:.2}", f64::from(self.loss) / 100.0)?;
+            if let Some(best) = &self.best {
+                write!(f, ", best {}", best)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Annotation grades one ply of a game.
+#[derive(Clone, Debug)]
+pub struct Annotation {
+    pub player: Player,
+    /// Judged with full information.
+    pub full: Judgement,
+    /// Judged from the mover's fog view.
+    pub fog: Judgement,
+}
+
+impl Annotation {
+    /// Comment summarises the annotation for a PGN comment, or `None` for a
+    /// ply that was good either way.
+    pub fn comment(&self) -> Option<String> {
+        let worst = self.full.grade.max(self.fog.grade);
+        if worst == Grade::Good {
+            return None;
+        }
+        let verdict = if self.fog.grade >= Grade::Mistake {
+            "bad play"
+        } else if self.full.grade >= Grade::Mistake {
+            "bad luck"
+        } else {
+            "imprecise"
+        };
+        Some(format!(
+            "{} full: {}; fog: {}; {}",
+            worst.symbol(),
+            self.full,
+            self.fog,
+            verdict
+        ))
+    }
+}
+
+/// Analyse replays the game from its initial state, grading every ply of its
+/// history.
+pub fn analyse(game: &Game, config: &AnalysisConfig) -> Result<Vec<Annotation>, String> {
+    analyse_steps(&game.initial, &game.state.history, config)
+}
+
+/// Analyse steps grades each of the steps played from the initial state.
+pub fn analyse_steps(
+    initial: &State,
+    steps: &[Step],
+    config: &AnalysisConfig,
+) -> Result<Vec<Annotation>, String> {
+    // Replies are searched one ply shallower so both sides of the comparison
+    // look equally far ahead.
+    let mut engines = (
+        AlphaBeta::with_budget(config.depth.max(1), config.time),
+        AlphaBeta::with_budget(config.depth.max(2) - 1, config.time),
+    );
+    let mut game = Game::new(initial.clone());
+    let mut annotations = vec![];
+    for (ii, step) in steps.iter().enumerate() {
+        let board = game.state.board.clone();
+        let view = game.view_for(&step.player);
+        let mut rng = StdRng::seed_from_u64(arena::mix(config.seed, ii as u64));
+        let samples: Vec<Board> = (0..config.samples.max(1))
+            .map(|_| determinize(&view, &mut rng))
+            .collect();
+        annotations.push(Annotation {
+            player: step.player,
+            full: judge(&mut engines, &board, std::slice::from_ref(&board), step),
+            fog: judge(&mut engines, &board, &samples, step),
+        });
+        if !game.apply(step) {
+            return Err(format!("ply {} is not a legal move", ii + 1));
+        }
+    }
+    Ok(annotations)
+}
+
+/// Comments renders annotations as PGN comments, one per ply.
+pub fn comments(annotations: &[Annotation]) -> Vec<Option<String>> {
+    annotations.iter().map(Annotation::comment).collect()
+}
+
+/// Summary counts each player's inaccuracies, mistakes and blunders with full
+/// information and from their fog view.
+pub fn summary(annotations: &[Annotation]) -> String {
+    let mut out = String::new();
+    for player in [Player::White, Player::Black].iter() {
+        for fog in [false, true].iter() {
+            let count = |grade: Grade| {
+                annotations
+                    .iter()
+                    .filter(|a| a.player == *player)
+                    .filter(|a| if *fog { a.fog.grade } else { a.full.grade } == grade)
+                    .count()
+            };
+            out.push_str(&format!(
+                "{:?} ({}): {} inaccuracies, {} mistakes, {} blunders\n",
+                player,
+                if *fog { "fog" } else { "full" },
+                count(Grade::Inaccuracy),
+                count(Grade::Mistake),
+                count(Grade::Blunder),
+            ));
+        }
+    }
+    out
+}
+
+// Judge the step by its average loss over the sampled boards, naming the
+// move the engine preferred most often. The step is named on the true board,
+// where it is always legal.
+fn judge(
+    (engine, replies): &mut (AlphaBeta, AlphaBeta),
+    board: &Board,
+    samples: &[Board],
+    step: &Step,
+) -> Judgement {
+    let player = step.player;
+    let mut total = 0;
+    let mut judged = 0;
+    let mut votes: HashMap<Move, u32> = HashMap::new();
+    for sample in samples {
+        let (best, best_score) = match engine.search(sample, player) {
+            Some(found) => found,
+            None => continue,
+        };
+        let mut after = sample.clone();
+        for (from, to) in step.moves.iter() {
+            after.move_piece(*from, *to);
+        }
+        let played = match after.winner() {
+            Some(winner) if winner == player => CAP,
+            Some(_) => -CAP,
+            None => match replies.search(&after, player.opponent()) {
+                Some((_, score)) => -score,
+                None => evaluate(&after, &player),
+            },
+        };
+        let loss = best_score.clamp(-CAP, CAP) - played.clamp(-CAP, CAP);
+        total += loss.max(0);
+        judged += 1;
+        *votes.entry(best).or_default() += 1;
+    }
+    let loss = if judged == 0 { 0 } else { total / judged };
+    let best = votes
+        .into_iter()
+        .max_by_key(|(mv, count)| (*count, std::cmp::Reverse(*mv)))
+        .map(|(mv, _)| mv)
+        .filter(|mv| step.moves != [*mv])
+        .map(|mv| {
+            pgn::san(
+                board,
+                &Step {
+                    player,
+                    moves: vec![mv],
+                },
+            )
+        });
+    Judgement {
+        grade: Grade::from_loss(loss),
+        loss,
+        best,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn judgement(grade: Grade) -> Judgement {
+        Judgement {
+            grade,
+            loss: 0,
+            best: None,
+        }
+    }
+
+    #[test]
+    fn grades_follow_the_thresholds() {
+        assert_eq!(Grade::from_loss(49), Grade::Good);
+        assert_eq!(Grade::from_loss(50), Grade::Inaccuracy);
+        assert_eq!(Grade::from_loss(100), Grade::Mistake);
+        assert_eq!(Grade::from_loss(299), Grade::Mistake);
+        assert_eq!(Grade::from_loss(300), Grade::Blunder);
+    }
+
+    #[test]
+    fn comments_tell_bad_play_from_bad_luck() {
+        let annotate = |full, fog| Annotation {
+            player: Player::White,
+            full: judgement(full),
+            fog: judgement(fog),
+        };
+        assert_eq!(annotate(Grade::Good, Grade::Good).comment(), None);
+        let luck = annotate(Grade::Blunder, Grade::Good).comment().unwrap();
+        assert!(
+            luck.starts_with("??") && luck.ends_with("bad luck"),
+            "{}",
+            luck
+        );
+        let play = annotate(Grade::Good, Grade::Mistake).comment().unwrap();
+        assert!(
+            play.starts_with("?") && play.ends_with("bad play"),
+            "{}",
+            play
+        );
+        let slip = annotate(Grade::Inaccuracy, Grade::Good).comment().unwrap();
+        assert!(slip.ends_with("imprecise"), "{}", slip);
+    }
+
+    #[test]
+    fn hanging_the_queen_is_a_blunder() {
+        let mut game = Game::headless(Board::new(), false, 5);
+        let plies = [
+            ((4, 1), (4, 3)),
+            ((4, 6), (4, 4)),
+            ((3, 0), (7, 4)),
+            ((1, 7), (2, 5)),
+            // The queen takes a pawn the knight guards.
+            ((7, 4), (4, 4)),
+        ];
+        for mv in plies.iter() {
+            assert!(game.make_move(*mv));
+        }
+        let config = AnalysisConfig {
+            depth: 2,
+            time: Duration::from_secs(10),
+            samples: 2,
+            seed: 5,
+        };
+        let annotations = analyse(&game, &config).unwrap();
+        assert_eq!(annotations.len(), plies.len());
+        let blunder = &annotations[4];
+        assert_eq!(blunder.player, Player::White);
+        assert_eq!(blunder.full.grade, Grade::Blunder);
+        assert!(blunder.full.loss >= 300);
+        assert!(blunder.full.best.is_some());
+        let pgn = pgn::export(
+            &[],
+            &game.initial.board,
+            &game.state.history,
+            &comments(&annotations),
+            "*",
+        );
+        let pgn = pgn.split_whitespace().collect::<Vec<_>>().join(" ");
+        assert!(pgn.contains("3. Qxe5 {?? full: blunder -"), "{}", pgn);
+    }
+}
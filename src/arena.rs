@@ -0,0 +1,356 @@
+//! Arena plays headless games between bots.
+
+use crate::bot::{self, Bot};
+use crate::{pgn, Board, Game, Player};
+use rand::Rng;
+use std::fmt;
+
+/// Outcome of a finished game.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Outcome {
+    Win(Player),
+    Draw,
+}
+
+impl Outcome {
+    /// Result in PGN notation, for example `1-0`.
+    pub fn pgn(&self) -> &'static str {
+        match self {
+            Outcome::Win(Player::White) => "1-0",
+            Outcome::Win(Player::Black) => "0-1",
+            Outcome::Draw => "1/2-1/2",
+        }
+    }
+    /// Score of the outcome for the given player: 1 for a win, 0.5 for a
+    /// draw and 0 for a loss.
+    pub fn score(&self, player: &Player) -> f64 {
+        match self {
+            Outcome::Win(winner) if winner == player => 1.0,
+            Outcome::Win(_) => 0.0,
+            Outcome::Draw => 0.5,
+        }
+    }
+}
+
+/// Record of a single finished game.
+#[derive(Clone, Debug)]
+pub struct Record {
+    pub round: u32,
+    pub white: String,
+    pub black: String,
+    pub outcome: Outcome,
+    pub plies: u32,
+    pub termination: String,
+    pub pgn: String,
+}
+
+impl Record {
+    /// Csv renders the record as a line matching `CSV_HEADER`.
+    pub fn csv(&self) -> String {
+        format!(
+            "{},{},{},{},{},{}",
+            self.round,
+            self.white,
+            self.black,
+            self.outcome.pgn(),
+            self.plies,
+            self.termination
+        )
+    }
+}
+
+/// Header line for records rendered with `Record::csv`.
+pub const CSV_HEADER: &str = "game,white,black,result,plies,termination";
+
+/// Settings for a match between two bots.
+#[derive(Clone, Debug)]
+pub struct MatchConfig {
+    pub games: u32,
+    /// Games still undecided after this many plies are drawn.
+    pub max_plies: u32,
+    pub no_fog: bool,
+    /// Seeds every game in the match, making it reproducible.
+    pub seed: Option<u64>,
+}
+
+/// Play a game to completion.
+pub fn play(
+    white: &mut dyn Bot,
+    black: &mut dyn Bot,
+    mut game: Game,
+    max_plies: u32,
+) -> (Game, Outcome, &'static str) {
+    loop {
+        if let Some(winner) = game.winner() {
+            return (game, Outcome::Win(winner), "king captured");
+        }
+        if game.state.ply >= max_plies {
+            return (game, Outcome::Draw, "move limit");
+        }
+        let turn = game.state.turn;
+        let view = game.view_for(&turn);
+        let (chosen, name) = match turn {
+            Player::White => (white.choose(&view), white.name()),
+            Player::Black => (black.choose(&view), black.name()),
+        };
+        match chosen {
+            Ok(Some(mv)) if game.make_move(mv) => {}
+            // An illegal move or a failed bot forfeits, so a broken bot can't
+            // stall a long match or draw its way through it.
+            Ok(Some(_)) => return (game, Outcome::Win(turn.opponent()), "illegal move"),
+            Err(err) => {
+                eprintln!("bot {:?}: {}", name, err);
+                return (game, Outcome::Win(turn.opponent()), "bot failed");
+            }
+            Ok(None) => return (game, Outcome::Draw, "no moves"),
+        }
+    }
+}
+
+/// Record a finished game, rendering it as PGN.
+pub fn record(
+    game: &Game,
+    round: u32,
+    white: &str,
+    black: &str,
+    outcome: Outcome,
+    termination: &str,
+) -> Record {
+    let tags = [
+        ("Event", "Fog of Chess match".to_string()),
+        ("Site", "?".to_string()),
+        ("Date", "????.??.??".to_string()),
+        ("Round", round.to_string()),
+        ("White", white.to_string()),
+        ("Black", black.to_string()),
+        ("Result", outcome.pgn().to_string()),
+        (
+            "Variant",
+            if game.state.no_fog {
+                "Standard"
+            } else {
+                "Fog of War"
+            }
+            .to_string(),
+        ),
+        ("Seed", game.state.seed.to_string()),
+        ("PlyCount", game.state.ply.to_string()),
+        ("Termination", termination.to_string()),
+    ];
+    Record {
+        round,
+        white: white.into(),
+        black: black.into(),
+        outcome,
+        plies: game.state.ply,
+        termination: termination.into(),
+        pgn: pgn::export(
+            &tags,
+            &game.initial.board,
+            &game.state.history,
+            &[],
+            outcome.pgn(),
+        ),
+    }
+}
+
+/// Run a match between bots `a` and `b`, identified by name, alternating
+/// colours so that `a` plays White in odd rounds. Each record is passed to
+/// `on_game` as soon as its game finishes.
+pub fn run(
+    a: &str,
+    b: &str,
+    config: &MatchConfig,
+    mut on_game: impl FnMut(&Record),
+) -> Result<Vec<Record>, String> {
+    let mut records = vec![];
+    for ii in 0..config.games {
+        let round = ii + 1;
+        let seed = config
+            .seed
+            .map_or_else(rand::random, |seed| mix(seed, u64::from(ii)));
+        let mut game = Game::headless(Board::new(), config.no_fog, seed);
+        let mut first =
+            bot::by_name(a, Some(game.state.rng.gen())).ok_or(format!("unknown bot {:?}", a))?;
+        let mut second =
+            bot::by_name(b, Some(game.state.rng.gen())).ok_or(format!("unknown bot {:?}", b))?;
+        let (white, black, white_name, black_name) = if ii % 2 == 0 {
+            (&mut first, &mut second, a, b)
+        } else {
+            (&mut second, &mut first, b, a)
+        };
+        let (game, outcome, termination) =
+            play(white.as_mut(), black.as_mut(), game, config.max_plies);
+        let record = record(&game, round, white_name, black_name, outcome, termination);
+        on_game(&record);
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// Mix derives an independent seed for the given stream from a base seed
+/// (SplitMix64).
+pub(crate) fn mix(seed: u64, stream: u64) -> u64 {
+    let mut z = seed.wrapping_add(stream.wrapping_add(1).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Score tallies results from one bot's point of view.
+#[derive(Clone, Debug, Default)]
+pub struct Score {
+    pub name: String,
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+}
+
+impl Score {
+    /// Tally the records for the named bot.
+    pub fn tally(name: &str, records: &[Record]) -> Self {
+        let mut score = Score {
+            name: name.into(),
+            ..Score::default()
+        };
+        for record in records {
+            let player = if record.white == name {
+                Player::White
+            } else if record.black == name {
+                Player::Black
+            } else {
+                continue;
+            };
+            match record.outcome.score(&player) {
+                s if s > 0.5 => score.wins += 1,
+                s if s < 0.5 => score.losses += 1,
+                _ => score.draws += 1,
+            }
+        }
+        score
+    }
+    pub fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+    /// Mean score per game, in `[0, 1]`.
+    pub fn mean(&self) -> f64 {
+        if self.games() == 0 {
+            return 0.5;
+        }
+        (f64::from(self.wins) + f64::from(self.draws) / 2.0) / f64::from(self.games())
+    }
+    /// Margin is the half width of the 95% confidence interval around the
+    /// mean score.
+    pub fn margin(&self) -> f64 {
+        let n = f64::from(self.games());
+        if n < 2.0 {
+            return 0.5;
+        }
+        let mean = self.mean();
+        let variance = (f64::from(self.wins) * (1.0 - mean).powi(2)
+            + f64::from(self.draws) * (0.5 - mean).powi(2)
+            + f64::from(self.losses) * mean.powi(2))
+            / n;
+        1.96 * (variance / n).sqrt()
+    }
+}
+
+/// Elo converts a mean score into a rating difference.
+pub fn elo(score: f64) -> f64 {
+    let score = score.clamp(1e-9, 1.0 - 1e-9);
+    -400.0 * (1.0 / score - 1.0).log10()
+}
+
+impl fmt::Display for Score {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (mean, margin) = (self.mean(), self.margin());
+        write!(
+            f,
+            "{}: +{} ={} -{} score {:.3} ± {:.3} (95%), elo {:+.0} [{:+.0}, {:+.0}]",
+            self.name,
+            self.wins,
+            self.draws,
+            self.losses,
+            mean,
+            margin,
+            elo(mean),
+            elo(mean - margin),
+            elo(mean + margin),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failed_bots_forfeit() {
+        let mut crashed = bot::by_spec("cmd:true", None).unwrap();
+        let mut random = bot::RandomBot::seeded(1);
+        let game = Game::headless(Board::new(), false, 1);
+        let (_, outcome, termination) = play(crashed.as_mut(), &mut random, game, 100);
+        assert_eq!(outcome, Outcome::Win(Player::Black));
+        assert_eq!(termination, "bot failed");
+    }
+
+    #[test]
+    fn scores_have_hand_checked_margins_and_ratings() {
+        let score = Score {
+            name: "a".into(),
+            wins: 6,
+            draws: 2,
+            losses: 2,
+        };
+        assert_eq!(score.games(), 10);
+        assert!((score.mean() - 0.7).abs() < 1e-12);
+        // Variance (6 * 0.3² + 2 * 0.2² + 2 * 0.7²) / 10 = 0.16 per game.
+        assert!((score.margin() - 1.96 * 0.016f64.sqrt()).abs() < 1e-12);
+        assert!((elo(0.7) - 147.19).abs() < 0.01);
+        assert!(elo(0.5).abs() < 1e-9);
+        assert!((elo(0.25) + elo(0.75)).abs() < 1e-9);
+        assert!(elo(1.0).is_finite() && elo(0.0).is_finite());
+        let single = Score {
+            wins: 1,
+            ..Score::default()
+        };
+        assert_eq!(single.margin(), 0.5);
+    }
+
+    #[test]
+    fn matches_alternate_colours_and_replay_from_their_seed() {
+        let config = MatchConfig {
+            games: 4,
+            max_plies: 2,
+            no_fog: false,
+            seed: Some(7),
+        };
+        let records = run("random", "alphabeta", &config, |_| {}).unwrap();
+        let whites: Vec<&str> = records.iter().map(|r| r.white.as_str()).collect();
+        assert_eq!(whites, ["random", "alphabeta", "random", "alphabeta"]);
+        let rounds: Vec<u32> = records.iter().map(|r| r.round).collect();
+        assert_eq!(rounds, [1, 2, 3, 4]);
+        let seeds: std::collections::HashSet<&str> = records
+            .iter()
+            .filter_map(|r| r.pgn.lines().find(|line| line.starts_with("[Seed ")))
+            .collect();
+        assert_eq!(seeds.len(), 4);
+        let again = run("random", "alphabeta", &config, |_| {}).unwrap();
+        for (a, b) in records.iter().zip(again.iter()) {
+            assert_eq!(a.pgn, b.pgn);
+        }
+        let score = Score::tally("random", &records);
+        assert_eq!(score.games(), 4);
+        assert_eq!(score.draws, 4);
+    }
+
+    #[test]
+    fn mixed_seeds_differ_by_stream_and_seed() {
+        assert_eq!(mix(1, 0), mix(1, 0));
+        assert_ne!(mix(1, 0), mix(1, 1));
+        assert_ne!(mix(1, 0), mix(2, 0));
+        let seeds: std::collections::HashSet<u64> =
+            (0..1000).map(|stream| mix(42, stream)).collect();
+        assert_eq!(seeds.len(), 1000);
+    }
+}
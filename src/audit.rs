@@ -0,0 +1,484 @@
+//! Audit keeps a tamper-evident record of each hosted game. The server is the
+//! only party that sees the whole board, so the log lets anyone check after
+//! the fact that it played by the rules and never showed a player a square
+//! outside their vision.
+//!
+//! A log has one entry per line: the entry's SHA-256 hash in hex, a space,
+//! then the entry as JSON. Each hash covers the previous entry's hash
+//! followed by the entry's JSON, chaining the entries together, so changing,
+//! removing or reordering any entry breaks every hash after it. Entries are
+//! only ever appended. The server prints the final hash when a game ends;
+//! kept somewhere else, it also shows whether the whole log was rewritten.
+
+use crate::protocol::GameSettings;
+use crate::{notation, Board, Game, Move, Player, Step};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::time::Instant;
+
+// Hash preceding the first entry.
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Record is one line of the log.
+#[derive(Deserialize, Serialize)]
+pub struct Record {
+    /// Position of the entry in the log, counting from 0.
+    pub seq: u64,
+    /// Milliseconds since the log was started.
+    pub at_ms: u64,
+    pub entry: Entry,
+}
+
+/// Entry is something the server did, or was asked to do.
+#[derive(Deserialize, Serialize)]
+pub enum Entry {
+    /// The game was created. Always the first entry.
+    Started {
+        game: u64,
+        settings: GameSettings,
+        seed: u64,
+        board: Board,
+        /// Plies spectators are kept behind play.
+        #[serde(default)]
+        spectator_delay: u32,
+    },
+    /// A client took a seat, or resumed it.
+    Seated { player: Player, name: String },
+    /// A seated client disconnected.
+    Left { player: Player },
+    /// A move was played.
+    Moved { player: Player, moves: Vec<Move> },
+    /// A move was refused.
+    Rejected {
+        player: Player,
+        moves: Vec<Move>,
+        reason: String,
+    },
+    /// The board a player was sent, and the ply it was sent at.
+    Sent {
+        player: Player,
+        ply: u32,
+        board: Board,
+    },
+    /// The whole board spectators were sent while the game was in play, the
+    /// ply it was from, and the delay behind play it was held back by.
+    Shown { ply: u32, delay: u32, board: Board },
+    /// The clocks as sent to the players.
+    Clock {
+        remaining_ms: [u64; 2],
+        running: Option<Player>,
+    },
+    /// The game ended. Always the last entry.
+    Ended {
+        winner: Option<Player>,
+        reason: String,
+    },
+}
+
+/// Log appends entries to an audit log.
+pub struct Log {
+    file: File,
+    // Hash of the newest entry.
+    last: String,
+    seq: u64,
+    start: Instant,
+}
+
+impl Log {
+    /// Create starts a log at the path, which must not already exist, with
+    /// its first entry.
+    pub fn create(path: &Path, started: Entry) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .append(true)
+            .create_new(true)
+            .open(path)?;
+        let mut log = Log {
+            file,
+            last: GENESIS.into(),
+            seq: 0,
+            start: Instant::now(),
+        };
+        log.record(started)?;
+        Ok(log)
+    }
+    /// Record appends an entry.
+    pub fn record(&mut self, entry: Entry) -> io::Result<()> {
+        let record = Record {
+            seq: self.seq,
+            at_ms: self.start.elapsed().as_millis() as u64,
+            entry,
+        };
+        let json = serde_json::to_string(&record)?;
+        let hash = chain(&self.last, &json);
+        writeln!(self.file, "{} {}", hash, json)?;
+        self.last = hash;
+        self.seq += 1;
+        Ok(())
+    }
+    /// Last hash is the hash of the newest entry, which vouches for every
+    /// entry before it.
+    pub fn last_hash(&self) -> &str {
+        &self.last
+    }
+}
+
+// Hash an entry onto the chain.
+fn chain(previous: &str, json: &str) -> String {
+    Sha256::new()
+        .chain_update(previous)
+        .chain_update(json)
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Summary describes a log that passed verification.
+#[derive(Debug)]
+pub struct Summary {
+    pub entries: usize,
+    pub moves: usize,
+    /// Boards sent to players, each checked against their vision.
+    pub views: usize,
+    /// Boards sent to spectators, each checked to trail play.
+    pub shown: usize,
+    /// Hash of the last entry.
+    pub last: String,
+    /// Whether the log records the end of the game. A log without an end is
+    /// of a game still in play, or was cut short.
+    pub ended: bool,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} entries, {} moves, {} views and {} spectator boards verified, {}, last hash {}",
+            self.entries,
+            self.moves,
+            self.views,
+            self.shown,
+            if self.ended {
+                "game ended"
+            } else {
+                "no end recorded"
+            },
+            self.last
+        )
+    }
+}
+
+/// Verify checks a log: that its hash chain is intact, that the rules engine
+/// replaying the game agrees with every move played and rejected, and that
+/// every board sent to a player showed only pieces that were really there,
+/// on squares the player could see, and that spectators were only shown past
+/// positions, at least the configured delay behind play. Returns the first
+/// problem found.
+pub fn verify(reader: impl BufRead) -> Result<Summary, String> {
+    let mut summary = Summary {
+        entries: 0,
+        moves: 0,
+        views: 0,
+        shown: 0,
+        last: GENESIS.into(),
+        ended: false,
+    };
+    let mut game: Option<Game> = None;
+    // Every position the game has been in, and how far behind play
+    // spectators must be kept.
+    let mut positions: Vec<Board> = vec![];
+    let mut delay = 0;
+    // Players seated, and whether both have been at once, which starts the
+    // game. Moves are refused until it starts.
+    let mut seated = [false; 2];
+    let mut started = false;
+    for (ii, line) in reader.lines().enumerate() {
+        let fail = |reason: String| format!("line {}: {}", ii + 1, reason);
+        let line = line.map_err(|err| fail(err.to_string()))?;
+        let (hash, json) = line
+            .split_once(' ')
+            .ok_or_else(|| fail("missing hash".into()))?;
+        if chain(&summary.last, json) != hash {
+            return Err(fail("hash does not match, the log was altered".into()));
+        }
+        summary.last = hash.into();
+        let record: Record = serde_json::from_str(json).map_err(|err| fail(err.to_string()))?;
+        if record.seq != ii as u64 {
+            return Err(fail(format!("entry {} out of place", record.seq)));
+        }
+        if summary.ended {
+            return Err(fail("entry after the game ended".into()));
+        }
+        summary.entries += 1;
+        let entry = match (record.entry, game.is_some()) {
+            (
+                Entry::Started {
+                    settings,
+                    seed,
+                    board,
+                    spectator_delay,
+                    ..
+                },
+                false,
+            ) => {
+                positions.push(board.clone());
+                delay = spectator_delay;
+                game = Some(Game::headless(board, !settings.fog, seed));
+                continue;
+            }
+            (Entry::Started { .. }, true) => return Err(fail("game started twice".into())),
+            (_, false) => return Err(fail("the log does not start with the game".into())),
+            (entry, true) => entry,
+        };
+        let game = game.as_mut().expect("game was started");
+        match entry {
+            Entry::Seated { player, .. } => {
+                seated[player.index()] = true;
+                started |= seated.iter().all(|seated| *seated);
+            }
+            // Seats are given up by leaving before the game starts.
+            Entry::Left { player } if !started => seated[player.index()] = false,
+            Entry::Moved { player, moves } => {
+                if game.state.turn != player || !started || !game.apply(&Step { player, moves }) {
+                    return Err(fail(format!("illegal move played for {:?}", player)));
+                }
+                positions.push(game.state.board.clone());
+                summary.moves += 1;
+            }
+            Entry::Rejected { player, moves, .. } => {
+                if game.state.turn == player
+                    && started
+                    && game.clone().apply(&Step { player, moves })
+                {
+                    return Err(fail(format!("legal move rejected for {:?}", player)));
+                }
+            }
+            Entry::Sent { player, ply, board } => {
+                if ply != game.state.ply {
+                    return Err(fail(format!(
+                        "{:?} was sent ply {} during ply {}",
+                        player, ply, game.state.ply
+                    )));
+                }
+                let visible = game.view_for(&player).visible;
+                for y in 0..8 {
+                    for x in 0..8 {
+                        let sent = match board.get((x, y)) {
+                            Some(sent) => sent,
+                            None => continue,
+                        };
+                        if !visible[y as usize][x as usize] {
+                            return Err(fail(format!(
+                                "{:?} was sent {} outside their vision",
+                                player,
+                                notation((x, y))
+                            )));
+                        }
+                        match game.state.board.get((x, y)) {
+                            Some(real) if real.unit == sent.unit && real.player == sent.player => {}
+                            _ => {
+                                return Err(fail(format!(
+                                    "{:?} was sent a {} on {} that is not there",
+                                    player,
+                                    sent.unit,
+                                    notation((x, y))
+                                )))
+                            }
+                        }
+                    }
+                }
+                summary.views += 1;
+            }
+            Entry::Shown {
+                ply,
+                delay: held,
+                board,
+            } => {
+                if held < delay {
+                    return Err(fail(format!(
+                        "spectators were held {} plies behind, not {}",
+                        held, delay
+                    )));
+                }
+                if ply > game.state.ply.saturating_sub(held) {
+                    return Err(fail(format!(
+                        "spectators were shown ply {} during ply {}",
+                        ply, game.state.ply
+                    )));
+                }
+                let real = &positions[ply as usize];
+                let same = (0..8).flat_map(|y| (0..8).map(move |x| (x, y))).all(|at| {
+                    match (board.get(at), real.get(at)) {
+                        (Some(a), Some(b)) => a.unit == b.unit && a.player == b.player,
+                        (None, None) => true,
+                        _ => false,
+                    }
+                });
+                if !same {
+                    return Err(fail(format!(
+                        "spectators were shown a board that was not ply {}",
+                        ply
+                    )));
+                }
+                summary.shown += 1;
+            }
+            Entry::Ended { .. } => summary.ended = true,
+            Entry::Left { .. } | Entry::Clock { .. } | Entry::Started { .. } => {}
+        }
+    }
+    if summary.entries == 0 {
+        return Err("the log is empty".into());
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::Cursor;
+    use std::path::PathBuf;
+
+    // Record a short game, then let the test add to it, returning the log's
+    // lines.
+    fn record(name: &str, extra: impl FnOnce(&mut Log, &mut Game)) -> Vec<String> {
+        let path: PathBuf = std::env::temp_dir().join(format!(
+            "fog-of-chess-audit-{}-{}.log",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_file(&path);
+        let started = Entry::Started {
+            game: 1,
+            settings: GameSettings::default(),
+            seed: 7,
+            board: Board::new(),
+            spectator_delay: 2,
+        };
+        let mut log = Log::create(&path, started).unwrap();
+        let mut game = Game::headless(Board::new(), false, 7);
+        for (player, name) in [(Player::White, "anna"), (Player::Black, "bob")] {
+            let name = name.into();
+            log.record(Entry::Seated { player, name }).unwrap();
+        }
+        let plies = [((4, 1), (4, 3)), ((4, 6), (4, 4)), ((6, 0), (5, 2))];
+        for mv in plies.iter() {
+            let player = game.state.turn;
+            assert!(game.apply(&Step {
+                player,
+                moves: vec![*mv],
+            }));
+            log.record(Entry::Moved {
+                player,
+                moves: vec![*mv],
+            })
+            .unwrap();
+            for player in [Player::White, Player::Black] {
+                let view = game.view_for(&player);
+                let (ply, board) = (view.ply, view.board);
+                log.record(Entry::Sent { player, ply, board }).unwrap();
+            }
+        }
+        extra(&mut log, &mut game);
+        let text = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        text.lines().map(String::from).collect()
+    }
+
+    fn check(lines: &[String]) -> Result<Summary, String> {
+        verify(Cursor::new(lines.join("\n")))
+    }
+
+    #[test]
+    fn honest_games_verify() {
+        let lines = record("honest", |log, _| {
+            let rejected = Entry::Rejected {
+                player: Player::White,
+                moves: vec![((0, 1), (0, 3))],
+                reason: "not your turn".into(),
+            };
+            log.record(rejected).unwrap();
+            let shown = Entry::Shown {
+                ply: 0,
+                delay: 2,
+                board: Board::new(),
+            };
+            log.record(shown).unwrap();
+            let ended = Entry::Ended {
+                winner: Some(Player::White),
+                reason: "resignation".into(),
+            };
+            log.record(ended).unwrap();
+        });
+        let summary = check(&lines).unwrap();
+        assert_eq!(
+            (summary.entries, summary.moves, summary.views, summary.shown),
+            (lines.len(), 3, 6, 1)
+        );
+        assert!(summary.ended);
+    }
+
+    #[test]
+    fn tampering_breaks_the_chain() {
+        let mut lines = record("tampered", |_, _| {});
+        lines[3] = lines[3].replace("[[4,1],[4,3]]", "[[4,1],[4,2]]");
+        assert!(check(&lines).unwrap_err().contains("altered"));
+        let mut lines = record("removed", |_, _| {});
+        lines.remove(4);
+        assert!(check(&lines).unwrap_err().contains("altered"));
+    }
+
+    #[test]
+    fn leaks_are_found() {
+        let lines = record("leaked", |log, game| {
+            let leak = Entry::Sent {
+                player: Player::White,
+                ply: game.state.ply,
+                board: game.state.board.clone(),
+            };
+            log.record(leak).unwrap();
+        });
+        let err = check(&lines).unwrap_err();
+        assert!(err.contains("outside their vision"), "{}", err);
+    }
+
+    #[test]
+    fn spectators_shown_current_play_are_found() {
+        let lines = record("watched", |log, game| {
+            let leak = Entry::Shown {
+                ply: game.state.ply,
+                delay: 2,
+                board: game.state.board.clone(),
+            };
+            log.record(leak).unwrap();
+        });
+        let err = check(&lines).unwrap_err();
+        assert!(err.contains("shown ply 3 during ply 3"), "{}", err);
+        let lines = record("undelayed", |log, game| {
+            let leak = Entry::Shown {
+                ply: game.state.ply,
+                delay: 0,
+                board: game.state.board.clone(),
+            };
+            log.record(leak).unwrap();
+        });
+        let err = check(&lines).unwrap_err();
+        assert!(err.contains("held 0 plies behind, not 2"), "{}", err);
+    }
+
+    #[test]
+    fn illegal_moves_are_found() {
+        let lines = record("illegal", |log, _| {
+            let illegal = Entry::Moved {
+                player: Player::Black,
+                moves: vec![((3, 7), (3, 0))],
+            };
+            log.record(illegal).unwrap();
+        });
+        assert!(check(&lines).unwrap_err().contains("illegal move"));
+    }
+}
@@ -0,0 +1,121 @@
+//! Bots choose moves for a player, seeing only what that player can see.
+
+use crate::{Belief, Board, Move, Player, PlayerView, Position, Unit};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+
+mod alphabeta;
+mod ismcts;
+mod personality;
+mod process;
+
+pub use alphabeta::{centipawns, evaluate, AlphaBeta, Difficulty};
+pub use ismcts::{determinize, Ismcts, IsmctsConfig};
+pub use personality::{load_personalities, Styled};
+pub use process::{ProcessBot, ProtocolBot};
+
+/// Names of the built-in bots, as accepted by `by_name`.
+pub const BOTS: [&str; 3] = ["random", "ismcts", "alphabeta"];
+
+/// Bot is a computer opponent.
+pub trait Bot {
+    /// Name identifies the bot in logs and results.
+    fn name(&self) -> String;
+    /// Choose a move for the viewing player, or `None` if there is nothing
+    /// to play. An error means the bot itself failed, for example because
+    /// its process crashed or stopped answering.
+    fn choose(&mut self, view: &PlayerView) -> Result<Option<Move>, String>;
+    /// Belief is where the bot thought hidden enemy units were when it last
+    /// chose a move, for bots that sample boards to decide.
+    fn belief(&self) -> Option<Belief> {
+        None
+    }
+}
+
+/// By name constructs a built-in bot with its default settings. Bots that
+/// use randomness are seeded when a seed is given.
+pub fn by_name(name: &str, seed: Option<u64>) -> Option<Box<dyn Bot>> {
+    match (name, seed) {
+        ("random", Some(seed)) => Some(Box::new(RandomBot::seeded(seed))),
+        ("random", None) => Some(Box::new(RandomBot::new())),
+        ("ismcts", Some(seed)) => Some(Box::new(Ismcts::seeded(IsmctsConfig::default(), seed))),
+        ("ismcts", None) => Some(Box::new(Ismcts::new(IsmctsConfig::default()))),
+        ("alphabeta", _) => Some(Box::new(AlphaBeta::default())),
+        _ => None,
+    }
+}
+
+/// By spec constructs a bot from a specification: either the name of a
+/// built-in bot, or `cmd:` or `proto:` followed by the command line of a
+/// process bot speaking the line or framed protocol.
+pub fn by_spec(spec: &str, seed: Option<u64>) -> Result<Box<dyn Bot>, String> {
+    if let Some(command) = spec.strip_prefix("cmd:") {
+        return Ok(Box::new(ProcessBot::spawn(command)?));
+    }
+    if let Some(command) = spec.strip_prefix("proto:") {
+        return Ok(Box::new(ProtocolBot::spawn(command)?));
+    }
+    by_name(spec, seed).ok_or(format!("unknown bot {:?}", spec))
+}
+
+/// RandomBot plays a uniformly random legal move.
+pub struct RandomBot {
+    rng: StdRng,
+}
+
+impl RandomBot {
+    pub fn new() -> Self {
+        RandomBot {
+            rng: StdRng::from_entropy(),
+        }
+    }
+    pub fn seeded(seed: u64) -> Self {
+        RandomBot {
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+}
+
+impl Default for RandomBot {
+    fn default() -> Self {
+        RandomBot::new()
+    }
+}
+
+impl Bot for RandomBot {
+    fn name(&self) -> String {
+        "random".into()
+    }
+    fn choose(&mut self, view: &PlayerView) -> Result<Option<Move>, String> {
+        Ok(view.moves().choose(&mut self.rng).cloned())
+    }
+}
+
+/// Value of a unit in pawns. The king is priced well above everything else
+/// combined since losing it loses the game.
+pub fn value(unit: Unit) -> i32 {
+    match unit {
+        Unit::Pawn => 1,
+        Unit::Knight => 3,
+        Unit::Bishop => 3,
+        Unit::Rook => 5,
+        Unit::Queen => 9,
+        Unit::King => 100,
+    }
+}
+
+/// Material is the player's total unit value minus their opponent's.
+pub fn material(board: &Board, player: &Player) -> i32 {
+    board
+        .iter()
+        .filter_map(|Position { piece, .. }| piece)
+        .map(|piece| {
+            if piece.player == *player {
+                value(piece.unit)
+            } else {
+                -value(piece.unit)
+            }
+        })
+        .sum()
+}
@@ -0,0 +1,526 @@
+//! Alpha-beta search for games without fog.
+//!
+//! Negamax with iterative deepening, a transposition table keyed by Zobrist
+//! hashes, captures-first move ordering and a capture-only quiescence search.
+//! Positions are scored by material plus piece-square tables. Against fog the
+//! bot simply searches the board it can see, treating hidden squares as empty.
+
+use super::Bot;
+use crate::{Board, Move, Player, PlayerView, Position, Unit};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+// Score of a captured king, less the ply it happens on so quicker wins are
+// preferred.
+const MATE: i32 = 1_000_000;
+const INFINITY: i32 = MATE + 1;
+// Entries kept in the transposition table before it is cleared.
+const TABLE_LIMIT: usize = 1 << 20;
+
+/// Difficulty trades playing strength for thinking time.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Difficulty {
+    Easy,
+    Medium,
+    Hard,
+}
+
+impl Difficulty {
+    // Maximum search depth and time per move.
+    fn budget(&self) -> (u32, Duration) {
+        match self {
+            Difficulty::Easy => (2, Duration::from_millis(250)),
+            Difficulty::Medium => (4, Duration::from_millis(1_000)),
+            Difficulty::Hard => (8, Duration::from_millis(3_000)),
+        }
+    }
+}
+
+/// AlphaBeta searches the full board to a fixed depth.
+pub struct AlphaBeta {
+    depth: u32,
+    time: Duration,
+    keys: [[u64; 12]; 64],
+    side: u64,
+    table: HashMap<u64, Entry>,
+    deadline: Option<Instant>,
+    aborted: bool,
+}
+
+#[derive(Clone, Copy)]
+enum Bound {
+    Exact,
+    Lower,
+    Upper,
+}
+
+#[derive(Clone, Copy)]
+struct Entry {
+    depth: u32,
+    score: i32,
+    bound: Bound,
+    best: Option<Move>,
+}
+
+impl AlphaBeta {
+    pub fn new(difficulty: Difficulty) -> Self {
+        let (depth, time) = difficulty.budget();
+        AlphaBeta::with_budget(depth, time)
+    }
+    /// With budget creates a bot searching at most `depth` plies and `time`
+    /// per move.
+    pub fn with_budget(depth: u32, time: Duration) -> Self {
+        // Zobrist keys only need to be well mixed, not secret, so derive them
+        // from a fixed seed to keep hashes stable between runs.
+        let mut seed = 0x9E37_79B9_7F4A_7C15u64;
+        let mut next = || {
+            seed = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            let mut z = seed;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            z ^ (z >> 31)
+        };
+        let mut keys = [[0; 12]; 64];
+        for square in keys.iter_mut() {
+            for key in square.iter_mut() {
+                *key = next();
+            }
+        }
+        AlphaBeta {
+            depth,
+            time,
+            keys,
+            side: next(),
+            table: HashMap::new(),
+            deadline: None,
+            aborted: false,
+        }
+    }
+    /// Set time changes how long each later search may take.
+    pub fn set_time(&mut self, time: Duration) {
+        self.time = time;
+    }
+    /// Search finds the best move for the player to move and its score in
+    /// centipawns from their perspective.
+    pub fn search(&mut self, board: &Board, turn: Player) -> Option<(Move, i32)> {
+        if self.table.len() > TABLE_LIMIT {
+            self.table.clear();
+        }
+        self.deadline = Some(Instant::now() + self.time);
+        self.aborted = false;
+        let hash = self.hash(board, turn);
+        let mut best = None;
+        for depth in 1..=self.depth {
+            let score = self.negamax(board, hash, turn, depth, 0, -INFINITY, INFINITY);
+            if self.aborted {
+                break;
+            }
+            if let Some(Entry { best: Some(mv), .. }) = self.table.get(&hash) {
+                best = Some((*mv, score));
+            }
+            // A forced win or loss won't change with more depth.
+            if score.abs() >= MATE - 100 {
+                break;
+            }
+        }
+        best.or_else(|| {
+            board
+                .moves_for(&turn)
+                .first()
+                .map(|mv| (*mv, evaluate(board, &turn)))
+        })
+    }
+    #[allow(clippy::too_many_arguments)]
+    fn negamax(
+        &mut self,
+        board: &Board,
+        hash: u64,
+        turn: Player,
+        depth: u32,
+        ply: i32,
+        mut alpha: i32,
+        mut beta: i32,
+    ) -> i32 {
+        if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            self.aborted = true;
+            return 0;
+        }
+        if let Some(winner) = board.winner() {
+            return if winner == turn {
+                MATE - ply
+            } else {
+                ply - MATE
+            };
+        }
+        if depth == 0 {
+            return self.quiesce(board, turn, ply, alpha, beta);
+        }
+        let original = alpha;
+        let mut hint = None;
+        if let Some(entry) = self.table.get(&hash) {
+            hint = entry.best;
+            if entry.depth >= depth {
+                match entry.bound {
+                    Bound::Exact => return entry.score,
+                    Bound::Lower => alpha = alpha.max(entry.score),
+                    Bound::Upper => beta = beta.min(entry.score),
+                }
+                if alpha >= beta {
+                    return entry.score;
+                }
+            }
+        }
+        let moves = ordered(board, &turn, hint);
+        if moves.is_empty() {
+            return 0;
+        }
+        let mut best = (-INFINITY, None);
+        for mv in moves {
+            let (child, child_hash) = self.make(board, hash, mv);
+            let score = -self.negamax(
+                &child,
+                child_hash,
+                turn.opponent(),
+                depth - 1,
+                ply + 1,
+                -beta,
+                -alpha,
+            );
+            if self.aborted {
+                return 0;
+            }
+            if score > best.0 {
+                best = (score, Some(mv));
+            }
+            alpha = alpha.max(score);
+            if alpha >= beta {
+                break;
+            }
+        }
+        let bound = if best.0 <= original {
+            Bound::Upper
+        } else if best.0 >= beta {
+            Bound::Lower
+        } else {
+            Bound::Exact
+        };
+        self.table.insert(
+            hash,
+            Entry {
+                depth,
+                score: best.0,
+                bound,
+                best: best.1,
+            },
+        );
+        best.0
+    }
+    // Quiesce extends the search through captures only, so the evaluation
+    // is never taken in the middle of an exchange.
+    fn quiesce(&mut self, board: &Board, turn: Player, ply: i32, mut alpha: i32, beta: i32) -> i32 {
+        if let Some(winner) = board.winner() {
+            return if winner == turn {
+                MATE - ply
+            } else {
+                ply - MATE
+            };
+        }
+        let stand = evaluate(board, &turn);
+        if stand >= beta {
+            return stand;
+        }
+        alpha = alpha.max(stand);
+        for mv in ordered(board, &turn, None) {
+            if board.get(mv.1).is_none() {
+                // Captures are ordered first, so the rest are quiet.
+                break;
+            }
+            let mut child = board.clone();
+            child.move_piece(mv.0, mv.1);
+            let score = -self.quiesce(&child, turn.opponent(), ply + 1, -beta, -alpha);
+            if score >= beta {
+                return score;
+            }
+            alpha = alpha.max(score);
+        }
+        alpha
+    }
+    // Make plays the move on a copy of the board, updating the hash.
+    fn make(&self, board: &Board, hash: u64, mv: Move) -> (Board, u64) {
+        let (from, to) = mv;
+        let mut hash = hash ^ self.side;
+        if let Some(piece) = board.get(from) {
+            hash ^= self.key(from, piece.unit, &piece.player);
+            hash ^= self.key(to, piece.unit, &piece.player);
+        }
+        if let Some(captured) = board.get(to) {
+            hash ^= self.key(to, captured.unit, &captured.player);
+        }
+        let mut child = board.clone();
+        child.move_piece(from, to);
+        (child, hash)
+    }
+    fn hash(&self, board: &Board, turn: Player) -> u64 {
+        let mut hash = if turn == Player::Black { self.side } else { 0 };
+        for Position { x, y, piece } in board.iter() {
+            if let Some(piece) = piece {
+                hash ^= self.key((x as i32, y as i32), piece.unit, &piece.player);
+            }
+        }
+        hash
+    }
+    fn key(&self, pos: (i32, i32), unit: Unit, player: &Player) -> u64 {
+        let (x, y) = pos;
+        self.keys[(y * 8 + x) as usize][player.index() * 6 + unit.index()]
+    }
+}
+
+impl Default for AlphaBeta {
+    fn default() -> Self {
+        AlphaBeta::new(Difficulty::Medium)
+    }
+}
+
+impl Bot for AlphaBeta {
+    fn name(&self) -> String {
+        "alphabeta".into()
+    }
+    fn choose(&mut self, view: &PlayerView) -> Result<Option<Move>, String> {
+        Ok(self.search(&view.board, view.player).map(|(mv, _)| mv))
+    }
+}
+
+// Ordered lists the player's moves with the hinted move first, then
+// captures by most valuable victim and least valuable attacker, then quiet
+// moves.
+fn ordered(board: &Board, player: &Player, hint: Option<Move>) -> Vec<Move> {
+    let mut moves = board.moves_for(player);
+    moves.sort_by_key(|mv| {
+        if Some(*mv) == hint {
+            return i32::MIN;
+        }
+        match (board.get(mv.0), board.get(mv.1)) {
+            (Some(attacker), Some(victim)) => {
+                -(centipawns(victim.unit) * 10 - centipawns(attacker.unit) / 100)
+            }
+            _ => 0,
+        }
+    });
+    moves
+}
+
+/// Evaluate scores the board in centipawns from the player's perspective,
+/// using material and piece-square tables.
+pub fn evaluate(board: &Board, player: &Player) -> i32 {
+    board
+        .iter()
+        .filter_map(|Position { x, y, piece }| piece.map(|piece| (x, y, piece)))
+        .map(|(x, y, piece)| {
+            // Tables are laid out from the owner's side with their back
+            // rank last.
+            let row = match piece.player {
+                Player::White => 7 - y,
+                Player::Black => y,
+            };
+            let score = centipawns(piece.unit) + table(piece.unit)[row * 8 + x];
+            if piece.player == *player {
+                score
+            } else {
+                -score
+            }
+        })
+        .sum()
+}
+
+/// Centipawns is the material value of a unit.
+pub fn centipawns(unit: Unit) -> i32 {
+    match unit {
+        Unit::Pawn => 100,
+        Unit::Knight => 320,
+        Unit::Bishop => 330,
+        Unit::Rook => 500,
+        Unit::Queen => 900,
+        Unit::King => 20_000,
+    }
+}
+
+// Piece-square tables, adapted from Tomasz Michniewski's "Simplified
+// Evaluation Function".
+fn table(unit: Unit) -> &'static [i32; 64] {
+    match unit {
+        Unit::Pawn => &PAWN,
+        Unit::Knight => &KNIGHT,
+        Unit::Bishop => &BISHOP,
+        Unit::Rook => &ROOK,
+        Unit::Queen => &QUEEN,
+        Unit::King => &KING,
+    }
+}
+
+#[rustfmt::skip]
+const PAWN: [i32; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+    50, 50, 50, 50, 50, 50, 50, 50,
+    10, 10, 20, 30, 30, 20, 10, 10,
+     5,  5, 10, 25, 25, 10,  5,  5,
+     0,  0,  0, 20, 20,  0,  0,  0,
+     5, -5,-10,  0,  0,-10, -5,  5,
+     5, 10, 10,-20,-20, 10, 10,  5,
+     0,  0,  0,  0,  0,  0,  0,  0,
+];
+
+#[rustfmt::skip]
+const KNIGHT: [i32; 64] = [
+    -50,-40,-30,-30,-30,-30,-40,-50,
+    -40,-20,  0,  0,  0,  0,-20,-40,
+    -30,  0, 10, 15, 15, 10,  0,-30,
+    -30,  5, 15, 20, 20, 15,  5,-30,
+    -30,  0, 15, 20, 20, 15,  0,-30,
+    -30,  5, 10, 15, 15, 10,  5,-30,
+    -40,-20,  0,  5,  5,  0,-20,-40,
+    -50,-40,-30,-30,-30,-30,-40,-50,
+];
+
+#[rustfmt::skip]
+const BISHOP: [i32; 64] = [
+    -20,-10,-10,-10,-10,-10,-10,-20,
+    -10,  0,  0,  0,  0,  0,  0,-10,
+    -10,  0,  5, 10, 10,  5,  0,-10,
+    -10,  5,  5, 10, 10,  5,  5,-10,
+    -10,  0, 10, 10, 10, 10,  0,-10,
+    -10, 10, 10, 10, 10, 10, 10,-10,
+    -10,  5,  0,  0,  0,  0,  5,-10,
+    -20,-10,-10,-10,-10,-10,-10,-20,
+];
+
+#[rustfmt::skip]
+const ROOK: [i32; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+     5, 10, 10, 10, 10, 10, 10,  5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+     0,  0,  0,  5,  5,  0,  0,  0,
+];
+
+#[rustfmt::skip]
+const QUEEN: [i32; 64] = [
+    -20,-10,-10, -5, -5,-10,-10,-20,
+    -10,  0,  0,  0,  0,  0,  0,-10,
+    -10,  0,  5,  5,  5,  5,  0,-10,
+     -5,  0,  5,  5,  5,  5,  0, -5,
+      0,  0,  5,  5,  5,  5,  0, -5,
+    -10,  5,  5,  5,  5,  5,  0,-10,
+    -10,  0,  5,  0,  0,  0,  0,-10,
+    -20,-10,-10, -5, -5,-10,-10,-20,
+];
+
+#[rustfmt::skip]
+const KING: [i32; 64] = [
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -20,-30,-30,-40,-40,-30,-30,-20,
+    -10,-20,-20,-20,-20,-20,-20,-10,
+     20, 20,  0,  0,  0,  0, 20, 20,
+     20, 30, 10,  0,  0, 10, 30, 20,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Piece;
+
+    // A board holding just the pieces given.
+    fn board(pieces: &[((i32, i32), Unit, Player)]) -> Board {
+        let mut board = Board::default();
+        for (at, unit, player) in pieces.iter() {
+            let piece = Piece {
+                unit: *unit,
+                player: *player,
+                moved: 1,
+            };
+            board.set(*at, piece);
+        }
+        board
+    }
+
+    // Plain negamax to a fixed depth, with neither pruning nor a table,
+    // finishing in the engine's own quiescence search.
+    fn negamax(engine: &mut AlphaBeta, board: &Board, turn: Player, depth: u32, ply: i32) -> i32 {
+        if let Some(winner) = board.winner() {
+            return if winner == turn {
+                MATE - ply
+            } else {
+                ply - MATE
+            };
+        }
+        if depth == 0 {
+            return engine.quiesce(board, turn, ply, -INFINITY, INFINITY);
+        }
+        let mut best = None;
+        for (from, to) in board.moves_for(&turn) {
+            let mut child = board.clone();
+            child.move_piece(from, to);
+            let score = -negamax(engine, &child, turn.opponent(), depth - 1, ply + 1);
+            best = best.max(Some(score));
+        }
+        best.unwrap_or(0)
+    }
+
+    #[test]
+    fn takes_a_hanging_king() {
+        let board = board(&[
+            ((4, 0), Unit::King, Player::White),
+            ((0, 3), Unit::Rook, Player::White),
+            ((7, 3), Unit::King, Player::Black),
+            ((3, 6), Unit::Queen, Player::Black),
+        ]);
+        let mut engine = AlphaBeta::with_budget(3, Duration::from_secs(60));
+        let (mv, score) = engine.search(&board, Player::White).unwrap();
+        assert_eq!(mv, ((0, 3), (7, 3)));
+        assert!(score >= MATE - 100);
+    }
+
+    #[test]
+    fn takes_a_hanging_queen() {
+        let board = board(&[
+            ((4, 0), Unit::King, Player::White),
+            ((0, 4), Unit::Rook, Player::White),
+            ((7, 7), Unit::King, Player::Black),
+            ((5, 4), Unit::Queen, Player::Black),
+            ((6, 6), Unit::Pawn, Player::Black),
+        ]);
+        let mut engine = AlphaBeta::with_budget(3, Duration::from_secs(60));
+        let (mv, score) = engine.search(&board, Player::White).unwrap();
+        assert_eq!(mv, ((0, 4), (5, 4)));
+        // Up a rook for a pawn once the queen is gone.
+        assert!(score > centipawns(Unit::Rook) / 2);
+    }
+
+    #[test]
+    fn the_table_does_not_change_the_score() {
+        // Deep enough for White to reach a position by two routes.
+        let board = board(&[
+            ((4, 0), Unit::King, Player::White),
+            ((0, 0), Unit::Rook, Player::White),
+            ((1, 1), Unit::Pawn, Player::White),
+            ((5, 2), Unit::Knight, Player::White),
+            ((4, 7), Unit::King, Player::Black),
+            ((7, 7), Unit::Rook, Player::Black),
+            ((2, 6), Unit::Pawn, Player::Black),
+            ((3, 4), Unit::Pawn, Player::Black),
+        ]);
+        let mut engine = AlphaBeta::with_budget(3, Duration::from_secs(60));
+        let (_, score) = engine.search(&board, Player::White).unwrap();
+        assert!(!engine.table.is_empty());
+        let mut plain = AlphaBeta::with_budget(3, Duration::from_secs(60));
+        assert_eq!(score, negamax(&mut plain, &board, Player::White, 3, 0));
+    }
+}
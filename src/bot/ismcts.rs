@@ -0,0 +1,375 @@
+//! Information set Monte Carlo tree search.
+//!
+//! Hidden enemy pieces make a position unknowable, so each iteration first
+//! samples a full board consistent with what the bot has observed (a
+//! "determinization") and then searches it. All iterations share a single
+//! tree whose edges are moves, so statistics for a move are aggregated across
+//! every sampled board in which it was legal (single observer ISMCTS).
+
+use super::{material, Bot};
+use crate::{Belief, Board, Move, Piece, Player, PlayerView, Position, Unit, UNITS};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use std::time::{Duration, Instant};
+
+/// Search budgets and tuning for the ISMCTS bot. Search stops at whichever
+/// budget runs out first; at least one must be set.
+#[derive(Clone, Debug)]
+pub struct IsmctsConfig {
+    /// Maximum number of iterations per move.
+    pub iterations: Option<u32>,
+    /// Maximum thinking time per move.
+    pub time: Option<Duration>,
+    /// Number of random plies played out after leaving the tree.
+    pub rollout_depth: u32,
+    /// UCB exploration constant.
+    pub exploration: f64,
+}
+
+impl Default for IsmctsConfig {
+    fn default() -> Self {
+        IsmctsConfig {
+            iterations: Some(2_000),
+            time: Some(Duration::from_millis(1_000)),
+            rollout_depth: 30,
+            exploration: 0.7,
+        }
+    }
+}
+
+/// Stat aggregates search results for one candidate move.
+#[derive(Clone, Debug)]
+pub struct Stat {
+    pub mv: Move,
+    pub visits: u32,
+    /// Mean reward in `[0, 1]` from the searching player's perspective.
+    pub value: f64,
+}
+
+/// Ismcts searches a fog-filtered view with information set Monte Carlo tree
+/// search.
+pub struct Ismcts {
+    config: IsmctsConfig,
+    rng: StdRng,
+    // Where the boards sampled by the last search put hidden enemy units.
+    belief: Option<Belief>,
+}
+
+// Node in the search tree. The move on the edge into the node was made by
+// `player`.
+struct Node {
+    mv: Option<Move>,
+    player: Player,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    visits: u32,
+    avails: u32,
+    reward: f64,
+}
+
+impl Ismcts {
+    pub fn new(config: IsmctsConfig) -> Self {
+        Ismcts {
+            config,
+            rng: StdRng::from_entropy(),
+            belief: None,
+        }
+    }
+    /// Seeded creates a bot whose sampling is reproducible. Searches bounded
+    /// by time rather than iterations may still differ between runs.
+    pub fn seeded(config: IsmctsConfig, seed: u64) -> Self {
+        Ismcts {
+            config,
+            rng: StdRng::seed_from_u64(seed),
+            belief: None,
+        }
+    }
+    /// Search runs until the budget is spent and returns statistics for each
+    /// root move, most visited first.
+    pub fn search(&mut self, view: &PlayerView) -> Vec<Stat> {
+        let mut tree = vec![Node {
+            mv: None,
+            player: view.player.opponent(),
+            parent: None,
+            children: vec![],
+            visits: 0,
+            avails: 0,
+            reward: 0.0,
+        }];
+        let started = Instant::now();
+        let mut iterations = 0;
+        let mut belief = Belief::new(view);
+        loop {
+            if self.config.iterations.is_some_and(|max| iterations >= max)
+                || self.config.time.is_some_and(|max| started.elapsed() >= max)
+                || (self.config.iterations.is_none() && self.config.time.is_none())
+            {
+                break;
+            }
+            let board = determinize(view, &mut self.rng);
+            belief.add(view, &board);
+            self.iterate(&mut tree, board, view.player);
+            iterations += 1;
+        }
+        self.belief = Some(belief);
+        let mut stats: Vec<Stat> = tree[0]
+            .children
+            .iter()
+            .map(|&child| {
+                let node = &tree[child];
+                Stat {
+                    mv: node.mv.expect("child nodes carry a move"),
+                    visits: node.visits,
+                    value: node.reward / node.visits.max(1) as f64,
+                }
+            })
+            .collect();
+        stats.sort_by_key(|stat| std::cmp::Reverse(stat.visits));
+        stats
+    }
+    // Run one select, expand, rollout and backpropagate pass over a single
+    // determinization.
+    fn iterate(&mut self, tree: &mut Vec<Node>, mut board: Board, root: Player) {
+        let mut node = 0;
+        let mut turn = root;
+        // Select and expand.
+        while board.winner().is_none() {
+            let legal = board.moves_for(&turn);
+            if legal.is_empty() {
+                break;
+            }
+            let untried: Vec<Move> = legal
+                .iter()
+                .cloned()
+                .filter(|mv| !tree[node].children.iter().any(|&c| tree[c].mv == Some(*mv)))
+                .collect();
+            if let Some(mv) = untried.choose(&mut self.rng).cloned() {
+                tree.push(Node {
+                    mv: Some(mv),
+                    player: turn,
+                    parent: Some(node),
+                    children: vec![],
+                    visits: 0,
+                    avails: 1,
+                    reward: 0.0,
+                });
+                let child = tree.len() - 1;
+                tree[node].children.push(child);
+                board.move_piece(mv.0, mv.1);
+                node = child;
+                turn = turn.opponent();
+                break;
+            }
+            let available: Vec<usize> = tree[node]
+                .children
+                .iter()
+                .cloned()
+                .filter(|&c| legal.contains(&tree[c].mv.expect("child nodes carry a move")))
+                .collect();
+            for &child in available.iter() {
+                tree[child].avails += 1;
+            }
+            let exploration = self.config.exploration;
+            let ucb = |n: &Node| {
+                n.reward / n.visits as f64
+                    + exploration * ((n.avails as f64).ln() / n.visits as f64).sqrt()
+            };
+            let next = *available
+                .iter()
+                .max_by(|&&a, &&b| {
+                    ucb(&tree[a])
+                        .partial_cmp(&ucb(&tree[b]))
+                        .unwrap_or(std::cmp::Ordering::Equal)
+                })
+                .expect("every legal move has been tried");
+            let (from, to) = tree[next].mv.expect("child nodes carry a move");
+            board.move_piece(from, to);
+            node = next;
+            turn = turn.opponent();
+        }
+        // Rollout.
+        for _ in 0..self.config.rollout_depth {
+            if board.winner().is_some() {
+                break;
+            }
+            match board.moves_for(&turn).choose(&mut self.rng) {
+                Some((from, to)) => board.move_piece(*from, *to),
+                None => break,
+            }
+            turn = turn.opponent();
+        }
+        // Backpropagate.
+        let result = score(&board, &root);
+        let mut cursor = Some(node);
+        while let Some(ii) = cursor {
+            let n = &mut tree[ii];
+            n.visits += 1;
+            n.reward += if n.player == root {
+                result
+            } else {
+                1.0 - result
+            };
+            cursor = n.parent;
+        }
+    }
+}
+
+impl Bot for Ismcts {
+    fn name(&self) -> String {
+        "ismcts".into()
+    }
+    fn choose(&mut self, view: &PlayerView) -> Result<Option<Move>, String> {
+        // Prefer the most visited move, breaking ties on value.
+        let best = self.search(view).into_iter().max_by(|a, b| {
+            a.visits.cmp(&b.visits).then(
+                a.value
+                    .partial_cmp(&b.value)
+                    .unwrap_or(std::cmp::Ordering::Equal),
+            )
+        });
+        Ok(match best {
+            Some(stat) => Some(stat.mv),
+            None => view.moves().choose(&mut self.rng).cloned(),
+        })
+    }
+    fn belief(&self) -> Option<Belief> {
+        self.belief.clone()
+    }
+}
+
+// Score a board in `[0, 1]` for the player: 1 for a win, 0 for a loss,
+// otherwise scaled by the material balance.
+fn score(board: &Board, player: &Player) -> f64 {
+    match board.winner() {
+        Some(winner) if winner == *player => 1.0,
+        Some(_) => 0.0,
+        None => (0.5 + material(board, player) as f64 / 78.0).clamp(0.0, 1.0),
+    }
+}
+
+/// Determinize samples a full board consistent with the player's view:
+/// visible pieces stay where they are and the remaining enemy units are
+/// scattered over squares their information set allows.
+pub fn determinize(view: &PlayerView, rng: &mut StdRng) -> Board {
+    let mut board = view.board.clone();
+    let enemy = view.player.opponent();
+    let mut visible = [0u32; 6];
+    for Position { piece, .. } in view.board.iter() {
+        if let Some(piece) = piece {
+            if piece.player == enemy {
+                visible[piece.unit.index()] += 1;
+            }
+        }
+    }
+    // Place the most constrained units first so they are least likely to
+    // find their squares already taken.
+    let mut units: Vec<Unit> = UNITS.to_vec();
+    units.sort_by_key(|unit| view.infoset.possible_mask(*unit).count_ones());
+    for unit in units {
+        let hidden = view
+            .infoset
+            .remaining(unit)
+            .saturating_sub(visible[unit.index()]) as usize;
+        let mut candidates: Vec<(i32, i32)> = view
+            .infoset
+            .possible(unit)
+            .into_iter()
+            .filter(|(x, y)| !view.visible[*y as usize][*x as usize])
+            .filter(|pos| board.get(*pos).is_none())
+            .collect();
+        candidates.shuffle(rng);
+        for pos in candidates.into_iter().take(hidden) {
+            board.set(
+                pos,
+                Piece {
+                    unit,
+                    player: enemy,
+                    moved: if unit == Unit::Pawn && pos.1 == enemy.pawn_rank() {
+                        0
+                    } else {
+                        1
+                    },
+                },
+            );
+        }
+    }
+    board
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Game;
+
+    // Count each unit of the player's on the board.
+    fn counts(board: &Board, player: Player) -> [u32; 6] {
+        let mut counts = [0; 6];
+        for Position { piece, .. } in board.iter() {
+            if let Some(piece) = piece.filter(|piece| piece.player == player) {
+                counts[piece.unit.index()] += 1;
+            }
+        }
+        counts
+    }
+
+    #[test]
+    fn determinizations_fill_only_the_fog() {
+        let mut game = Game::headless(Board::new(), false, 3);
+        for mv in [((4, 1), (4, 3)), ((3, 6), (3, 4)), ((4, 3), (3, 4))].iter() {
+            assert!(game.make_move(*mv));
+        }
+        let view = game.view_for(&Player::Black);
+        let mut rng = StdRng::seed_from_u64(9);
+        for _ in 0..200 {
+            let board = determinize(&view, &mut rng);
+            for y in 0..8 {
+                for x in 0..8 {
+                    if view.visible[y as usize][x as usize] {
+                        assert_eq!(
+                            board.get((x, y)).map(|p| (p.unit, p.player)),
+                            view.board.get((x, y)).map(|p| (p.unit, p.player)),
+                            "changed a visible square"
+                        );
+                    }
+                }
+            }
+            assert_eq!(
+                counts(&board, Player::Black),
+                counts(&view.board, Player::Black)
+            );
+            assert_eq!(
+                counts(&board, Player::White),
+                counts(&game.state.board, Player::White)
+            );
+        }
+    }
+
+    #[test]
+    fn takes_a_visible_king() {
+        let mut board = Board::default();
+        let mut place = |at, unit, player| {
+            let piece = Piece {
+                unit,
+                player,
+                moved: 1,
+            };
+            board.set(at, piece);
+        };
+        place((4, 0), Unit::King, Player::White);
+        place((3, 0), Unit::Queen, Player::White);
+        place((3, 5), Unit::King, Player::Black);
+        place((0, 6), Unit::Pawn, Player::Black);
+        place((7, 6), Unit::Pawn, Player::Black);
+        let game = Game::headless(board, false, 1);
+        let view = game.view_for(&Player::White);
+        assert!(view.visible[5][3]);
+        let config = IsmctsConfig {
+            iterations: Some(500),
+            time: None,
+            ..IsmctsConfig::default()
+        };
+        let mv = Ismcts::seeded(config, 7).choose(&view).unwrap();
+        assert_eq!(mv, Some(((3, 0), (3, 5))));
+    }
+}
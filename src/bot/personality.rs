@@ -0,0 +1,332 @@
+//! Personalities are bots with a tunable playing style, loaded from a TOML
+//! file so new opponents can be created without recompiling:
+//!
+//! ```toml
+//! [rita]
+//! aggression = 0.9
+//! scouting = 2.0
+//! risk = 0.9
+//! ```
+//!
+//! Every candidate move is tried on a few boards sampled from the bot's view
+//! and scored by a shallow search, then adjusted for the bot's style.
+
+use super::{centipawns, determinize, evaluate, AlphaBeta, Bot};
+use crate::{Belief, Board, Move, Player, PlayerView};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::time::{Duration, Instant};
+
+// Scores are capped at this many centipawns either way, so a single sample
+// where the king falls doesn't swamp the rest.
+const CAP: i32 = 1_000;
+
+// Thinking time for a whole decision, shared between every search it runs.
+const BUDGET: Duration = Duration::from_millis(500);
+
+/// Personality tunes how a bot plays.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Personality {
+    /// How eagerly the bot captures and pushes towards the enemy, from 0
+    /// upwards. At 1 a capture is worth half again its material value.
+    pub aggression: f64,
+    /// Centipawns the bot will trade for each extra square it can see.
+    pub scouting: f64,
+    /// Tolerance for moves whose outcome depends on what hides in the fog,
+    /// from 0 (judge every move by the worst board it could face) to 1
+    /// (judge by the average board).
+    pub risk: f64,
+    /// Plies searched after each candidate move.
+    pub depth: u32,
+    /// Boards sampled from the fog for each decision.
+    pub samples: u32,
+    /// Random centipawns added to each move's score, for variety.
+    pub noise: f64,
+}
+
+impl Default for Personality {
+    fn default() -> Self {
+        Personality {
+            aggression: 0.3,
+            scouting: 5.0,
+            risk: 0.5,
+            depth: 2,
+            samples: 4,
+            noise: 0.0,
+        }
+    }
+}
+
+/// Load personalities reads named opponents from a TOML file with one
+/// table each.
+pub fn load_personalities(path: &str) -> Result<BTreeMap<String, Personality>, String> {
+    let text = fs::read_to_string(path).map_err(|err| format!("reading {}: {}", path, err))?;
+    toml::from_str(&text).map_err(|err| format!("parsing {}: {}", path, err))
+}
+
+/// Styled is a bot playing with a personality.
+pub struct Styled {
+    name: String,
+    personality: Personality,
+    engine: AlphaBeta,
+    rng: StdRng,
+    // Where the boards sampled for the last decision put hidden enemy units.
+    belief: Option<Belief>,
+}
+
+impl Styled {
+    pub fn new(name: &str, personality: Personality, seed: Option<u64>) -> Self {
+        Styled {
+            name: name.into(),
+            engine: AlphaBeta::with_budget(personality.depth.max(1), BUDGET),
+            personality,
+            rng: match seed {
+                Some(seed) => StdRng::seed_from_u64(seed),
+                None => StdRng::from_entropy(),
+            },
+            belief: None,
+        }
+    }
+    // Style scores the parts of a move the search doesn't: captures and
+    // advances for aggression, and newly visible squares for scouting.
+    fn style(&self, view: &PlayerView, (from, to): Move) -> f64 {
+        let p = &self.personality;
+        let mut score = 0.0;
+        if let Some(victim) = view.board.get(to) {
+            score += p.aggression * f64::from(centipawns(victim.unit)) / 2.0;
+        }
+        let forward = match view.player {
+            Player::White => to.1 - from.1,
+            Player::Black => from.1 - to.1,
+        };
+        score += p.aggression * 20.0 * f64::from(forward);
+        let mut after = view.board.clone();
+        after.move_piece(from, to);
+        let count = |board: &Board| {
+            board
+                .visibility(&view.player)
+                .iter()
+                .flatten()
+                .filter(|seen| **seen)
+                .count() as f64
+        };
+        score + p.scouting * (count(&after) - count(&view.board))
+    }
+}
+
+impl Bot for Styled {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+    fn choose(&mut self, view: &PlayerView) -> Result<Option<Move>, String> {
+        let moves = view.moves();
+        let samples: Vec<Board> = if view.visible.iter().flatten().all(|seen| *seen) {
+            vec![view.board.clone()]
+        } else {
+            (0..self.personality.samples.max(1))
+                .map(|_| determinize(view, &mut self.rng))
+                .collect()
+        };
+        let mut belief = Belief::new(view);
+        for sample in samples.iter() {
+            belief.add(view, sample);
+        }
+        self.belief = Some(belief);
+        // Each search gets an equal share of whatever budget is left, so
+        // the decision as a whole stays within it however many moves and
+        // samples there are.
+        let deadline = Instant::now() + BUDGET;
+        let mut searches = moves.len() * samples.len();
+        let mut best: Option<(f64, Move)> = None;
+        for mv in moves {
+            let mut outcomes = Vec::with_capacity(samples.len());
+            for sample in samples.iter() {
+                let mut after = sample.clone();
+                after.move_piece(mv.0, mv.1);
+                let left = deadline.saturating_duration_since(Instant::now());
+                self.engine.set_time(left / searches as u32);
+                searches -= 1;
+                let score = match after.winner() {
+                    Some(winner) if winner == view.player => CAP,
+                    Some(_) => -CAP,
+                    None => match self.engine.search(&after, view.player.opponent()) {
+                        Some((_, score)) => -score,
+                        None => evaluate(&after, &view.player),
+                    },
+                };
+                outcomes.push(f64::from(score.clamp(-CAP, CAP)));
+            }
+            let mut score = blend(&outcomes, self.personality.risk) + self.style(view, mv);
+            if self.personality.noise > 0.0 {
+                score += self
+                    .rng
+                    .gen_range(-self.personality.noise, self.personality.noise);
+            }
+            if best.is_none_or(|(top, _)| score > top) {
+                best = Some((score, mv));
+            }
+        }
+        Ok(best.map(|(_, mv)| mv))
+    }
+    fn belief(&self) -> Option<Belief> {
+        self.belief.clone()
+    }
+}
+
+// Blend a move's outcomes over the sampled boards by the bot's tolerance for
+// risk, from the worst of them at 0 to their mean at 1.
+fn blend(outcomes: &[f64], risk: f64) -> f64 {
+    let risk = risk.clamp(0.0, 1.0);
+    let mean = outcomes.iter().sum::<f64>() / outcomes.len() as f64;
+    let worst = outcomes.iter().cloned().fold(f64::INFINITY, f64::min);
+    risk * mean + (1.0 - risk) * worst
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Game, Piece, Unit};
+    use std::path::PathBuf;
+
+    // Load personalities from TOML written to a scratch file.
+    fn load(name: &str, text: &str) -> Result<BTreeMap<String, Personality>, String> {
+        let path: PathBuf = std::env::temp_dir().join(format!(
+            "fog-of-chess-personality-{}-{}.toml",
+            std::process::id(),
+            name
+        ));
+        fs::write(&path, text).unwrap();
+        let loaded = load_personalities(path.to_str().unwrap());
+        let _ = fs::remove_file(&path);
+        loaded
+    }
+
+    fn place(board: &mut Board, at: (i32, i32), unit: Unit, player: Player) {
+        let piece = Piece {
+            unit,
+            player,
+            moved: 1,
+        };
+        board.set(at, piece);
+    }
+
+    #[test]
+    fn missing_settings_take_their_defaults() {
+        let loaded = load("defaults", "[bold]\naggression = 2.0\n\n[plain]\n").unwrap();
+        assert_eq!(
+            loaded["bold"],
+            Personality {
+                aggression: 2.0,
+                ..Personality::default()
+            }
+        );
+        assert_eq!(loaded["plain"], Personality::default());
+    }
+
+    #[test]
+    fn unknown_settings_are_refused() {
+        let err = load("unknown", "[bold]\nagression = 2.0\n").unwrap_err();
+        assert!(err.contains("agression"), "{}", err);
+        assert!(load_personalities("/no/such/opponents.toml").is_err());
+    }
+
+    #[test]
+    fn the_shipped_opponents_load() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/opponents.toml");
+        let loaded = load_personalities(path).unwrap();
+        let names: Vec<&str> = loaded.keys().map(String::as_str).collect();
+        assert_eq!(names, ["owl", "rita", "rookie", "turtle"]);
+        assert_eq!(loaded["turtle"].samples, 8);
+        assert_eq!(loaded["rookie"].depth, 1);
+    }
+
+    #[test]
+    fn aggression_takes_defended_pawns() {
+        // The rook can take a pawn its neighbour defends, or advance short
+        // of it.
+        let mut board = Board::default();
+        place(&mut board, (4, 0), Unit::King, Player::White);
+        place(&mut board, (0, 0), Unit::Rook, Player::White);
+        place(&mut board, (7, 7), Unit::King, Player::Black);
+        place(&mut board, (0, 5), Unit::Pawn, Player::Black);
+        place(&mut board, (1, 6), Unit::Pawn, Player::Black);
+        let game = Game::headless(board, true, 1);
+        let view = game.view_for(&Player::White);
+        let capture = ((0, 0), (0, 5));
+        let style = |aggression| Personality {
+            aggression,
+            depth: 1,
+            ..Personality::default()
+        };
+        let mut calm = Styled::new("calm", style(0.0), Some(1));
+        assert_ne!(calm.choose(&view).unwrap(), Some(capture));
+        let mut bold = Styled::new("bold", style(10.0), Some(1));
+        assert_eq!(bold.choose(&view).unwrap(), Some(capture));
+    }
+
+    #[test]
+    fn scouting_looks_furthest() {
+        let game = Game::headless(Board::new(), false, 1);
+        let view = game.view_for(&Player::White);
+        let seen = |mv: Move| {
+            let mut after = view.board.clone();
+            after.move_piece(mv.0, mv.1);
+            after
+                .visibility(&Player::White)
+                .iter()
+                .flatten()
+                .filter(|seen| **seen)
+                .count()
+        };
+        let furthest = view.moves().into_iter().map(seen).max().unwrap();
+        let style = |scouting| Personality {
+            aggression: 0.0,
+            scouting,
+            depth: 1,
+            samples: 1,
+            ..Personality::default()
+        };
+        let mut blind = Styled::new("blind", style(0.0), Some(1));
+        let mv = blind.choose(&view).unwrap().unwrap();
+        assert!(seen(mv) < furthest);
+        let mut scout = Styled::new("scout", style(1e6), Some(1));
+        let mv = scout.choose(&view).unwrap().unwrap();
+        assert_eq!(seen(mv), furthest);
+    }
+
+    #[test]
+    fn decisions_share_one_budget() {
+        let game = Game::headless(Board::new(), false, 1);
+        let view = game.view_for(&Player::White);
+        let deep = Personality {
+            depth: 8,
+            samples: 8,
+            ..Personality::default()
+        };
+        let started = Instant::now();
+        assert!(Styled::new("deep", deep, Some(1))
+            .choose(&view)
+            .unwrap()
+            .is_some());
+        assert!(
+            started.elapsed() < BUDGET * 2,
+            "took {:?}",
+            started.elapsed()
+        );
+    }
+
+    #[test]
+    fn risk_trades_the_worst_case_for_the_average() {
+        let safe = [300.0, 300.0];
+        let gamble = [900.0, -200.0];
+        assert!(blend(&safe, 0.0) > blend(&gamble, 0.0));
+        assert!(blend(&safe, 1.0) < blend(&gamble, 1.0));
+        assert_eq!(blend(&gamble, 0.5), 75.0);
+        // Settings out of range are held to it.
+        assert_eq!(blend(&gamble, 3.0), blend(&gamble, 1.0));
+    }
+}
@@ -0,0 +1,238 @@
+//! Process bots are external programs that speak a line based protocol over
+//! their standard input and output, one exchange per move.
+//!
+//! The engine writes a single line describing the bot's view:
+//!
+//! ```text
+//! position <white|black> <ply> <board> <move>...
+//! ```
+//!
+//! `<board>` lists the ranks from 8 down to 1 separated by `/`, one
+//! character per square: FEN piece letters (upper case for White), `.` for a
+//! visible empty square and `?` for a square hidden in fog. Each `<move>` is
+//! a legal move in coordinate notation, such as `e2e4`.
+//!
+//! The bot replies with a single line holding its chosen move in the same
+//! notation, or `none` to play nothing. A bot that exits, replies with
+//! anything else or takes longer than `DEADLINE` to reply has failed.
+//!
+//! Bots started with `proto:` instead speak the framed protocol described in
+//! `crate::protocol`, receiving the full view with each request.
+
+use super::Bot;
+use crate::protocol::{self, read_frame, write_frame, Message};
+use crate::{notation, parse_notation, Move, Player, PlayerView, Unit};
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+/// Deadline is how long a process bot has to answer each request.
+pub const DEADLINE: Duration = Duration::from_secs(30);
+
+/// ProcessBot runs a bot as a child process.
+pub struct ProcessBot {
+    name: String,
+    child: Child,
+    stdin: ChildStdin,
+    replies: Receiver<io::Result<Option<String>>>,
+}
+
+impl ProcessBot {
+    /// Spawn the command line, split on whitespace, as a bot.
+    pub fn spawn(command: &str) -> Result<Self, String> {
+        let (child, stdin, stdout) = spawn_child(command)?;
+        let mut stdout = BufReader::new(stdout);
+        Ok(ProcessBot {
+            name: command.into(),
+            child,
+            stdin,
+            replies: listen(move || {
+                let mut line = String::new();
+                Ok(match stdout.read_line(&mut line)? {
+                    0 => None,
+                    _ => Some(line.trim().into()),
+                })
+            }),
+        })
+    }
+    fn exchange(&mut self, request: &str) -> Result<String, String> {
+        writeln!(self.stdin, "{}", request)
+            .and_then(|_| self.stdin.flush())
+            .map_err(|err| err.to_string())?;
+        receive(&self.replies)
+    }
+}
+
+// Listen reads replies on a thread of their own, so that waiting for one can
+// give up at the deadline. The thread ends with the process's output.
+fn listen<T: Send + 'static>(
+    mut next: impl FnMut() -> io::Result<Option<T>> + Send + 'static,
+) -> Receiver<io::Result<Option<T>>> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || loop {
+        let reply = next();
+        let done = !matches!(reply, Ok(Some(_)));
+        if tx.send(reply).is_err() || done {
+            break;
+        }
+    });
+    rx
+}
+
+// Receive waits up to the deadline for the next reply.
+fn receive<T>(replies: &Receiver<io::Result<Option<T>>>) -> Result<T, String> {
+    match replies.recv_timeout(DEADLINE) {
+        Ok(Ok(Some(reply))) => Ok(reply),
+        Ok(Err(err)) => Err(err.to_string()),
+        Ok(Ok(None)) | Err(RecvTimeoutError::Disconnected) => Err("bot exited".into()),
+        Err(RecvTimeoutError::Timeout) => Err(format!("no reply within {}s", DEADLINE.as_secs())),
+    }
+}
+
+// Start a command line with piped standard input and output.
+fn spawn_child(command: &str) -> Result<(Child, ChildStdin, ChildStdout), String> {
+    let mut words = command.split_whitespace();
+    let program = words.next().ok_or("empty bot command")?;
+    let mut child = Command::new(program)
+        .args(words)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|err| format!("spawning {:?}: {}", command, err))?;
+    let stdin = child.stdin.take().ok_or("bot stdin unavailable")?;
+    let stdout = child.stdout.take().ok_or("bot stdout unavailable")?;
+    Ok((child, stdin, stdout))
+}
+
+impl Drop for ProcessBot {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl Bot for ProcessBot {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+    fn choose(&mut self, view: &PlayerView) -> Result<Option<Move>, String> {
+        match self.exchange(&encode(view))?.as_str() {
+            "none" => Ok(None),
+            reply => parse_move(reply)
+                .map(Some)
+                .ok_or_else(|| format!("unreadable move {:?}", reply)),
+        }
+    }
+}
+
+/// ProtocolBot runs a bot as a child process speaking the framed protocol.
+pub struct ProtocolBot {
+    name: String,
+    child: Child,
+    stdin: ChildStdin,
+    replies: Receiver<io::Result<Option<Message>>>,
+}
+
+impl ProtocolBot {
+    /// Spawn the command line, split on whitespace, as a bot and negotiate a
+    /// protocol version with it.
+    pub fn spawn(command: &str) -> Result<Self, String> {
+        let (child, stdin, stdout) = spawn_child(command)?;
+        let mut stdout = BufReader::new(stdout);
+        let mut bot = ProtocolBot {
+            name: command.into(),
+            child,
+            stdin,
+            replies: listen(move || read_frame(&mut stdout)),
+        };
+        match bot.exchange(&Message::hello(crate::net::AGENT)) {
+            Ok(Message::Hello {
+                min_version,
+                max_version,
+                ..
+            }) if min_version == max_version
+                && protocol::negotiate(min_version, max_version).is_some() => {}
+            Ok(_) => return Err(format!("bot {:?} did not agree a version", command)),
+            Err(err) => return Err(format!("greeting bot {:?}: {}", command, err)),
+        }
+        Ok(bot)
+    }
+    fn exchange(&mut self, request: &Message) -> Result<Message, String> {
+        write_frame(&mut self.stdin, request).map_err(|err| err.to_string())?;
+        receive(&self.replies)
+    }
+}
+
+impl Drop for ProtocolBot {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl Bot for ProtocolBot {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+    fn choose(&mut self, view: &PlayerView) -> Result<Option<Move>, String> {
+        match self.exchange(&Message::Update(Box::new(view.clone())))? {
+            Message::Move { moves } => Ok(moves.first().copied()),
+            _ => Err("expected a move".into()),
+        }
+    }
+}
+
+/// Encode renders a view as a `position` line.
+pub fn encode(view: &PlayerView) -> String {
+    let mut ranks = vec![];
+    for y in (0..8).rev() {
+        let mut rank = String::new();
+        for x in 0..8 {
+            rank.push(match view.board.get((x, y)) {
+                Some(piece) => {
+                    let letter = match piece.unit {
+                        Unit::Pawn => 'p',
+                        Unit::Knight => 'n',
+                        Unit::Bishop => 'b',
+                        Unit::Rook => 'r',
+                        Unit::Queen => 'q',
+                        Unit::King => 'k',
+                    };
+                    if piece.player == Player::White {
+                        letter.to_ascii_uppercase()
+                    } else {
+                        letter
+                    }
+                }
+                None if view.visible[y as usize][x as usize] => '.',
+                None => '?',
+            });
+        }
+        ranks.push(rank);
+    }
+    let mut line = format!(
+        "position {} {} {}",
+        match view.player {
+            Player::White => "white",
+            Player::Black => "black",
+        },
+        view.ply,
+        ranks.join("/")
+    );
+    for (from, to) in view.moves() {
+        line.push(' ');
+        line.push_str(&notation(from));
+        line.push_str(&notation(to));
+    }
+    line
+}
+
+/// Parse move reads a move in coordinate notation, such as `e2e4`.
+pub fn parse_move(text: &str) -> Option<Move> {
+    if text.len() != 4 || !text.is_ascii() {
+        return None;
+    }
+    Some((parse_notation(&text[..2])?, parse_notation(&text[2..])?))
+}
@@ -0,0 +1,180 @@
+//! Chat keeps the messages exchanged during a game, and the one being typed,
+//! for the chat panel. In network games lines come from the server; in
+//! hot-seat games they are entered locally in the name of the player to move.
+
+use crate::protocol::MAX_CHAT;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Line is one chat message.
+pub struct Line {
+    /// How long after the chat began the message arrived.
+    pub at: Duration,
+    /// Who sent it, or empty for notices from the server.
+    pub from: String,
+    pub text: String,
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let seconds = self.at.as_secs();
+        write!(f, "[{}:{:02}] ", seconds / 60, seconds % 60)?;
+        if self.from.is_empty() {
+            write!(f, "* {}", self.text)
+        } else {
+            write!(f, "{}: {}", self.from, self.text)
+        }
+    }
+}
+
+/// Chat is a scrollback of messages and the input line.
+pub struct Chat {
+    pub lines: Vec<Line>,
+    /// The message being typed, while the input line is open.
+    pub input: Option<String>,
+    /// How many lines the view is scrolled back from the newest.
+    pub scroll: usize,
+    // When the chat began, for timestamps.
+    start: Instant,
+}
+
+impl Default for Chat {
+    fn default() -> Self {
+        Chat {
+            lines: vec![],
+            input: None,
+            scroll: 0,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Chat {
+    /// Push adds a message to the scrollback, keeping the view where it is
+    /// if scrolled back.
+    pub fn push(&mut self, from: &str, text: &str) {
+        self.lines.push(Line {
+            at: self.start.elapsed(),
+            from: from.into(),
+            text: text.into(),
+        });
+        if self.scroll > 0 {
+            self.scroll += 1;
+        }
+    }
+    /// Typing is whether the input line is open.
+    pub fn typing(&self) -> bool {
+        self.input.is_some()
+    }
+    /// Open the input line.
+    pub fn open(&mut self) {
+        self.input.get_or_insert_with(String::new);
+    }
+    /// Close the input line, discarding what was typed.
+    pub fn cancel(&mut self) {
+        self.input = None;
+    }
+    /// Type adds a character to the input line, ignoring control characters
+    /// and anything past `MAX_CHAT`.
+    pub fn type_char(&mut self, ch: char) {
+        if let Some(input) = &mut self.input {
+            if !ch.is_control() && input.chars().count() < MAX_CHAT {
+                input.push(ch);
+            }
+        }
+    }
+    /// Delete the last character typed.
+    pub fn backspace(&mut self) {
+        if let Some(input) = &mut self.input {
+            input.pop();
+        }
+    }
+    /// Take closes the input line, returning what was typed unless blank.
+    pub fn take(&mut self) -> Option<String> {
+        let text = self.input.take()?;
+        let text = text.trim();
+        if text.is_empty() {
+            None
+        } else {
+            Some(text.into())
+        }
+    }
+    /// Scroll back moves the view back through older lines, or forward with a
+    /// negative count.
+    pub fn scroll_back(&mut self, lines: isize) {
+        let oldest = self.lines.len().saturating_sub(1) as isize;
+        self.scroll = (self.scroll as isize + lines).clamp(0, oldest) as usize;
+    }
+    /// Shown is up to `count` lines ending at the scroll position.
+    pub fn shown(&self, count: usize) -> &[Line] {
+        let end = self.lines.len() - self.scroll.min(self.lines.len());
+        &self.lines[end.saturating_sub(count)..end]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(lines: &[Line]) -> Vec<&str> {
+        lines.iter().map(|line| line.text.as_str()).collect()
+    }
+
+    #[test]
+    fn scrolling_stays_within_the_scrollback() {
+        let mut chat = Chat::default();
+        assert!(chat.shown(3).is_empty());
+        chat.scroll_back(4);
+        assert_eq!(chat.scroll, 0);
+        for text in ["one", "two", "three", "four", "five"].iter() {
+            chat.push("anna", text);
+        }
+        assert_eq!(texts(chat.shown(2)), ["four", "five"]);
+        chat.scroll_back(2);
+        assert_eq!(texts(chat.shown(2)), ["two", "three"]);
+        // New lines leave a scrolled back view where it is.
+        chat.push("ben", "six");
+        assert_eq!(texts(chat.shown(2)), ["two", "three"]);
+        chat.scroll_back(100);
+        assert_eq!(chat.scroll, 5);
+        assert_eq!(texts(chat.shown(2)), ["one"]);
+        chat.scroll_back(-100);
+        assert_eq!(chat.scroll, 0);
+        assert_eq!(texts(chat.shown(100)).len(), 6);
+    }
+
+    #[test]
+    fn input_is_capped() {
+        let mut chat = Chat::default();
+        chat.type_char('x');
+        assert!(!chat.typing());
+        chat.open();
+        for _ in 0..MAX_CHAT + 5 {
+            chat.type_char('é');
+        }
+        chat.type_char('\n');
+        assert_eq!(chat.input.as_ref().unwrap().chars().count(), MAX_CHAT);
+        chat.backspace();
+        let text = chat.take().unwrap();
+        assert_eq!(text.chars().count(), MAX_CHAT - 1);
+        assert!(!chat.typing());
+        chat.open();
+        chat.type_char(' ');
+        assert_eq!(chat.take(), None);
+    }
+
+    #[test]
+    fn lines_show_when_and_who() {
+        let line = Line {
+            at: Duration::from_secs(75),
+            from: "anna".into(),
+            text: "hi".into(),
+        };
+        assert_eq!(line.to_string(), "[1:15] anna: hi");
+        let notice = Line {
+            from: String::new(),
+            ..line
+        };
+        assert_eq!(notice.to_string(), "[1:15] * hi");
+    }
+}
@@ -0,0 +1,190 @@
+//! Information sets track, for a single player, where each enemy unit type
+//! could be given everything that player has observed.
+
+use crate::{in_bounds, Board, Piece, Player, Position, Unit, UNITS};
+use serde::{Deserialize, Serialize};
+
+/// InfoSet holds the squares each enemy unit type could occupy, consistent
+/// with the squares a player has seen, the number of moves the opponent has
+/// made and the captures the player knows about.
+///
+/// Possible squares are tracked as a bitboard per unit type, where bit
+/// `y * 8 + x` is set if some enemy unit of that type could be on `(x, y)`.
+/// The set is conservative: hidden pieces are assumed never to block one
+/// another, so it may include squares that are in fact unreachable, but it
+/// never excludes a square an enemy unit could be on.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct InfoSet {
+    player: Option<Player>,
+    possible: [u64; 6],
+    remaining: [u32; 6],
+    moves: u32,
+}
+
+impl InfoSet {
+    /// Create an information set for the player, assuming the starting board
+    /// is public knowledge.
+    pub fn new(player: Player, board: &Board) -> Self {
+        let mut infoset = InfoSet {
+            player: Some(player),
+            ..InfoSet::default()
+        };
+        for Position { x, y, piece } in board.iter() {
+            if let Some(piece) = piece {
+                if piece.player != player {
+                    infoset.possible[piece.unit.index()] |= bit((x as i32, y as i32));
+                    infoset.remaining[piece.unit.index()] += 1;
+                }
+            }
+        }
+        infoset
+    }
+    /// Possible lists every square an enemy unit of the given type could be
+    /// on.
+    pub fn possible(&self, unit: Unit) -> Vec<(i32, i32)> {
+        squares(self.possible[unit.index()])
+    }
+    /// Possible mask returns the raw bitboard of squares an enemy unit of the
+    /// given type could be on.
+    pub fn possible_mask(&self, unit: Unit) -> u64 {
+        self.possible[unit.index()]
+    }
+    /// Could be lists the enemy unit types that could be on the given square.
+    pub fn could_be(&self, pos: (i32, i32)) -> Vec<Unit> {
+        if !in_bounds(pos) {
+            return vec![];
+        }
+        UNITS
+            .iter()
+            .cloned()
+            .filter(|unit| self.possible[unit.index()] & bit(pos) != 0)
+            .collect()
+    }
+    /// Remaining is the number of enemy units of the given type not yet
+    /// known to be captured.
+    pub fn remaining(&self, unit: Unit) -> u32 {
+        self.remaining[unit.index()]
+    }
+    /// Moves is the number of opponent moves observed so far.
+    pub fn moves(&self) -> u32 {
+        self.moves
+    }
+    /// Observe updates the information set after a ply.
+    ///
+    /// `before` and `after` are the full boards either side of the ply; only
+    /// the parts visible to the player are used. `opponent_moved` is set when
+    /// the ply was made by the opponent, and `captured` lists the enemy units
+    /// the player captured during the ply.
+    pub fn observe(
+        &mut self,
+        before: &Board,
+        after: &Board,
+        opponent_moved: bool,
+        captured: &[Unit],
+    ) {
+        let player = match self.player {
+            Some(player) => player,
+            None => return,
+        };
+        if opponent_moved {
+            self.expand(&known(before, &player), &player);
+            self.moves += 1;
+        }
+        for unit in captured {
+            let remaining = &mut self.remaining[unit.index()];
+            *remaining = remaining.saturating_sub(1);
+        }
+        self.constrain(after, &player);
+    }
+    // Expand each possible square by every move its unit type could make
+    // from there, given what the player knew of the board.
+    fn expand(&mut self, known: &Board, player: &Player) {
+        let enemy = player.opponent();
+        for unit in UNITS.iter() {
+            let mut reach = self.possible[unit.index()];
+            for pos in squares(self.possible[unit.index()]) {
+                if let Some(piece) = known.get(pos) {
+                    if piece.player != enemy || piece.unit != *unit {
+                        continue;
+                    }
+                }
+                let mut board = known.clone();
+                board.set(
+                    pos,
+                    Piece {
+                        unit: *unit,
+                        player: enemy,
+                        moved: if *unit == Unit::Pawn && pos.1 == enemy.pawn_rank() {
+                            0
+                        } else {
+                            1
+                        },
+                    },
+                );
+                for to in board.moves(pos) {
+                    reach |= bit(to);
+                }
+            }
+            self.possible[unit.index()] = reach;
+        }
+    }
+    // Constrain possible squares to agree with what the player can currently
+    // see.
+    fn constrain(&mut self, board: &Board, player: &Player) {
+        let visible = board.visibility(player);
+        let mut visible_mask = 0;
+        let mut seen = [0u64; 6];
+        for Position { x, y, piece } in board.iter() {
+            if !visible[y][x] {
+                continue;
+            }
+            let pos = (x as i32, y as i32);
+            visible_mask |= bit(pos);
+            if let Some(piece) = piece {
+                if piece.player != *player {
+                    seen[piece.unit.index()] |= bit(pos);
+                }
+            }
+        }
+        for unit in UNITS.iter() {
+            let ii = unit.index();
+            let count = seen[ii].count_ones();
+            if count >= self.remaining[ii] {
+                // Every remaining unit of this type is in plain sight.
+                self.remaining[ii] = count;
+                self.possible[ii] = seen[ii];
+            } else {
+                self.possible[ii] = (self.possible[ii] & !visible_mask) | seen[ii];
+            }
+        }
+    }
+}
+
+// Known returns the board as the player knows it: their own pieces and any
+// enemy pieces they can see.
+fn known(board: &Board, player: &Player) -> Board {
+    let visible = board.visibility(player);
+    let mut known = Board::default();
+    for Position { x, y, piece } in board.iter() {
+        if let Some(piece) = piece {
+            if piece.player == *player || visible[y][x] {
+                known.set((x as i32, y as i32), piece.clone());
+            }
+        }
+    }
+    known
+}
+
+/// Bit returns the bitboard bit for a board position.
+pub fn bit(pos: (i32, i32)) -> u64 {
+    let (x, y) = pos;
+    1 << (y * 8 + x)
+}
+
+/// Squares lists the positions set in a bitboard.
+pub fn squares(mask: u64) -> Vec<(i32, i32)> {
+    (0..64)
+        .filter(|ii| mask & (1 << ii) != 0)
+        .map(|ii| (ii % 8, ii / 8))
+        .collect()
+}
@@ -6,7 +6,30 @@ use ggez::input::keyboard::{is_key_pressed, KeyCode, KeyMods};
 use ggez::input::mouse::MouseButton;
 use ggez::{conf::WindowMode, conf::WindowSetup};
 use ggez::{Context, ContextBuilder, GameResult};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
 use std::collections::HashSet;
+use std::fmt;
+use std::fs::File;
+use std::io::Write;
+use std::rc::Rc;
+
+mod analysis;
+mod arena;
+mod audit;
+mod bot;
+mod chat;
+mod infoset;
+mod net;
+mod peer;
+mod pgn;
+mod protocol;
+mod tournament;
+
+use bot::Bot;
+use infoset::InfoSet;
 
 const PURE_APPLE: Color = Color {
     r: 106.0 / 256.0,
@@ -29,6 +52,34 @@ const WIZARD_GREY: Color = Color {
     a: 1.0,
 };
 
+const CARMINE_PINK: Color = Color {
+    r: 235.0 / 256.0,
+    g: 77.0 / 256.0,
+    b: 75.0 / 256.0,
+    a: 1.0,
+};
+
+// Fog is tinted towards the board palette rather than pure black, so that the
+// checkerboard still reads through it.
+const FOG: Color = Color {
+    r: 20.0 / 256.0,
+    g: 23.0 / 256.0,
+    b: 28.0 / 256.0,
+    a: 1.0,
+};
+
+// Hints are outlined in yellow, apart from the green of the selection.
+const TURBO: Color = Color {
+    r: 249.0 / 256.0,
+    g: 202.0 / 256.0,
+    b: 36.0 / 256.0,
+    a: 1.0,
+};
+
+// How many plies a square stays in the "recently seen" tier after it was last
+// visible.
+const RECENT_PLIES: u32 = 4;
+
 fn main() {
     let app = App::new("Fog Of Chess")
         .arg(
@@ -37,6 +88,62 @@ fn main() {
                 .long("no-fog")
                 .help("Turn off the fog of war."),
         )
+        .arg(
+            Arg::with_name("view")
+                .takes_value(true)
+                .long("view")
+                .possible_values(&["white", "black", "mover", "omniscient"])
+                .conflicts_with_all(&["no-fog", "vs-bot"])
+                .help("Whose view of the board to show. Cycle at runtime with V, except against a bot until the game is over."),
+        )
+        .arg(
+            Arg::with_name("vs-bot")
+                .takes_value(false)
+                .long("vs-bot")
+                .help("Play as White against a computer opponent."),
+        )
+        .arg(
+            Arg::with_name("bot")
+                .takes_value(true)
+                .long("bot")
+                .default_value("random")
+                .help("Which bot to play against: random, ismcts, alphabeta or a named opponent."),
+        )
+        .arg(
+            Arg::with_name("opponents")
+                .takes_value(true)
+                .long("opponents")
+                .default_value("opponents.toml")
+                .help("File of named opponents with their playing styles."),
+        )
+        .arg(
+            Arg::with_name("difficulty")
+                .takes_value(true)
+                .long("difficulty")
+                .possible_values(&["easy", "medium", "hard"])
+                .default_value("medium")
+                .help("Strength of the alphabeta bot."),
+        )
+        .arg(
+            Arg::with_name("hints")
+                .takes_value(true)
+                .long("hints")
+                .default_value("3")
+                .help("How many hints each player may ask for with ? per game."),
+        )
+        .arg(
+            Arg::with_name("seed")
+                .takes_value(true)
+                .long("seed")
+                .help("Seed all randomness so the game can be reproduced. A random seed is printed otherwise."),
+        )
+        .arg(
+            Arg::with_name("load")
+                .takes_value(true)
+                .long("load")
+                .conflicts_with("seed")
+                .help("Resume a game saved with S, replaying its moves from its seed."),
+        )
         .arg(
             Arg::with_name("debug-stats")
                 .takes_value(false)
@@ -50,7 +157,533 @@ fn main() {
                     .help("Name of scenario to test."),
             ),
         )
+        .subcommand(
+            SubCommand::with_name("serve")
+                .about("Host network games for any number of clients without a window. Games joined without settings use these.")
+                .arg(
+                    Arg::with_name("addr")
+                        .long("addr")
+                        .takes_value(true)
+                        .default_value("127.0.0.1:7878")
+                        .help("Address to listen on."),
+                )
+                .arg(
+                    Arg::with_name("time-control")
+                        .long("time-control")
+                        .takes_value(true)
+                        .help("Play games on the clock: minutes plus increment seconds, such as 5+3."),
+                )
+                .arg(
+                    Arg::with_name("variant")
+                        .long("variant")
+                        .takes_value(true)
+                        .default_value("standard")
+                        .help("Starting position: standard, or a test scenario such as castle."),
+                )
+                .arg(
+                    Arg::with_name("reconnect-timeout")
+                        .long("reconnect-timeout")
+                        .takes_value(true)
+                        .default_value("60")
+                        .help("Seconds a disconnected player has to resume before forfeiting."),
+                )
+                .arg(
+                    Arg::with_name("pause-clock")
+                        .long("pause-clock")
+                        .takes_value(false)
+                        .help("Stop the clocks while a player is disconnected."),
+                )
+                .arg(
+                    Arg::with_name("spectator-delay")
+                        .long("spectator-delay")
+                        .takes_value(true)
+                        .help("Show spectators the game this many plies behind play. Fog games default to 10, others to 0; fog games cannot be watched with 0."),
+                )
+                .arg(
+                    Arg::with_name("audit")
+                        .long("audit")
+                        .takes_value(true)
+                        .help("Write a tamper-evident audit log of every game to this directory."),
+                )
+                .arg(
+                    Arg::with_name("no-chat")
+                        .long("no-chat")
+                        .takes_value(false)
+                        .help("Disable chat between players in games joined without choosing settings, as for tournament games."),
+                )
+                .arg(
+                    Arg::with_name("web")
+                        .long("web")
+                        .takes_value(true)
+                        .help("Also serve the browser client on this address, such as 127.0.0.1:8080."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("connect")
+                .about("Join a network game.")
+                .arg(
+                    Arg::with_name("addr")
+                        .required(true)
+                        .help("Address of the server, such as 127.0.0.1:7878."),
+                )
+                .arg(
+                    Arg::with_name("name")
+                        .long("name")
+                        .takes_value(true)
+                        .default_value("")
+                        .help("Name shown to the other player."),
+                )
+                .arg(
+                    Arg::with_name("resume")
+                        .long("resume")
+                        .takes_value(true)
+                        .help("Session token printed when joining, to take back a seat after a disconnect."),
+                )
+                .arg(
+                    Arg::with_name("list")
+                        .long("list")
+                        .takes_value(false)
+                        .help("List the games on the server and exit."),
+                )
+                .arg(
+                    Arg::with_name("game")
+                        .long("game")
+                        .takes_value(true)
+                        .help("Id of the game to join, as listed."),
+                )
+                .arg(
+                    Arg::with_name("spectate")
+                        .long("spectate")
+                        .takes_value(true)
+                        .help("Id of a game to watch, as listed."),
+                )
+                .arg(
+                    Arg::with_name("create")
+                        .long("create")
+                        .takes_value(false)
+                        .help("Create a game with --variant, --time-control and --no-fog, and join it."),
+                )
+                .arg(
+                    Arg::with_name("variant")
+                        .long("variant")
+                        .takes_value(true)
+                        .default_value("standard")
+                        .help("Starting position of a created game."),
+                )
+                .arg(
+                    Arg::with_name("time-control")
+                        .long("time-control")
+                        .takes_value(true)
+                        .help("Clock of a created game: minutes plus increment seconds, such as 5+3."),
+                )
+                .arg(
+                    Arg::with_name("no-chat")
+                        .long("no-chat")
+                        .takes_value(false)
+                        .help("Disable chat between players in a created game."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("peer")
+                .about("Play a fog game directly against another player, without a server. Cheating is caught when the game ends.")
+                .arg(
+                    Arg::with_name("addr")
+                        .required(true)
+                        .help("Address of the other player, or with --listen the address to wait on, such as 0.0.0.0:7879."),
+                )
+                .arg(
+                    Arg::with_name("listen")
+                        .long("listen")
+                        .takes_value(false)
+                        .help("Wait for the other player to connect, and play White."),
+                )
+                .arg(
+                    Arg::with_name("name")
+                        .long("name")
+                        .takes_value(true)
+                        .default_value("player")
+                        .help("Name shown to the other player."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("mail")
+                .about("Play a fog game by correspondence, sending signed move files instead of connecting. Cheating is caught when the game ends.")
+                .subcommand(
+                    SubCommand::with_name("new")
+                        .about("Start a game as White, writing an invitation to send.")
+                        .arg(Arg::with_name("state")
+                                .required(true)
+                                .help("Private file keeping your side of the game. Never send it."))
+                        .arg(Arg::with_name("out")
+                                .required(true)
+                                .help("Invitation to write."))
+                        .arg(Arg::with_name("name")
+                                .long("name")
+                                .takes_value(true)
+                                .default_value("player")
+                                .help("Name shown to the other player.")),
+                )
+                .subcommand(
+                    SubCommand::with_name("accept")
+                        .about("Accept an invitation as Black, writing the reply to send.")
+                        .arg(Arg::with_name("state")
+                                .required(true)
+                                .help("Private file keeping your side of the game. Never send it."))
+                        .arg(
+                            Arg::with_name("invitation")
+                                .required(true)
+                                .help("Invitation received."),
+                        )
+                        .arg(Arg::with_name("out")
+                                .required(true)
+                                .help("Reply to write."))
+                        .arg(Arg::with_name("name")
+                                .long("name")
+                                .takes_value(true)
+                                .default_value("player")
+                                .help("Name shown to the other player.")),
+                )
+                .subcommand(
+                    SubCommand::with_name("read")
+                        .about("Read a file from the other player and show the board.")
+                        .arg(Arg::with_name("state")
+                                .required(true)
+                                .help("Private file keeping your side of the game. Never send it."))
+                        .arg(
+                            Arg::with_name("file")
+                                .required(true)
+                                .help("File received."),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("show")
+                        .about("Show the board as you last saw it.")
+                        .arg(Arg::with_name("state")
+                                .required(true)
+                                .help("Private file keeping your side of the game. Never send it.")),
+                )
+                .subcommand(
+                    SubCommand::with_name("move")
+                        .about("Play your move, writing the file to send.")
+                        .arg(Arg::with_name("state")
+                                .required(true)
+                                .help("Private file keeping your side of the game. Never send it."))
+                        .arg(
+                            Arg::with_name("move")
+                                .required(true)
+                                .help("Squares moved from and to, such as e2e4. Castle with the king's square then the rook's, such as e1h1 to castle kingside as White."),
+                        )
+                        .arg(Arg::with_name("out")
+                                .required(true)
+                                .help("File to write and send."))
+                        .arg(
+                            Arg::with_name("offer-draw")
+                                .long("offer-draw")
+                                .takes_value(false)
+                                .help("Offer a draw along with the move."),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("draw")
+                        .about("Accept the other player's draw offer instead of moving.")
+                        .arg(Arg::with_name("state")
+                                .required(true)
+                                .help("Private file keeping your side of the game. Never send it."))
+                        .arg(Arg::with_name("out")
+                                .required(true)
+                                .help("File to write and send.")),
+                )
+                .subcommand(
+                    SubCommand::with_name("resign")
+                        .about("Resign instead of moving.")
+                        .arg(Arg::with_name("state")
+                                .required(true)
+                                .help("Private file keeping your side of the game. Never send it."))
+                        .arg(Arg::with_name("out")
+                                .required(true)
+                                .help("File to write and send.")),
+                )
+                .subcommand(
+                    SubCommand::with_name("reveal")
+                        .about("Once the game is over, reveal your game so the other player can check it.")
+                        .arg(Arg::with_name("state")
+                                .required(true)
+                                .help("Private file keeping your side of the game. Never send it."))
+                        .arg(Arg::with_name("out")
+                                .required(true)
+                                .help("File to write and send.")),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("replay")
+                .about("Step through a saved game with the arrow keys, Home and End. Escape leaves the replay, and again quits.")
+                .arg(
+                    Arg::with_name("pgn")
+                        .required(true)
+                        .help("Saved game to replay."),
+                )
+                .arg(
+                    Arg::with_name("analyse")
+                        .long("analyse")
+                        .help("Grade every move before replaying, replacing any comments."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("verify")
+                .about("Check a server audit log: its hashes, every move against the rules and every view against the player's vision.")
+                .arg(
+                    Arg::with_name("log")
+                        .required(true)
+                        .help("Audit log to verify."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("analyse")
+                .about("Grade every move of a saved game without a window.")
+                .arg(
+                    Arg::with_name("pgn")
+                        .required(true)
+                        .help("Saved game to analyse."),
+                )
+                .arg(
+                    Arg::with_name("out")
+                        .long("out")
+                        .takes_value(true)
+                        .help("Write the game with the analysis as comments to this PGN file."),
+                )
+                .arg(
+                    Arg::with_name("depth")
+                        .long("depth")
+                        .takes_value(true)
+                        .default_value("3")
+                        .help("Search depth in plies."),
+                )
+                .arg(
+                    Arg::with_name("samples")
+                        .long("samples")
+                        .takes_value(true)
+                        .default_value("6")
+                        .help("Boards sampled from each fog view."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("match")
+                .about("Play bots against each other without a window.")
+                .arg(
+                    Arg::with_name("a")
+                        .required(true)
+                        .possible_values(&bot::BOTS)
+                        .help("Bot playing White in odd games."),
+                )
+                .arg(
+                    Arg::with_name("b")
+                        .required(true)
+                        .possible_values(&bot::BOTS)
+                        .help("Bot playing White in even games."),
+                )
+                .arg(
+                    Arg::with_name("games")
+                        .long("games")
+                        .takes_value(true)
+                        .default_value("10")
+                        .help("Number of games to play."),
+                )
+                .arg(
+                    Arg::with_name("max-plies")
+                        .long("max-plies")
+                        .takes_value(true)
+                        .default_value("300")
+                        .help("Draw games still undecided after this many plies."),
+                )
+                .arg(
+                    Arg::with_name("results")
+                        .long("results")
+                        .takes_value(true)
+                        .help("Write one CSV line per game to this file."),
+                )
+                .arg(
+                    Arg::with_name("pgn")
+                        .long("pgn")
+                        .takes_value(true)
+                        .help("Write every game to this PGN file."),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("tournament")
+                .about("Play a tournament between bots without a window.")
+                .arg(
+                    Arg::with_name("players")
+                        .multiple(true)
+                        .help("Bots to enter: built-in names, or cmd:<command> or proto:<command> for process bots."),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["round-robin", "swiss"])
+                        .default_value("round-robin")
+                        .help("How players are paired."),
+                )
+                .arg(
+                    Arg::with_name("rounds")
+                        .long("rounds")
+                        .takes_value(true)
+                        .help("Round-robin cycles, or Swiss rounds. Defaults to one cycle, or enough Swiss rounds to find a winner."),
+                )
+                .arg(
+                    Arg::with_name("max-plies")
+                        .long("max-plies")
+                        .takes_value(true)
+                        .default_value("300")
+                        .help("Draw games still undecided after this many plies."),
+                )
+                .arg(
+                    Arg::with_name("state")
+                        .long("state")
+                        .takes_value(true)
+                        .help("Save progress to this file, resuming from it if it exists."),
+                )
+                .arg(
+                    Arg::with_name("pgn")
+                        .long("pgn")
+                        .takes_value(true)
+                        .help("Append every game to this PGN file."),
+                ),
+        )
         .get_matches();
+    if let Some(args) = app.subcommand_matches("tournament") {
+        if let Err(err) = run_tournament(args, app.is_present("no-fog"), app.value_of("seed")) {
+            eprintln!("tournament: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(args) = app.subcommand_matches("match") {
+        if let Err(err) = run_match(args, app.is_present("no-fog"), app.value_of("seed")) {
+            eprintln!("match: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(args) = app.subcommand_matches("analyse") {
+        if let Err(err) = run_analysis(args) {
+            eprintln!("analyse: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(args) = app.subcommand_matches("mail") {
+        if let Err(err) = run_mail(args) {
+            eprintln!("mail: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(args) = app.subcommand_matches("verify") {
+        let path = args.value_of("log").expect("log is required");
+        let verified = File::open(path)
+            .map_err(|err| err.to_string())
+            .and_then(|file| audit::verify(std::io::BufReader::new(file)));
+        match verified {
+            Ok(summary) => println!("{}: {}", path, summary),
+            Err(err) => {
+                eprintln!("{}: {}", path, err);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if let Some(args) = app.subcommand_matches("serve") {
+        let config = net::ServerConfig {
+            defaults: game_settings(&app, args),
+            seed: app
+                .value_of("seed")
+                .map(|seed| seed.parse().expect("--seed must be a number")),
+            reconnect_timeout: std::time::Duration::from_secs(
+                args.value_of("reconnect-timeout")
+                    .expect("reconnect-timeout has a default")
+                    .parse()
+                    .expect("--reconnect-timeout must be a number of seconds"),
+            ),
+            pause_clock: args.is_present("pause-clock"),
+            spectator_delay: args.value_of("spectator-delay").map(|delay| {
+                delay
+                    .parse()
+                    .expect("--spectator-delay must be a number of plies")
+            }),
+            web: args.value_of("web").map(String::from),
+            audit: args.value_of("audit").map(std::path::PathBuf::from),
+        };
+        if let Some(dir) = &config.audit {
+            std::fs::create_dir_all(dir).expect("creating audit log directory");
+        }
+        net::serve(args.value_of("addr").expect("addr has a default"), config)
+            .expect("serving games");
+        return;
+    }
+    if let Some(args) = app.subcommand_matches("connect") {
+        if args.is_present("list") {
+            let addr = args.value_of("addr").expect("addr is required");
+            let games = net::list_games(addr).expect("listing games");
+            if games.is_empty() {
+                println!("no games on {}", addr);
+            }
+            for info in games {
+                let seat = |name: &Option<String>| name.clone().unwrap_or_else(|| "(open)".into());
+                println!(
+                    "game {}: {} vs {}, {}{}",
+                    info.id,
+                    seat(&info.players[0]),
+                    seat(&info.players[1]),
+                    info.settings,
+                    if info.started { ", in play" } else { "" }
+                );
+            }
+            return;
+        }
+    }
+    let remote = app.subcommand_matches("connect").map(|args| {
+        let addr = args.value_of("addr").expect("addr is required");
+        let name = args.value_of("name").expect("name has a default");
+        let id = |arg| {
+            args.value_of(arg)
+                .map(|id: &str| id.parse().expect("game ids are numbers"))
+        };
+        let remote = if let Some(token) = args.value_of("resume") {
+            net::Remote::resume(addr, token)
+        } else if let Some(game) = id("spectate") {
+            net::Remote::spectate(addr, game)
+        } else if let Some(game) = id("game") {
+            net::Remote::join_game(addr, game, name)
+        } else if args.is_present("create") {
+            net::Remote::create(addr, name, game_settings(&app, args))
+        } else {
+            net::Remote::connect(addr, name)
+        }
+        .expect("connecting to server");
+        match remote.player {
+            Some(player) => println!("playing {:?} on {}", player, addr),
+            None => println!("watching on {}", addr),
+        }
+        if let Some(token) = &remote.token {
+            println!("session token {}, rejoin with --resume {}", token, token);
+        }
+        Rc::new(remote)
+    });
+    let remote = remote.or_else(|| {
+        app.subcommand_matches("peer").map(|args| {
+            let addr = args.value_of("addr").expect("addr is required");
+            let name = args.value_of("name").expect("name has a default");
+            let local = if args.is_present("listen") {
+                println!("waiting for the other player on {}", addr);
+                std::net::TcpListener::bind(addr).and_then(|listener| peer::host(listener, name))
+            } else {
+                peer::join(addr, name)
+            }
+            .expect("starting game with the other player");
+            Rc::new(net::Remote::connect(local, name).expect("connecting to the local game"))
+        })
+    });
     let (board, single_player) = match app.subcommand_matches("test") {
         Some(test) => match Board::scenario(
             test.value_of("scenario")
@@ -61,8 +694,29 @@ fn main() {
         },
         None => (Board::new(), false),
     };
+    if app.is_present("load") && app.subcommand_matches("test").is_some() {
+        eprintln!("--load cannot be combined with a test scenario, saved games start from the standard board");
+        std::process::exit(1);
+    }
+    let replay = app.subcommand_matches("replay");
+    let saved = app
+        .value_of("load")
+        .or_else(|| replay.and_then(|args| args.value_of("pgn")))
+        .map(|path| {
+            let text = std::fs::read_to_string(path).expect("reading saved game");
+            pgn::parse(&text).expect("parsing saved game")
+        });
+    let seed: u64 = match saved
+        .as_ref()
+        .and_then(|saved| saved.tag("Seed"))
+        .or_else(|| app.value_of("seed"))
+    {
+        Some(seed) => seed.parse().expect("seed must be a number"),
+        None => rand::random(),
+    };
+    println!("seed {}", seed);
     let (width, height) = (800.0, 800.0);
-    let (mut ctx, mut event_loop) = ContextBuilder::new("Fog of War", "Jack Mordaunt")
+    let (mut ctx, event_loop) = ContextBuilder::new("Fog of War", "Jack Mordaunt")
         .window_mode(
             WindowMode::default()
                 .dimensions(width, height)
@@ -74,7 +728,18 @@ fn main() {
     let state = StateBuilder::default()
         .board(board)
         .single_player(single_player)
-        .fog(!app.is_present("no-fog"))
+        .view(if app.is_present("no-fog") {
+            View::Omniscient
+        } else {
+            match app.value_of("view") {
+                Some("white") => View::White,
+                Some("black") => View::Black,
+                Some("omniscient") => View::Omniscient,
+                // Against a bot, only ever show the human's view.
+                _ if app.is_present("vs-bot") => View::White,
+                _ => View::Mover,
+            }
+        })
         .selected(HashSet::new())
         .turn(Player::White)
         .font(
@@ -82,27 +747,235 @@ fn main() {
                 .expect("loading font"),
         )
         .debug_stats(app.is_present("debug-stats"))
+        .no_fog(
+            app.is_present("no-fog")
+                || saved.as_ref().and_then(|saved| saved.tag("Variant")) == Some("Standard"),
+        )
+        .seed(seed)
+        .hint_limit(
+            app.value_of("hints")
+                .expect("hints has a default")
+                .parse()
+                .expect("--hints must be a number"),
+        )
         .build()
         .expect("building game object");
-    event::run(
-        ctx,
-        event_loop,
-        Game {
-            state: state.clone(),
-            initial: state.clone(),
-        },
-    )
+    let mut game = Game::new(state);
+    if app.is_present("vs-bot") {
+        let seed = game.state.rng.gen();
+        let bot: Box<dyn Bot> = match app.value_of("bot").expect("bot has a default") {
+            "alphabeta" => Box::new(bot::AlphaBeta::new(
+                match app
+                    .value_of("difficulty")
+                    .expect("difficulty has a default")
+                {
+                    "easy" => bot::Difficulty::Easy,
+                    "hard" => bot::Difficulty::Hard,
+                    _ => bot::Difficulty::Medium,
+                },
+            )),
+            name => match bot::by_name(name, Some(seed)) {
+                Some(bot) => bot,
+                None => {
+                    let path = app.value_of("opponents").expect("opponents has a default");
+                    let personalities =
+                        bot::load_personalities(path).unwrap_or_else(|err| panic!("{}", err));
+                    match personalities.get(name) {
+                        Some(personality) => {
+                            Box::new(bot::Styled::new(name, personality.clone(), Some(seed)))
+                        }
+                        None => panic!(
+                            "unknown bot {:?}: choose one of {} or an opponent from {}",
+                            name,
+                            bot::BOTS.join(", "),
+                            path
+                        ),
+                    }
+                }
+            },
+        };
+        game.opponent = Some(Opponent::new(Player::Black, bot));
+    }
+    if let Some(saved) = saved {
+        game.play_pgn(&saved).expect("replaying saved game");
+        if let Some(args) = replay {
+            let comments = if args.is_present("analyse") {
+                analysed(&game)
+            } else {
+                saved.comments
+            };
+            game.start_replay(comments);
+        }
+    }
+    if let Some(remote) = remote {
+        game.state.view = match remote.player {
+            Some(Player::White) => View::White,
+            Some(Player::Black) => View::Black,
+            None => View::Omniscient,
+        };
+        game.remote = Some(remote);
+    }
+    event::run(ctx, event_loop, game)
 }
 
 impl EventHandler for Game {
     fn update(&mut self, _ctx: &mut Context) -> GameResult<()> {
+        if let Some(remote) = self.remote.clone() {
+            while let Some(message) = remote.poll() {
+                match message {
+                    protocol::Message::Update(view) => self.show(*view),
+                    protocol::Message::Position { ply, turn, board } => {
+                        self.state.board = *board;
+                        self.state.turn = turn;
+                        self.state.ply = ply;
+                    }
+                    protocol::Message::MoveRejected { reason, .. } => {
+                        println!("move rejected: {}", reason)
+                    }
+                    protocol::Message::Clock {
+                        remaining_ms,
+                        running,
+                    } => self.state.clocks = Some(net::Clocks::reading(remaining_ms, running)),
+                    protocol::Message::Spectators { count } => self.state.spectators = count,
+                    protocol::Message::Chat { from, text } => {
+                        self.chat.borrow_mut().push(&from, &text)
+                    }
+                    protocol::Message::DrawOffer { player } => {
+                        println!("{:?} offers a draw, press D to accept", player)
+                    }
+                    protocol::Message::GameOver { winner, reason } => {
+                        println!("game over: {}", reason);
+                        self.state.outcome =
+                            Some(winner.map_or(arena::Outcome::Draw, arena::Outcome::Win));
+                        if let Some(clocks) = &mut self.state.clocks {
+                            *clocks = net::Clocks::reading(
+                                [Player::White, Player::Black]
+                                    .map(|player| clocks.left(player).as_millis() as u64),
+                                None,
+                            );
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            if self.state.outcome.is_none() {
+                match remote.retry() {
+                    Some(Ok(resumed)) => {
+                        println!("resumed the game");
+                        self.remote = Some(Rc::new(resumed));
+                    }
+                    Some(Err(err)) => eprintln!("resuming game: {}", err),
+                    None => {}
+                }
+            }
+        }
+        if self.state.belief_filter.is_some() {
+            self.refresh_belief();
+        }
+        if let Some(opponent) = self.opponent.clone() {
+            if opponent.player == self.state.turn
+                && self.winner().is_none()
+                && self.replay.is_none()
+            {
+                let view = self.view_for(&opponent.player);
+                let chosen = opponent.bot.borrow_mut().choose(&view);
+                match chosen {
+                    Ok(Some(mv)) => {
+                        self.make_move(mv);
+                    }
+                    // Nothing to play, so pass rather than stall the game.
+                    Ok(None) => self.state.turn = self.state.turn.opponent(),
+                    // Asking again would only wait out the deadline again, so
+                    // leave both sides to the human.
+                    Err(err) => {
+                        eprintln!("bot {:?}: {}", opponent.bot.borrow().name(), err);
+                        self.opponent = None;
+                    }
+                }
+            }
+        }
         Ok(())
     }
 
+    fn key_down_event(&mut self, ctx: &mut Context, kc: KeyCode, _keymods: KeyMods, _repeat: bool) {
+        let typing = self.chat.borrow().typing();
+        match kc {
+            KeyCode::Return | KeyCode::NumpadEnter if typing => self.send_chat(),
+            KeyCode::Return | KeyCode::NumpadEnter => self.chat.borrow_mut().open(),
+            KeyCode::Back => self.chat.borrow_mut().backspace(),
+            KeyCode::Escape if typing => self.chat.borrow_mut().cancel(),
+            // Leave a replay at the end of the game, rather than quitting.
+            KeyCode::Escape if self.replay.is_some() => {
+                self.seek(usize::MAX);
+                self.replay = None;
+            }
+            KeyCode::Escape => event::quit(ctx),
+            KeyCode::PageUp => self.chat.borrow_mut().scroll_back(5),
+            KeyCode::PageDown => self.chat.borrow_mut().scroll_back(-5),
+            _ => {}
+        }
+    }
+
+    fn text_input_event(&mut self, _ctx: &mut Context, ch: char) {
+        self.chat.borrow_mut().type_char(ch);
+    }
+
     fn key_up_event(&mut self, _ctx: &mut Context, kc: KeyCode, _keymods: KeyMods) {
+        // Keys typed into the chat are not commands.
+        if self.chat.borrow().typing() {
+            return;
+        }
+        match kc {
+            KeyCode::V if self.view_locked() => println!("the view is fixed while playing a bot"),
+            KeyCode::V => self.state.view = self.state.view.next(),
+            KeyCode::H => self.state.heatmap = !self.state.heatmap,
+            KeyCode::T => self.state.threats = !self.state.threats,
+            KeyCode::B => {
+                // Cycle through every unit together, each unit alone, then off.
+                self.state.belief_filter = match self.state.belief_filter {
+                    None => Some(None),
+                    Some(None) => Some(Some(UNITS[0])),
+                    Some(Some(unit)) => UNITS.get(unit.index() + 1).map(|next| Some(*next)),
+                };
+            }
+            KeyCode::Slash => {
+                if let Err(reason) = self.hint() {
+                    self.state.feedback = Some(format!("no hint: {}", reason));
+                }
+            }
+            KeyCode::A if self.winner().is_some() && self.replay.is_none() => {
+                let comments = analysed(self);
+                self.start_replay(comments);
+            }
+            KeyCode::Q | KeyCode::D if self.winner().is_none() => {
+                if let Some(remote) = &self.remote {
+                    let sent = if kc == KeyCode::Q {
+                        remote.resign()
+                    } else {
+                        remote.offer_draw()
+                    };
+                    if let Err(err) = sent {
+                        eprintln!("sending to server: {}", err);
+                    }
+                }
+            }
+            KeyCode::S => match self.save() {
+                Ok(path) => println!("saved game to {}", path),
+                Err(err) => eprintln!("saving game: {}", err),
+            },
+            _ => {}
+        };
+        if let Some(cursor) = self.replay.as_ref().map(|replay| replay.cursor) {
+            match kc {
+                KeyCode::Left => self.seek(cursor.saturating_sub(1)),
+                KeyCode::Right => self.seek(cursor + 1),
+                KeyCode::Home => self.seek(0),
+                KeyCode::End => self.seek(usize::MAX),
+                _ => {}
+            }
+        }
         if cfg!(debug_assertions) {
             match kc {
-                KeyCode::F => self.state.fog = !self.state.fog,
                 KeyCode::F3 => self.state.debug_stats = !self.state.debug_stats,
                 KeyCode::R => self.state = self.initial.clone(),
                 _ => {}
@@ -111,6 +984,9 @@ impl EventHandler for Game {
     }
 
     fn mouse_button_up_event(&mut self, ctx: &mut Context, _b: MouseButton, x: f32, y: f32) {
+        if self.replay.is_some() {
+            return;
+        }
         let (col, row) = self.pixels_to_grid(ctx, (x, y));
         if is_key_pressed(ctx, KeyCode::LShift) {
             if self.contains_ally((col, row)) {
@@ -150,12 +1026,25 @@ impl EventHandler for Game {
     }
 
     fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
+        let viewer = self.viewer();
         self.draw_board(ctx)?;
-        self.draw_pieces(ctx)?;
-        self.draw_highlights(ctx)?;
-        if self.state.fog {
-            self.draw_fog(ctx)?;
+        if let Some(viewer) = viewer {
+            self.draw_fog(ctx, &viewer)?;
         }
+        self.draw_pieces(ctx, viewer.as_ref())?;
+        if self.state.heatmap {
+            self.draw_heatmap(ctx, &viewer.unwrap_or(self.state.turn))?;
+        }
+        if let Some(filter) = self.state.belief_filter {
+            self.draw_belief(ctx, filter)?;
+        }
+        if self.state.threats && viewer.is_none() {
+            self.draw_threats(ctx, &self.state.turn)?;
+        }
+        self.draw_highlights(ctx)?;
+        self.draw_coordinates(ctx);
+        self.draw_hud(ctx)?;
+        self.draw_chat(ctx)?;
         if self.state.debug_stats {
             self.draw_debug_stats(ctx)?;
         }
@@ -175,7 +1064,7 @@ impl EventHandler for Game {
 }
 
 /// Unique chess units.
-#[derive(Clone, Debug)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
 pub enum Unit {
     Pawn,
     Rook,
@@ -185,15 +1074,25 @@ pub enum Unit {
     King,
 }
 
+/// Every unit, in `Unit::index` order.
+pub const UNITS: [Unit; 6] = [
+    Unit::Pawn,
+    Unit::Rook,
+    Unit::Knight,
+    Unit::Bishop,
+    Unit::Queen,
+    Unit::King,
+];
+
 /// Player denotes the two unique players that can own units.
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
 pub enum Player {
     White,
     Black,
 }
 
 /// Piece is a Unit-Player pair that represents a piece on the board.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Piece {
     pub unit: Unit,
     pub player: Player,
@@ -201,8 +1100,11 @@ pub struct Piece {
     pub moved: u32,
 }
 
+/// Move is a (from, to) pair of board positions.
+pub type Move = ((i32, i32), (i32, i32));
+
 /// Board contains the location information of each piece.
-#[derive(Clone, Default)]
+#[derive(Clone, Default, Deserialize, Serialize)]
 pub struct Board([[Option<Piece>; 8]; 8]);
 
 /// Game contains meta information.
@@ -210,6 +1112,42 @@ pub struct Board([[Option<Piece>; 8]; 8]);
 pub struct Game {
     pub initial: State,
     pub state: State,
+    pub opponent: Option<Opponent>,
+    /// Set while stepping through a finished game.
+    pub replay: Option<Replay>,
+    /// Connection to the server hosting a network game. The server holds
+    /// the real game; this one only mirrors what the player can see.
+    pub remote: Option<Rc<net::Remote>>,
+    /// Messages exchanged during the game, shared by clones of it.
+    pub chat: Rc<RefCell<chat::Chat>>,
+}
+
+/// Replay steps through a game one ply at a time.
+#[derive(Clone)]
+pub struct Replay {
+    /// Every ply of the game.
+    pub steps: Vec<Step>,
+    /// Comment on each ply, such as its analysis.
+    pub comments: Vec<Option<String>>,
+    /// Number of plies currently shown.
+    pub cursor: usize,
+}
+
+/// Opponent is a bot playing one side of the game.
+/// Clones of a game share the same bot.
+#[derive(Clone)]
+pub struct Opponent {
+    pub player: Player,
+    pub bot: Rc<RefCell<Box<dyn Bot>>>,
+}
+
+impl Opponent {
+    pub fn new(player: Player, bot: Box<dyn Bot>) -> Self {
+        Opponent {
+            player,
+            bot: Rc::new(RefCell::new(bot)),
+        }
+    }
 }
 
 #[derive(Clone, Builder)]
@@ -219,205 +1157,561 @@ pub struct State {
     // TODO: Use a set to avoid duplicates.
     pub selected: HashSet<(i32, i32)>,
     pub font: graphics::Font,
-    pub fog: bool,
+    pub view: View,
     pub single_player: bool,
     pub debug_stats: bool,
+    // Play ordinary chess: every player sees the whole board.
+    #[builder(default)]
+    pub no_fog: bool,
+    // Shade squares by how many of the viewer's pieces can see them.
+    #[builder(default)]
+    pub heatmap: bool,
+    // In the omniscient view, mark enemy pieces that can see the mover's
+    // pieces.
+    #[builder(default)]
+    pub threats: bool,
+    // Number of completed moves.
+    #[builder(default)]
+    pub ply: u32,
+    // Reports generated for each player, oldest first.
+    #[builder(default)]
+    pub notices: Vec<Notice>,
+    // Every ply played so far, oldest first.
+    #[builder(default)]
+    pub history: Vec<Step>,
+    // What each player last saw, indexed by `Player::index`.
+    #[builder(default)]
+    pub memories: [Memory; 2],
+    // Where each player believes enemy units could be, indexed by
+    // `Player::index`.
+    #[builder(default)]
+    pub infosets: [InfoSet; 2],
+    // Seed for `rng`, recorded in saved games so they can be reproduced.
+    #[builder(default)]
+    pub seed: u64,
+    // Source of all randomness in the game, such as seeds for bots. Only
+    // ever drawn from in a fixed order, so a seed and the moves played
+    // reproduce a game exactly.
+    #[builder(default = "StdRng::seed_from_u64(0)")]
+    pub rng: StdRng,
+    // Move suggested to the current player, until they move.
+    #[builder(default)]
+    pub hint: Option<Move>,
+    // How many hints each player may ask for per game.
+    #[builder(default)]
+    pub hint_limit: u32,
+    // Hints asked for by each player, indexed by `Player::index`.
+    #[builder(default)]
+    pub hints_used: [u32; 2],
+    // Answer to the last key pressed, shown in the HUD until the next move.
+    #[builder(default)]
+    pub feedback: Option<String>,
+    // Which unit type the belief overlay shows: `None` hides the overlay and
+    // `Some(None)` shows every type together.
+    #[builder(default)]
+    pub belief_filter: Option<Option<Unit>>,
+    // Belief shown by the overlay, recomputed when it goes stale.
+    #[builder(default)]
+    pub belief: Option<Belief>,
+    // Result announced by the server in a network game.
+    #[builder(default)]
+    pub outcome: Option<arena::Outcome>,
+    // Last clock reading from the server in a timed network game.
+    #[builder(default)]
+    pub clocks: Option<net::Clocks>,
+    // Number of spectators watching a network game.
+    #[builder(default)]
+    pub spectators: u32,
+}
+
+/// Belief estimates where a player thinks the hidden enemy units are, from
+/// boards sampled consistently with what they have observed.
+#[derive(Clone, Debug)]
+pub struct Belief {
+    pub player: Player,
+    pub ply: u32,
+    pub samples: u32,
+    /// Probability of an enemy unit on each hidden square, indexed by
+    /// `[Unit::index][y][x]`. Visible squares are left at zero.
+    pub odds: [[[f32; 8]; 8]; 6],
+}
+
+impl Belief {
+    /// New starts a belief for the view's player with no boards sampled.
+    pub fn new(view: &PlayerView) -> Self {
+        Belief {
+            player: view.player,
+            ply: view.ply,
+            samples: 0,
+            odds: [[[0.0; 8]; 8]; 6],
+        }
+    }
+    /// Add counts the hidden enemy units on a board sampled from the view
+    /// towards the odds.
+    pub fn add(&mut self, view: &PlayerView, board: &Board) {
+        self.samples += 1;
+        let weight = 1.0 / self.samples as f32;
+        for Position { piece, x, y } in board.iter() {
+            for unit in UNITS.iter() {
+                let here = matches!(piece, Some(piece) if piece.player != view.player
+                    && piece.unit == *unit
+                    && !view.visible[y][x]);
+                let odds = &mut self.odds[unit.index()][y][x];
+                *odds += (if here { 1.0 } else { 0.0 } - *odds) * weight;
+            }
+        }
+    }
+}
+
+/// Memory records what a player last saw on each square, and when.
+#[derive(Clone, Default)]
+pub struct Memory {
+    /// The board as it was when each square was last seen.
+    pub board: Board,
+    /// The ply at which each square was last seen, indexed by `[y][x]`.
+    pub seen: [[Option<u32>; 8]; 8],
+}
+
+/// PlayerView is everything a single player is allowed to know about the
+/// game: their own pieces, the enemy pieces they can currently see, and what
+/// they have deduced so far.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct PlayerView {
+    pub player: Player,
+    pub turn: Player,
+    pub ply: u32,
+    /// The board with every square hidden from the player emptied.
+    pub board: Board,
+    /// Squares the player can currently see, indexed by `[y][x]`.
+    pub visible: [[bool; 8]; 8],
+    pub infoset: InfoSet,
+    pub notices: Vec<Notice>,
+}
+
+impl PlayerView {
+    /// Moves lists every move available to the player.
+    /// Everything that can block or enable a move is within sight, so the
+    /// filtered board yields the same moves as the full one.
+    pub fn moves(&self) -> Vec<Move> {
+        self.board.moves_for(&self.player)
+    }
+}
+
+/// Visibility tiers used when rendering the fog.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Sight {
+    /// Currently visible.
+    Clear,
+    /// Seen within the last `RECENT_PLIES` plies.
+    Recent,
+    /// Not seen recently, or never.
+    Fog,
+}
+
+/// View determines whose knowledge of the board is displayed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum View {
+    /// Always show what White can see.
+    White,
+    /// Always show what Black can see.
+    Black,
+    /// Show what the player whose turn it is can see.
+    Mover,
+    /// Show the whole board, without fog.
+    Omniscient,
+}
+
+/// Step records a single ply: who played it and the moves made. Every ply
+/// is a single move, except castling which moves both king and rook.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Step {
+    pub player: Player,
+    pub moves: Vec<Move>,
+}
+
+/// Report describes something a player observed as the result of a move.
+/// Reports only ever contain information the player could see.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum Report {
+    /// The player captured an enemy unit.
+    Captured { unit: Unit, at: (i32, i32) },
+    /// One of the player's units was captured, by a unit that may be hidden.
+    Lost {
+        unit: Unit,
+        at: (i32, i32),
+        by: Option<Unit>,
+    },
+    /// An enemy unit was seen moving. Either end may be hidden in fog.
+    Sighted {
+        unit: Unit,
+        from: Option<(i32, i32)>,
+        to: Option<(i32, i32)>,
+    },
+}
+
+/// Notice is a report addressed to a single player.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Notice {
+    pub ply: u32,
+    pub player: Player,
+    pub report: Report,
 }
 
 impl Game {
-    /// Moves calculates all valid moves for the currently selected piece.
+    /// Headless creates a game from the given board that is played without
+    /// a window, such as between bots.
+    pub fn headless(board: Board, no_fog: bool, seed: u64) -> Self {
+        Game::new(
+            StateBuilder::default()
+                .board(board)
+                .turn(Player::White)
+                .selected(HashSet::new())
+                .font(Font::default())
+                .view(View::Omniscient)
+                .single_player(false)
+                .debug_stats(false)
+                .no_fog(no_fog)
+                .seed(seed)
+                .build()
+                .expect("building headless game"),
+        )
+    }
+    /// Create a game from its initial state, with each player's starting
+    /// observations recorded.
+    pub fn new(mut state: State) -> Self {
+        state.rng = StdRng::seed_from_u64(state.seed);
+        let mut game = Game {
+            initial: state.clone(),
+            state,
+            opponent: None,
+            replay: None,
+            remote: None,
+            chat: Rc::default(),
+        };
+        game.remember();
+        for player in [Player::White, Player::Black].iter() {
+            game.state.infosets[player.index()] = InfoSet::new(*player, &game.state.board);
+        }
+        game.initial = game.state.clone();
+        game
+    }
+    /// Moves calculates all valid moves for the piece at the given position,
+    /// from the perspective of the player that owns it.
     pub fn moves(&self, pos: (i32, i32)) -> Vec<(i32, i32)> {
-        let (x, y) = pos;
-        use Unit::*;
-        match self.state.board.get((x, y)) {
-            Some(Piece {
-                unit,
-                player,
-                moved,
-            }) => match unit {
-                // Pawn can move in the direction of the player by 1 square.
-                // For the first move, a pawn can move up to 2 squares.
-                // Pawns can only attack diagonally in the direction of the
-                // player.
-                // Cannot attack straight ahead.
-                Pawn => {
-                    let mut moves = vec![];
-                    match player {
-                        // Clean: The only difference between these two
-                        // blocks is the direction.
-                        Player::White => {
-                            if self.contains_enemy((x - 1, y + 1)) {
-                                moves.push((x - 1, y + 1));
-                            }
-                            if self.contains_enemy((x + 1, y + 1)) {
-                                moves.push((x + 1, y + 1));
-                            }
-                            if self.state.board.0[y as usize + 1][x as usize].is_none() {
-                                moves.push((x, y + 1));
-                                if *moved == 0
-                                    && self.state.board.0[y as usize + 2][x as usize].is_none()
-                                {
-                                    moves.push((x, y + 2));
-                                }
-                            }
-                        }
-                        Player::Black => {
-                            if self.contains_enemy((x - 1, y - 1)) {
-                                moves.push((x - 1, y - 1));
-                            }
-                            if self.contains_enemy((x + 1, y - 1)) {
-                                moves.push((x + 1, y - 1));
-                            }
-                            if self.state.board.0[y as usize - 1][x as usize].is_none() {
-                                moves.push((x, y - 1));
-                                if *moved == 0
-                                    && self.state.board.0[y as usize - 2][x as usize].is_none()
-                                {
-                                    moves.push((x, y - 2));
-                                }
-                            }
-                        }
-                    };
-                    moves
+        self.state.board.moves(pos)
+    }
+    // Calculate line of sight for any piece at the given coordinate.
+    pub fn line_of_sight(&self, pos: (i32, i32)) -> Vec<(i32, i32)> {
+        self.state.board.line_of_sight(pos)
+    }
+    /// Visibility calculates which squares the given player can currently see,
+    /// indexed by `[y][x]`.
+    pub fn visibility(&self, player: &Player) -> [[bool; 8]; 8] {
+        self.state.board.visibility(player)
+    }
+    /// Coverage counts how many of the given player's pieces can see each
+    /// square, indexed by `[y][x]`.
+    pub fn coverage(&self, player: &Player) -> [[u32; 8]; 8] {
+        self.state.board.coverage(player)
+    }
+    /// Viewer is the player whose view of the board is displayed, or `None`
+    /// when the whole board is displayed.
+    pub fn viewer(&self) -> Option<Player> {
+        if self.state.no_fog {
+            return None;
+        }
+        if let (true, Some(opponent)) = (self.view_locked(), &self.opponent) {
+            return Some(opponent.player.opponent());
+        }
+        match self.state.view {
+            View::White => Some(Player::White),
+            View::Black => Some(Player::Black),
+            View::Mover => Some(self.state.turn),
+            View::Omniscient => None,
+        }
+    }
+    /// View locked is whether only the human's view may be shown, as while
+    /// a game against a bot is in play, whose pieces any other view would
+    /// give away.
+    pub fn view_locked(&self) -> bool {
+        self.opponent.is_some() && self.winner().is_none() && self.replay.is_none()
+    }
+    /// View for builds the given player's fog-filtered view of the game.
+    pub fn view_for(&self, player: &Player) -> PlayerView {
+        let visible = if self.state.no_fog {
+            [[true; 8]; 8]
+        } else {
+            self.visibility(player)
+        };
+        let mut board = Board::default();
+        for Position { x, y, piece } in self.state.board.iter() {
+            if let Some(piece) = piece {
+                if piece.player == *player || visible[y][x] {
+                    board.set((x as i32, y as i32), piece.clone());
                 }
-                // Knight moves in an L shape: two out, one across.
-                Knight => vec![
-                    (x + 2, y - 1),
-                    (x + 2, y + 1),
-                    (x - 2, y - 1),
-                    (x - 2, y + 1),
-                    (x + 1, y + 2),
-                    (x - 1, y + 2),
-                    (x + 1, y - 2),
-                    (x - 1, y - 2),
-                ],
-                // Rook moves in all non diagonal directions.
-                Rook => vec![]
-                    .into_iter()
-                    .chain(LineOfSight::new(
-                        (1..8).map(|ii| (x + ii, y)),
-                        &self.state.board,
-                    ))
-                    .chain(LineOfSight::new(
-                        (1..8).map(|ii| (x - ii, y)),
-                        &self.state.board,
-                    ))
-                    .chain(LineOfSight::new(
-                        (1..8).map(|ii| (x, y + ii)),
-                        &self.state.board,
-                    ))
-                    .chain(LineOfSight::new(
-                        (1..8).map(|ii| (x, y - ii)),
-                        &self.state.board,
-                    ))
-                    .collect(),
-                // Bishop moves all diagonal directions.
-                Bishop => vec![]
-                    .into_iter()
-                    .chain(LineOfSight::new(
-                        (1..8).map(|ii| (x + ii, y + ii)),
-                        &self.state.board,
-                    ))
-                    .chain(LineOfSight::new(
-                        (1..8).map(|ii| (x - ii, y - ii)),
-                        &self.state.board,
-                    ))
-                    .chain(LineOfSight::new(
-                        (1..8).map(|ii| (x - ii, y + ii)),
-                        &self.state.board,
-                    ))
-                    .chain(LineOfSight::new(
-                        (1..8).map(|ii| (x + ii, y - ii)),
-                        &self.state.board,
-                    ))
-                    .collect(),
-                // Queen moves in all eight directions.
-                Queen => vec![]
-                    .into_iter()
-                    .chain(LineOfSight::new(
-                        (1..8).map(|ii| (x + ii, y)),
-                        &self.state.board,
-                    ))
-                    .chain(LineOfSight::new(
-                        (1..8).map(|ii| (x - ii, y)),
-                        &self.state.board,
-                    ))
-                    .chain(LineOfSight::new(
-                        (1..8).map(|ii| (x, y + ii)),
-                        &self.state.board,
-                    ))
-                    .chain(LineOfSight::new(
-                        (1..8).map(|ii| (x, y - ii)),
-                        &self.state.board,
-                    ))
-                    .chain(LineOfSight::new(
-                        (1..8).map(|ii| (x + ii, y + ii)),
-                        &self.state.board,
-                    ))
-                    .chain(LineOfSight::new(
-                        (1..8).map(|ii| (x - ii, y - ii)),
-                        &self.state.board,
-                    ))
-                    .chain(LineOfSight::new(
-                        (1..8).map(|ii| (x - ii, y + ii)),
-                        &self.state.board,
-                    ))
-                    .chain(LineOfSight::new(
-                        (1..8).map(|ii| (x + ii, y - ii)),
-                        &self.state.board,
-                    ))
-                    .collect(),
-                // King can move to any adjacent cell that isn't occupied by
-                // a piece of the same player.
-                King => vec![
-                    (x + 1, y + 1),
-                    (x - 1, y - 1),
-                    (x + 1, y - 1),
-                    (x - 1, y + 1),
-                    (x + 1, y),
-                    (x - 1, y),
-                    (x, y + 1),
-                    (x, y - 1),
-                ],
-            },
-            None => vec![],
+            }
+        }
+        PlayerView {
+            player: *player,
+            turn: self.state.turn,
+            ply: self.state.ply,
+            board,
+            visible,
+            infoset: self.infoset(player).clone(),
+            notices: self.notices(player).cloned().collect(),
         }
-        .into_iter()
-        .filter(|(x, y)| !self.contains_ally((*x, *y)))
-        .collect()
     }
-    // Calculate line of sight for any piece at the given coordinate.
-    pub fn line_of_sight(&self, pos: (i32, i32)) -> Vec<(i32, i32)> {
-        let (x, y) = pos;
-        self.moves(pos)
-            .into_iter()
-            .chain(
-                vec![
-                    (x + 1, y + 1),
-                    (x - 1, y - 1),
-                    (x + 1, y - 1),
-                    (x - 1, y + 1),
-                    (x + 1, y),
-                    (x - 1, y),
-                    (x, y + 1),
-                    (x, y - 1),
-                ]
-                .into_iter(),
-            )
-            .collect()
+    /// Winner is the player who has captured the opposing king, if any.
+    /// In a network game the enemy king may be hidden, so only the server
+    /// can say.
+    pub fn winner(&self) -> Option<Player> {
+        if self.remote.is_some() {
+            return match self.state.outcome {
+                Some(arena::Outcome::Win(winner)) => Some(winner),
+                _ => None,
+            };
+        }
+        self.state.board.winner()
+    }
+    /// Show replaces the mirrored game with a view sent by the server.
+    pub fn show(&mut self, view: PlayerView) {
+        self.state.board = view.board;
+        self.state.turn = view.turn;
+        self.state.ply = view.ply;
+        self.state.notices = view.notices;
+        self.state.infosets[view.player.index()] = view.infoset;
+        self.state.selected.clear();
+        self.state.hint = None;
+        self.state.feedback = None;
+        self.remember();
+    }
+    /// Legal moves lists every move available to the player whose turn it
+    /// is.
+    pub fn legal_moves(&self) -> Vec<Move> {
+        self.state.board.moves_for(&self.state.turn)
+    }
+    /// Make move plays the move for the current player if it is legal,
+    /// reporting whether it was played.
+    pub fn make_move(&mut self, mv: Move) -> bool {
+        let (from, to) = mv;
+        if self.contains_ally(from) && self.moves(from).contains(&to) {
+            self.move_turn(from, to);
+            true
+        } else {
+            false
+        }
+    }
+    /// Infoset returns where the given player believes enemy units could be.
+    pub fn infoset(&self, player: &Player) -> &InfoSet {
+        &self.state.infosets[player.index()]
+    }
+    /// Memory returns what the given player last saw of the board.
+    pub fn memory(&self, player: &Player) -> &Memory {
+        &self.state.memories[player.index()]
+    }
+    /// Sight classifies each square for the given player, indexed by `[y][x]`.
+    pub fn sight(&self, player: &Player) -> [[Sight; 8]; 8] {
+        let mut tiers = [[Sight::Fog; 8]; 8];
+        let visible = self.visibility(player);
+        let memory = self.memory(player);
+        for (y, row) in tiers.iter_mut().enumerate() {
+            for (x, tier) in row.iter_mut().enumerate() {
+                *tier = if visible[y][x] {
+                    Sight::Clear
+                } else {
+                    match memory.seen[y][x] {
+                        Some(ply) if self.state.ply - ply <= RECENT_PLIES => Sight::Recent,
+                        _ => Sight::Fog,
+                    }
+                };
+            }
+        }
+        tiers
+    }
+    /// Remember records the currently visible squares into each player's
+    /// memory.
+    fn remember(&mut self) {
+        for player in [Player::White, Player::Black].iter() {
+            let visible = self.visibility(player);
+            let memory = &mut self.state.memories[player.index()];
+            for Position { x, y, piece } in self.state.board.iter() {
+                if visible[y][x] {
+                    memory.seen[y][x] = Some(self.state.ply);
+                    memory.board.0[y][x] = piece.cloned();
+                }
+            }
+        }
+    }
+    /// Notices yields every report addressed to the given player, oldest first.
+    pub fn notices<'a>(&'a self, player: &'a Player) -> impl Iterator<Item = &'a Notice> + 'a {
+        self.state
+            .notices
+            .iter()
+            .filter(move |notice| notice.player == *player)
     }
     /// Move a piece and conclude the turn.
     pub fn move_turn(&mut self, from: (i32, i32), to: (i32, i32)) {
+        if let Some(remote) = &self.remote {
+            if let Err(err) = remote.play(vec![(from, to)]) {
+                eprintln!("sending move: {}", err);
+            }
+            self.state.selected.clear();
+            return;
+        }
         if self.contains_ally(from) {
-            self.state.board.move_piece((from.0, from.1), (to.0, to.1));
-            if !self.state.single_player {
-                self.state.turn = match self.state.turn {
-                    Player::Black => Player::White,
-                    Player::White => Player::Black,
+            self.play(&[(from, to)]);
+            self.end_turn();
+        }
+    }
+    // Hand the turn to the other player and drop the selection.
+    fn end_turn(&mut self) {
+        if !self.state.single_player {
+            self.state.turn = self.state.turn.opponent();
+        }
+        self.state.selected.clear();
+        self.state.hint = None;
+        self.state.feedback = None;
+    }
+    /// Refresh belief brings the overlay's belief up to date: against a bot,
+    /// whatever the bot itself believed when it last moved, and otherwise
+    /// the belief of the player to move.
+    pub fn refresh_belief(&mut self) {
+        if let Some(opponent) = &self.opponent {
+            self.state.belief = opponent.bot.borrow().belief();
+            return;
+        }
+        let (player, ply) = (self.state.turn, self.state.ply);
+        if !matches!(&self.state.belief, Some(b) if b.player == player && b.ply == ply) {
+            self.state.belief = Some(self.belief(&player, 200));
+        }
+    }
+    /// Belief estimates where the player thinks hidden enemy units are by
+    /// sampling boards consistent with their view, the same way the ISMCTS
+    /// bot does.
+    pub fn belief(&self, player: &Player, samples: u32) -> Belief {
+        let view = self.view_for(player);
+        // Seeded from the game's seed and ply rather than drawn from `rng`, so
+        // looking doesn't change how the game replays.
+        let mut rng = StdRng::seed_from_u64(arena::mix(self.state.seed, u64::from(self.state.ply)));
+        let mut belief = Belief::new(&view);
+        for _ in 0..samples {
+            belief.add(&view, &bot::determinize(&view, &mut rng));
+        }
+        belief
+    }
+    /// Hint asks a bot for a move suggestion from the current player's view,
+    /// or says why there is none. Each new suggestion counts against the
+    /// player's hint limit, and none are given for a side played by a bot.
+    pub fn hint(&mut self) -> Result<Move, &'static str> {
+        let player = self.state.turn;
+        if let Some(hint) = self.state.hint {
+            return Ok(hint);
+        }
+        if self.winner().is_some() {
+            return Err("the game is over");
+        }
+        if matches!(&self.opponent, Some(opponent) if opponent.player == player) {
+            return Err("the bot is to move");
+        }
+        if self.state.hints_used[player.index()] >= self.state.hint_limit {
+            return Err("none left");
+        }
+        let config = bot::IsmctsConfig {
+            iterations: Some(1_000),
+            time: Some(std::time::Duration::from_millis(500)),
+            ..bot::IsmctsConfig::default()
+        };
+        // Seeded from the game's seed and ply rather than drawn from `rng`, so
+        // asking for hints doesn't change how the game replays.
+        let seed = arena::mix(self.state.seed, u64::from(self.state.ply));
+        let mv = bot::Ismcts::seeded(config, seed)
+            .choose(&self.view_for(&player))
+            .ok()
+            .flatten()
+            .ok_or("nothing to play")?;
+        self.state.hints_used[player.index()] += 1;
+        self.state.hint = Some(mv);
+        Ok(mv)
+    }
+    /// Play applies the given moves to the board as a single ply, then reports
+    /// to each player what they could observe of it.
+    fn play(&mut self, moves: &[Move]) {
+        let players = [Player::White, Player::Black];
+        let before: Vec<_> = players.iter().map(|p| self.visibility(p)).collect();
+        let board = self.state.board.clone();
+        if let Some(piece) = moves.first().and_then(|(from, _)| board.get(*from)) {
+            self.state.history.push(Step {
+                player: piece.player,
+                moves: moves.to_vec(),
+            });
+        }
+        let mut played = vec![];
+        for (from, to) in moves.iter().cloned() {
+            if let Some(piece) = self.state.board.get(from).cloned() {
+                let captured = self.state.board.get(to).cloned();
+                self.state.board.move_piece(from, to);
+                played.push((piece, from, to, captured));
+            }
+        }
+        let after: Vec<_> = players.iter().map(|p| self.visibility(p)).collect();
+        let seen = |mask: &[[bool; 8]; 8], (x, y): (i32, i32)| {
+            in_bounds((x, y)) && mask[y as usize][x as usize]
+        };
+        for (ii, player) in players.iter().enumerate() {
+            for (piece, from, to, captured) in played.iter() {
+                let report = if piece.player == *player {
+                    match captured {
+                        Some(captured) => Report::Captured {
+                            unit: captured.unit,
+                            at: *to,
+                        },
+                        None => continue,
+                    }
+                } else if let Some(captured) = captured {
+                    Report::Lost {
+                        unit: captured.unit,
+                        at: *to,
+                        by: if seen(&after[ii], *to) {
+                            Some(piece.unit)
+                        } else {
+                            None
+                        },
+                    }
+                } else {
+                    let from = Some(*from).filter(|pos| seen(&before[ii], *pos));
+                    let to = Some(*to).filter(|pos| seen(&after[ii], *pos));
+                    if from.is_none() && to.is_none() {
+                        continue;
+                    }
+                    Report::Sighted {
+                        unit: piece.unit,
+                        from,
+                        to,
+                    }
                 };
+                self.state.notices.push(Notice {
+                    ply: self.state.ply,
+                    player: *player,
+                    report,
+                });
             }
-            self.state.selected.clear();
         }
+        for player in players.iter() {
+            let opponent_moved = played.iter().any(|(piece, ..)| piece.player != *player);
+            let captured: Vec<Unit> = played
+                .iter()
+                .filter(|(piece, ..)| piece.player == *player)
+                .filter_map(|(.., captured)| captured.as_ref().map(|c| c.unit))
+                .collect();
+            self.state.infosets[player.index()].observe(
+                &board,
+                &self.state.board,
+                opponent_moved,
+                &captured,
+            );
+        }
+        self.state.ply += 1;
+        self.remember();
     }
     /// Attack move one piece onto another.
     pub fn attack_move(&mut self, from: (i32, i32), to: (i32, i32)) {
@@ -452,46 +1746,221 @@ impl Game {
         }
     }
     /// Perform castle move if valid.
-    /// Castle move where King and Rook crossover into the 2 spaces between them.
+    /// Castle move where the King steps two squares towards the Rook, and the
+    /// Rook lands on the square the King passed over.
     /// Only valid if:
     /// - Pieces are the same player (duh).
     /// - Neither piece has been moved.
-    /// - Nothing is in the two spaces between them.
+    /// - Nothing is in any of the spaces between them.
     fn castle_move(&mut self) {
-        let moves = self
+        let pieces: Vec<(i32, i32)> = self.state.selected.iter().cloned().collect();
+        if self.castle(&pieces) {
+            self.state.selected.clear();
+        }
+    }
+    /// Castle the current player's king and rook at the given positions,
+    /// in either order, concluding the turn. Reports whether the castle was
+    /// valid.
+    pub fn castle(&mut self, pieces: &[(i32, i32)]) -> bool {
+        if pieces.len() != 2 || !pieces.iter().all(|pos| self.contains_ally(*pos)) {
+            return false;
+        }
+        let moves = match self
             .state
-            .selected
-            .iter()
-            .take(2)
-            .filter_map(|pos| match self.state.board.get(*pos).cloned() {
-                Some(piece) => Some((pos, piece)),
-                None => None,
-            })
-            .filter_map(|(pos, piece)| {
-                // Direction is derived from standard chess layout,
-                // where Rook is 3 positions to the left of the King.
-                let projected_move = match piece {
-                    Piece {
-                        unit: Unit::Rook, ..
-                    } => (pos.0 + 2, pos.1),
-                    Piece {
-                        unit: Unit::King, ..
-                    } => (pos.0 - 2, pos.1),
-                    _ => return None,
+            .board
+            .castle(pieces[0], pieces[1])
+            .or_else(|| self.state.board.castle(pieces[1], pieces[0]))
+        {
+            Some(moves) => moves.to_vec(),
+            None => return false,
+        };
+        match &self.remote {
+            Some(remote) => {
+                if let Err(err) = remote.play(moves) {
+                    eprintln!("sending move: {}", err);
+                }
+                self.state.selected.clear();
+            }
+            None => {
+                self.play(&moves);
+                self.end_turn();
+            }
+        }
+        true
+    }
+    /// Replay creates a game from its initial state and plays the given
+    /// steps in order. A game replayed from the same state, seed included,
+    /// and steps always ends up in the same state.
+    pub fn replay(initial: State, steps: &[Step]) -> Result<Self, String> {
+        let mut game = Game::new(initial);
+        for (ii, step) in steps.iter().enumerate() {
+            if !game.apply(step) {
+                return Err(format!("ply {} is not a legal move", ii + 1));
+            }
+        }
+        Ok(game)
+    }
+    /// Apply plays a recorded step for the current player, reporting whether
+    /// it was legal.
+    pub fn apply(&mut self, step: &Step) -> bool {
+        match step.moves.as_slice() {
+            [mv] => self.make_move(*mv),
+            [(king, _), (rook, _)] => {
+                // Every square of a castle follows from where the king and rook
+                // start, so the claimed destinations must match exactly.
+                match self.state.board.castle(*king, *rook) {
+                    Some(moves) if moves[..] == step.moves[..] => self.castle(&[*king, *rook]),
+                    _ => false,
+                }
+            }
+            _ => false,
+        }
+    }
+    /// Play pgn plays every move of an imported game, in order.
+    pub fn play_pgn(&mut self, import: &pgn::Import) -> Result<(), String> {
+        for (ii, san) in import.moves.iter().enumerate() {
+            if !self.play_san(san) {
+                return Err(format!("ply {} ({}) is not a legal move", ii + 1, san));
+            }
+        }
+        Ok(())
+    }
+    /// Analyse grades every ply played so far, sampling fog views from the
+    /// game's seed.
+    pub fn analyse(&self) -> Result<Vec<analysis::Annotation>, String> {
+        let config = analysis::AnalysisConfig {
+            seed: self.state.seed,
+            ..analysis::AnalysisConfig::default()
+        };
+        analysis::analyse(self, &config)
+    }
+    /// Send chat sends the message typed, or in a local game posts it in the
+    /// name of the player at the keyboard.
+    pub fn send_chat(&mut self) {
+        let text = match self.chat.borrow_mut().take() {
+            Some(text) => text,
+            None => return,
+        };
+        match &self.remote {
+            Some(remote) => {
+                if let Err(err) = remote.chat(&text) {
+                    eprintln!("sending chat: {}", err);
+                }
+            }
+            None => {
+                let player = match &self.opponent {
+                    Some(opponent) => opponent.player.opponent(),
+                    None => self.state.turn,
                 };
-                if piece.moved > 0 || self.state.board.get(projected_move).is_some() {
-                    None
-                } else {
-                    Some((*pos, projected_move))
+                self.chat.borrow_mut().push(&format!("{:?}", player), &text);
+            }
+        }
+    }
+    /// Start replay switches to stepping through the game played so far,
+    /// from its first ply.
+    pub fn start_replay(&mut self, comments: Vec<Option<String>>) {
+        self.replay = Some(Replay {
+            steps: self.state.history.clone(),
+            comments,
+            cursor: self.state.history.len(),
+        });
+        self.seek(0);
+    }
+    /// Seek shows the replayed game after the given number of plies.
+    pub fn seek(&mut self, cursor: usize) {
+        let mut replay = match self.replay.take() {
+            Some(replay) => replay,
+            None => return,
+        };
+        let cursor = cursor.min(replay.steps.len());
+        if let Ok(game) = Game::replay(self.initial.clone(), &replay.steps[..cursor]) {
+            // Keep the display settings chosen while replaying.
+            self.state = State {
+                view: self.state.view,
+                heatmap: self.state.heatmap,
+                threats: self.state.threats,
+                debug_stats: self.state.debug_stats,
+                ..game.state
+            };
+            replay.cursor = cursor;
+        }
+        self.replay = Some(replay);
+    }
+    /// Play san plays the move named in standard algebraic notation for the
+    /// current player, reporting whether it was found and played.
+    pub fn play_san(&mut self, san: &str) -> bool {
+        let san = san.trim_end_matches(['+', '#', '!', '?']);
+        let board = self.state.board.clone();
+        let player = self.state.turn;
+        for mv in self.legal_moves() {
+            let step = Step {
+                player,
+                moves: vec![mv],
+            };
+            if pgn::san(&board, &step) == san {
+                return self.make_move(mv);
+            }
+        }
+        // Castling is not among the legal moves, so try every king and rook
+        // pair on a copy of the game.
+        let units = |unit: Unit| -> Vec<(i32, i32)> {
+            board
+                .iter()
+                .filter(|Position { piece, .. }| {
+                    matches!(piece, Some(p) if p.unit == unit && p.player == player)
+                })
+                .map(|Position { x, y, .. }| (x as i32, y as i32))
+                .collect()
+        };
+        for king in units(Unit::King) {
+            for rook in units(Unit::Rook) {
+                let mut trial = self.clone();
+                if trial.castle(&[king, rook])
+                    && matches!(trial.state.history.last(), Some(step) if pgn::san(&board, step) == san)
+                {
+                    *self = trial;
+                    return true;
                 }
-            })
-            .collect::<Vec<((i32, i32), (i32, i32))>>();
-        if moves.len() == 2 {
-            for (from, to) in moves {
-                self.state.board.move_piece(from, to);
             }
-            self.state.selected.clear();
         }
+        false
+    }
+    /// Save writes the game so far as PGN, tagged with its seed, and returns
+    /// the path written.
+    pub fn save(&self) -> std::io::Result<String> {
+        let path = format!("fog-of-chess-{}-{}.pgn", self.state.seed, self.state.ply);
+        let result = match self.winner() {
+            Some(Player::White) => "1-0",
+            Some(Player::Black) => "0-1",
+            None => "*",
+        };
+        let name = |player: Player| match &self.opponent {
+            Some(opponent) if opponent.player == player => opponent.bot.borrow().name(),
+            _ => "?".to_string(),
+        };
+        let tags = [
+            ("Event", "Fog of Chess game".to_string()),
+            ("Site", "?".to_string()),
+            ("Date", "????.??.??".to_string()),
+            ("Round", "-".to_string()),
+            ("White", name(Player::White)),
+            ("Black", name(Player::Black)),
+            ("Result", result.to_string()),
+            (
+                "Variant",
+                if self.state.no_fog {
+                    "Standard"
+                } else {
+                    "Fog of War"
+                }
+                .to_string(),
+            ),
+            ("Seed", self.state.seed.to_string()),
+            ("PlyCount", self.state.ply.to_string()),
+        ];
+        let text = pgn::export(&tags, &self.initial.board, &self.state.history, &[], result);
+        std::fs::write(&path, text)?;
+        Ok(path)
     }
     /// Draw the board which the pieces are placed onto.
     fn draw_board(&self, ctx: &mut Context) -> GameResult<()> {
@@ -500,9 +1969,7 @@ impl Game {
         for Position { x, y, .. } in self.state.board.iter() {
             let (x, y) = (x as i32, y as i32);
             // TODO: get color from color map.
-            let color = if x % 2 == 0 && y % 2 == 0 {
-                SOARING_EAGLE
-            } else if x % 2 != 0 && y % 2 != 0 {
+            let color = if x % 2 == y % 2 {
                 SOARING_EAGLE
             } else {
                 WIZARD_GREY
@@ -512,57 +1979,84 @@ impl Game {
                 graphics::DrawMode::fill(),
                 graphics::Rect::new(x * w, y * h, w, h),
                 color,
-            );
+            )?;
         }
-        let mut mesh = mb.build(ctx)?;
-        graphics::draw(ctx, &mut mesh, DrawParam::default())
+        let mesh = mb.build(ctx)?;
+        graphics::draw(ctx, &mesh, DrawParam::default())
     }
     // Draw the chess pieces onto the baord.
-    fn draw_pieces(&self, ctx: &mut Context) -> GameResult<()> {
-        let (w, h) = self.cell_size(ctx);
-        let size = w.min(h);
+    // Given a viewer, only pieces they can currently see are drawn, along with
+    // faded ghosts of enemy pieces they saw recently.
+    fn draw_pieces(&self, ctx: &mut Context, viewer: Option<&Player>) -> GameResult<()> {
+        let sight = viewer.map(|viewer| self.sight(viewer));
         for Position { x, y, piece } in self.state.board.iter() {
-            if let Some(Piece { player, unit, .. }) = piece {
-                // Chess pieces are part of unicode.
-                // All we need is a font that provides these.
-                let text = match unit {
-                    Unit::Pawn => '\u{265F}',
-                    Unit::King => '\u{265A}',
-                    Unit::Queen => '\u{265B}',
-                    Unit::Bishop => '\u{265D}',
-                    Unit::Knight => '\u{265E}',
-                    Unit::Rook => '\u{265C}',
-                };
-                let color = match player {
-                    Player::White => graphics::Color::WHITE,
-                    Player::Black => graphics::Color::BLACK,
-                };
-                // In order to center the pieces there are a few tricks to do.
-                // First, scale the text by the larger side to "fill out" the space.
-                // Then queue and draw the text immediately, centering the text horizontally.
-                // The fixed offset of -2.0 is required to counteract 1px borders (I think!).
-                // The text must be drawn individually so that we can scale each fragment individually.
-                let fragment: graphics::TextFragment = (text, self.state.font, size).into();
-                graphics::queue_text(ctx, &Text::new(fragment), [0.0, 0.0], Some(color));
-                let scale = if h > w {
-                    [1.0, h / w]
-                } else if w > h {
-                    [w / h, 1.0]
-                } else {
-                    [1.0, 1.0]
-                };
-                graphics::draw_queued_text(
-                    ctx,
-                    DrawParam::default()
-                        .dest([x as f32 * w + (w / 4.0 - 2.0), y as f32 * h])
-                        .scale(scale),
-                    None,
-                    graphics::FilterMode::Linear,
-                )?;
+            let tier = sight.map_or(Sight::Clear, |sight| sight[y][x]);
+            if let (Some(piece), Sight::Clear) = (piece, tier) {
+                self.draw_piece(ctx, (x, y), piece, 1.0)?;
+            }
+        }
+        if let (Some(viewer), Some(sight)) = (viewer, sight) {
+            let memory = self.memory(viewer);
+            for Position { x, y, piece } in memory.board.iter() {
+                if let (Some(piece), Sight::Recent) = (piece, sight[y][x]) {
+                    if piece.player != *viewer {
+                        self.draw_piece(ctx, (x, y), piece, 0.35)?;
+                    }
+                }
             }
         }
         Ok(())
     }
+    // Draw a single piece at the given cell, faded by alpha.
+    fn draw_piece(
+        &self,
+        ctx: &mut Context,
+        pos: (usize, usize),
+        piece: &Piece,
+        alpha: f32,
+    ) -> GameResult<()> {
+        let (x, y) = pos;
+        let (w, h) = self.cell_size(ctx);
+        let size = w.min(h);
+        let Piece { player, unit, .. } = piece;
+        // Chess pieces are part of unicode.
+        // All we need is a font that provides these.
+        let text = match unit {
+            Unit::Pawn => '\u{265F}',
+            Unit::King => '\u{265A}',
+            Unit::Queen => '\u{265B}',
+            Unit::Bishop => '\u{265D}',
+            Unit::Knight => '\u{265E}',
+            Unit::Rook => '\u{265C}',
+        };
+        let mut color = match player {
+            Player::White => graphics::Color::WHITE,
+            Player::Black => graphics::Color::BLACK,
+        };
+        color.a = alpha;
+        // In order to center the pieces there are a few tricks to do.
+        // First, scale the text by the larger side to "fill out" the space.
+        // Then queue and draw the text immediately, centering the text horizontally.
+        // The fixed offset of -2.0 is required to counteract 1px borders (I think!).
+        // The text must be drawn individually so that we can scale each fragment individually.
+        let fragment: graphics::TextFragment = (text, self.state.font, size).into();
+        graphics::queue_text(ctx, &Text::new(fragment), [0.0, 0.0], Some(color));
+        let scale = if h > w {
+            [1.0, h / w]
+        } else if w > h {
+            [w / h, 1.0]
+        } else {
+            [1.0, 1.0]
+        };
+        graphics::draw_queued_text(
+            ctx,
+            DrawParam::default()
+                .dest([x as f32 * w + (w / 4.0 - 2.0), y as f32 * h])
+                .scale(scale),
+            None,
+            graphics::FilterMode::Linear,
+        )
+    }
     // Draw highlights for selected pieces.
     fn draw_highlights(&self, ctx: &mut Context) -> GameResult<()> {
         let mut mb = MeshBuilder::new();
@@ -573,104 +2067,937 @@ impl Game {
                 DrawMode::stroke(2.0),
                 Rect::new(x * w, y * h, w, h),
                 PURE_APPLE,
+            )?;
+        }
+        if let Some((from, to)) = self.state.hint {
+            for (x, y) in [from, to].iter() {
+                let (x, y) = (*x as f32, *y as f32);
+                mb.rectangle(
+                    DrawMode::stroke(4.0),
+                    Rect::new(x * w + 2.0, y * h + 2.0, w - 4.0, h - 4.0),
+                    TURBO,
+                )?;
+            }
+        }
+        if let Ok(mesh) = mb.build(ctx) {
+            graphics::draw(ctx, &mesh, DrawParam::default())?;
+        }
+        Ok(())
+    }
+    // Draw the fog over war, as seen by the viewer.
+    // Fogged squares are heavily tinted and recently seen squares are dimmed,
+    // so the checkerboard still shows through both. Clear squares are
+    // feathered along any edge they share with a darker square.
+    fn draw_fog(&self, ctx: &mut Context, viewer: &Player) -> GameResult<()> {
+        let sight = self.sight(viewer);
+        let density = |tier: Sight| match tier {
+            Sight::Clear => 0.0,
+            Sight::Recent => 0.55,
+            Sight::Fog => 0.85,
+        };
+        let mut mb = MeshBuilder::new();
+        let (w, h) = self.cell_size(ctx);
+        let feather = w.min(h) / 6.0;
+        let steps = 4;
+        for (y, row) in sight.iter().enumerate() {
+            for (x, tier) in row.iter().enumerate() {
+                let alpha = density(*tier);
+                let (fx, fy) = (x as f32 * w, y as f32 * h);
+                if alpha > 0.0 {
+                    mb.rectangle(
+                        graphics::DrawMode::fill(),
+                        graphics::Rect::new(fx, fy, w, h),
+                        Color { a: alpha, ..FOG },
+                    )?;
+                }
+                // Bleed each neighbour's fog into this square with a short
+                // gradient of progressively fainter strips.
+                let neighbours = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+                for (dx, dy) in neighbours.iter() {
+                    let (nx, ny) = (x as i32 + dx, y as i32 + dy);
+                    if nx < 0 || ny < 0 || nx > 7 || ny > 7 {
+                        continue;
+                    }
+                    let bleed = density(sight[ny as usize][nx as usize]) - alpha;
+                    if bleed <= 0.0 {
+                        continue;
+                    }
+                    for step in 0..steps {
+                        let inset = feather * step as f32 / steps as f32;
+                        let width = feather / steps as f32;
+                        let rect = match (dx, dy) {
+                            (-1, _) => Rect::new(fx + inset, fy, width, h),
+                            (1, _) => Rect::new(fx + w - inset - width, fy, width, h),
+                            (_, -1) => Rect::new(fx, fy + inset, w, width),
+                            _ => Rect::new(fx, fy + h - inset - width, w, width),
+                        };
+                        let fade = 1.0 - (step as f32 + 0.5) / steps as f32;
+                        mb.rectangle(
+                            graphics::DrawMode::fill(),
+                            rect,
+                            Color {
+                                a: bleed * fade * 0.5,
+                                ..FOG
+                            },
+                        )?;
+                    }
+                }
+            }
+        }
+        if let Ok(mesh) = mb.build(ctx) {
+            graphics::draw(ctx, &mesh, DrawParam::default())?;
+        }
+        Ok(())
+    }
+    // Draw a heatmap over each square the player can see, shaded by how
+    // many of their pieces see it.
+    fn draw_heatmap(&self, ctx: &mut Context, player: &Player) -> GameResult<()> {
+        let coverage = self.coverage(player);
+        let mut mb = MeshBuilder::new();
+        let (w, h) = self.cell_size(ctx);
+        for (y, row) in coverage.iter().enumerate() {
+            for (x, count) in row.iter().enumerate() {
+                if *count == 0 {
+                    continue;
+                }
+                let (fx, fy) = (x as f32 * w, y as f32 * h);
+                mb.rectangle(
+                    DrawMode::fill(),
+                    Rect::new(fx, fy, w, h),
+                    Color {
+                        a: (0.15 * *count as f32).min(0.6),
+                        ..PURE_APPLE
+                    },
+                )?;
+                self.text(
+                    ctx,
+                    &count.to_string(),
+                    (fx + w - w / 6.0, fy + 2.0),
+                    (w.min(h) / 6.0).max(10.0),
+                    Some(graphics::Color::WHITE),
+                );
+            }
+        }
+        if let Ok(mesh) = mb.build(ctx) {
+            graphics::draw(ctx, &mesh, DrawParam::default())?;
+        }
+        Ok(())
+    }
+    // Draw which enemy pieces can see the player's pieces: the watched
+    // pieces are tinted and the watchers outlined.
+    fn draw_threats(&self, ctx: &mut Context, player: &Player) -> GameResult<()> {
+        let mut mb = MeshBuilder::new();
+        let (w, h) = self.cell_size(ctx);
+        let mut watched = HashSet::new();
+        for Position { x, y, piece } in self.state.board.iter() {
+            if let Some(piece) = piece {
+                if piece.player == *player {
+                    continue;
+                }
+                let sees: Vec<(i32, i32)> = self
+                    .line_of_sight((x as i32, y as i32))
+                    .into_iter()
+                    .filter(|pos| self.state.board.contains_ally_of(*player, *pos))
+                    .collect();
+                if !sees.is_empty() {
+                    mb.rectangle(
+                        DrawMode::stroke(3.0),
+                        Rect::new(x as f32 * w, y as f32 * h, w, h),
+                        CARMINE_PINK,
+                    )?;
+                    watched.extend(sees);
+                }
+            }
+        }
+        for (x, y) in watched {
+            mb.rectangle(
+                DrawMode::fill(),
+                Rect::new(x as f32 * w, y as f32 * h, w, h),
+                Color {
+                    a: 0.35,
+                    ..CARMINE_PINK
+                },
+            )?;
+        }
+        if let Ok(mesh) = mb.build(ctx) {
+            graphics::draw(ctx, &mesh, DrawParam::default())?;
+        }
+        Ok(())
+    }
+    // Queue file and rank labels along the board edges. Labels are drawn
+    // after the fog so they always remain readable.
+    fn draw_coordinates(&self, ctx: &mut Context) {
+        let (w, h) = self.cell_size(ctx);
+        let size = (w.min(h) / 6.0).max(10.0);
+        // Label in the colour of the opposite square so it contrasts.
+        let contrast = |x: i32, y: i32| {
+            if x % 2 == y % 2 {
+                WIZARD_GREY
+            } else {
+                SOARING_EAGLE
+            }
+        };
+        for ii in 0..8 {
+            // The bottom row carries the files, the left column the ranks.
+            let file = notation((ii, 7));
+            let rank = notation((0, ii));
+            self.text(
+                ctx,
+                &file[..1],
+                ((ii + 1) as f32 * w - size * 0.8, 8.0 * h - size * 1.2),
+                size,
+                Some(contrast(ii, 7)),
+            );
+            self.text(
+                ctx,
+                &rank[1..],
+                (3.0, ii as f32 * h + 2.0),
+                size,
+                Some(contrast(0, ii)),
+            );
+        }
+    }
+    // Draw the current view and the viewer's most recent notices in the
+    // bottom left.
+    fn draw_hud(&self, ctx: &mut Context) -> GameResult<()> {
+        let (text_size, padding, limit) = (16.0, 5.0, 5);
+        let (_, height) = graphics::drawable_size(ctx);
+        let notices: Vec<String> = match self.viewer() {
+            Some(viewer) => self.notices(&viewer).map(|n| n.to_string()).collect(),
+            None => self
+                .state
+                .notices
+                .iter()
+                .map(|n| format!("{:?}: {}", n.player, n))
+                .collect(),
+        };
+        let mut status = format!("view: {}", self.state.view);
+        if self.state.hint_limit > 0 {
+            let used = self.state.hints_used[self.state.turn.index()];
+            status.push_str(&format!(
+                "  hints left: {}",
+                self.state.hint_limit.saturating_sub(used)
+            ));
+        }
+        if let Some(clocks) = &self.state.clocks {
+            for player in [Player::White, Player::Black].iter() {
+                let left = clocks.left(*player).as_secs();
+                status.push_str(&format!("  {:?} {}:{:02}", player, left / 60, left % 60));
+            }
+        }
+        if self.remote.is_some() {
+            status.push_str(&format!("  spectators: {}", self.state.spectators));
+        }
+        if let Some(feedback) = &self.state.feedback {
+            status.push_str(&format!("  {}", feedback));
+        }
+        let mut comment = None;
+        if let Some(replay) = &self.replay {
+            status.push_str(&format!("  ply {}/{}", replay.cursor, replay.steps.len()));
+            if let Some(Some(text)) = replay.comments.get(replay.cursor.wrapping_sub(1)) {
+                comment = Some(format!("{}. {}", replay.cursor, text));
+            }
+        }
+        let lines: Vec<String> = vec![status]
+            .into_iter()
+            .chain(comment)
+            .chain(
+                notices[notices.len().saturating_sub(limit)..]
+                    .iter()
+                    .cloned(),
+            )
+            .collect();
+        let top = height - lines.len() as f32 * text_size - padding * 2.0;
+        let width = lines.iter().map(|line| line.len()).max().unwrap_or(0) as f32 * text_size * 0.6;
+        let backdrop = graphics::Mesh::new_rectangle(
+            ctx,
+            DrawMode::fill(),
+            Rect::new(0.0, top, width + padding * 4.0, height - top),
+            Color::new(0.0, 0.0, 0.0, 0.6),
+        )?;
+        graphics::draw(ctx, &backdrop, DrawParam::default())?;
+        for (ii, line) in lines.iter().enumerate() {
+            self.text(
+                ctx,
+                line,
+                (10.0, top + padding + ii as f32 * text_size),
+                text_size,
+                Some(graphics::Color::WHITE),
+            );
+        }
+        Ok(())
+    }
+    // Draw the chat scrollback and the line being typed in the top right.
+    fn draw_chat(&self, ctx: &mut Context) -> GameResult<()> {
+        let chat = self.chat.borrow();
+        if chat.lines.is_empty() && !chat.typing() {
+            return Ok(());
+        }
+        let (text_size, padding, rows) = (16.0, 5.0, 10);
+        let (width, _) = graphics::drawable_size(ctx);
+        let panel = (width * 0.4).max(200.0);
+        // Wrap long messages to the panel, using the same estimate of
+        // character width as the HUD.
+        let columns = ((panel - padding * 4.0) / (text_size * 0.6)).max(1.0) as usize;
+        let wrap = |line: String| -> Vec<String> {
+            let chars: Vec<char> = line.chars().collect();
+            chars
+                .chunks(columns)
+                .map(|chunk| chunk.iter().collect())
+                .collect()
+        };
+        let mut lines: Vec<String> = chat
+            .shown(rows)
+            .iter()
+            .flat_map(|line| wrap(line.to_string()))
+            .collect();
+        lines.drain(..lines.len().saturating_sub(rows));
+        if chat.scroll > 0 {
+            lines.push(format!("({} newer, PageDown)", chat.scroll));
+        }
+        if let Some(input) = &chat.input {
+            let typed = wrap(format!("> {}_", input));
+            lines.extend(typed.last().cloned());
+        }
+        let left = width - panel;
+        let backdrop = graphics::Mesh::new_rectangle(
+            ctx,
+            DrawMode::fill(),
+            Rect::new(
+                left,
+                0.0,
+                panel,
+                lines.len() as f32 * text_size + padding * 2.0,
+            ),
+            Color::new(0.0, 0.0, 0.0, 0.6),
+        )?;
+        graphics::draw(ctx, &backdrop, DrawParam::default())?;
+        for (ii, line) in lines.iter().enumerate() {
+            self.text(
+                ctx,
+                line,
+                (left + padding * 2.0, padding + ii as f32 * text_size),
+                text_size,
+                Some(graphics::Color::WHITE),
             );
         }
-        if let Ok(mut mesh) = mb.build(ctx) {
-            graphics::draw(ctx, &mut mesh, DrawParam::default())?;
+        Ok(())
+    }
+    // Draw the belief overlay: hidden squares tinted by how likely they are to
+    // hold an enemy unit of the filtered type, and a summary of the most
+    // likely squares for each type.
+    fn draw_belief(&self, ctx: &mut Context, filter: Option<Unit>) -> GameResult<()> {
+        let belief = match &self.state.belief {
+            Some(belief) => belief,
+            None => return Ok(()),
+        };
+        let units: Vec<Unit> = match filter {
+            Some(unit) => vec![unit],
+            None => UNITS.to_vec(),
+        };
+        let mut mb = MeshBuilder::new();
+        let (w, h) = self.cell_size(ctx);
+        for y in 0..8 {
+            for x in 0..8 {
+                let odds: f32 = units.iter().map(|u| belief.odds[u.index()][y][x]).sum();
+                if odds < 0.01 {
+                    continue;
+                }
+                let (fx, fy) = (x as f32 * w, y as f32 * h);
+                mb.rectangle(
+                    DrawMode::fill(),
+                    Rect::new(fx, fy, w, h),
+                    Color {
+                        a: odds.min(1.0) * 0.6,
+                        ..CARMINE_PINK
+                    },
+                )?;
+                self.text(
+                    ctx,
+                    &format!("{:.0}%", odds.min(1.0) * 100.0),
+                    (fx + 2.0, fy + h - (w.min(h) / 5.0).max(10.0) - 2.0),
+                    (w.min(h) / 5.0).max(10.0),
+                    Some(graphics::Color::WHITE),
+                );
+            }
+        }
+        if let Ok(mesh) = mb.build(ctx) {
+            graphics::draw(ctx, &mesh, DrawParam::default())?;
+        }
+        let (text_size, padding) = (16.0, 5.0);
+        let (width, _) = graphics::drawable_size(ctx);
+        let infoset = self.infoset(&belief.player);
+        let mut lines = vec![format!(
+            "{:?} believes ({} samples)",
+            belief.player, belief.samples
+        )];
+        for unit in units {
+            let mut squares: Vec<((usize, usize), f32)> = (0..64)
+                .map(|ii| ((ii % 8, ii / 8), belief.odds[unit.index()][ii / 8][ii % 8]))
+                .filter(|(_, odds)| *odds >= 0.01)
+                .collect();
+            squares.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+            let top: Vec<String> = squares
+                .iter()
+                .take(3)
+                .map(|((x, y), odds)| {
+                    format!("{} {:.0}%", notation((*x as i32, *y as i32)), odds * 100.0)
+                })
+                .collect();
+            lines.push(format!(
+                "{}: {} left, hidden at {}",
+                unit,
+                infoset.remaining(unit),
+                if top.is_empty() {
+                    "-".to_string()
+                } else {
+                    top.join(", ")
+                }
+            ));
+        }
+        let left =
+            width - lines.iter().map(|l| l.len()).max().unwrap_or(0) as f32 * text_size * 0.6;
+        for (ii, line) in lines.iter().enumerate() {
+            self.text(
+                ctx,
+                line,
+                (left - padding * 2.0, ii as f32 * text_size + padding),
+                text_size,
+                None,
+            );
+        }
+        Ok(())
+    }
+    // Draw meta information useful for debugging.
+    fn draw_debug_stats(&self, ctx: &mut Context) -> GameResult<()> {
+        let (text_size, padding) = (20.0, 5.0);
+        let (width, height) = graphics::size(ctx);
+        let (w, h) = self.cell_size(ctx);
+        let stats = [
+            format!("window: {} x {}", width, height),
+            format!("  cell: {} x {}", w, h),
+        ];
+        for (ii, stat) in stats.iter().enumerate() {
+            self.text(
+                ctx,
+                stat,
+                (10.0, ii as f32 * text_size + padding),
+                text_size,
+                None,
+            );
+        }
+        Ok(())
+    }
+    fn is_enemy(&self, player: &Player) -> bool {
+        self.state.turn != *player
+    }
+    // Calculate cell size based on window size (width, height).
+    fn cell_size(&self, ctx: &mut Context) -> (f32, f32) {
+        let (w, h) = graphics::drawable_size(ctx);
+        ((w / 8.0), (h / 8.0))
+    }
+    fn text(
+        &self,
+        ctx: &mut Context,
+        text: &str,
+        coord: (f32, f32),
+        scale: f32,
+        color: Option<Color>,
+    ) {
+        let fragment: graphics::TextFragment = (text, self.state.font, scale).into();
+        graphics::queue_text(
+            ctx,
+            &Text::new(fragment),
+            [coord.0, coord.1],
+            color.or(Some(graphics::Color::BLACK)),
+        );
+    }
+    // Translate pixel coordinates to grid cells.
+    fn pixels_to_grid(&self, ctx: &mut Context, coord: (f32, f32)) -> (i32, i32) {
+        let (x, y) = coord;
+        let (w, h) = self.cell_size(ctx);
+        ((x / w).floor() as i32, (y / h).floor() as i32)
+    }
+}
+
+impl Player {
+    /// Index of the player into per-player arrays.
+    pub fn index(&self) -> usize {
+        match self {
+            Player::White => 0,
+            Player::Black => 1,
+        }
+    }
+    /// Pawn rank is the rank on which the player's pawns start.
+    pub fn pawn_rank(&self) -> i32 {
+        match self {
+            Player::White => 1,
+            Player::Black => 6,
+        }
+    }
+    /// Opponent returns the other player.
+    pub fn opponent(&self) -> Player {
+        match self {
+            Player::White => Player::Black,
+            Player::Black => Player::White,
+        }
+    }
+}
+
+impl View {
+    /// Next cycles through the views in declaration order.
+    pub fn next(&self) -> View {
+        match self {
+            View::White => View::Black,
+            View::Black => View::Mover,
+            View::Mover => View::Omniscient,
+            View::Omniscient => View::White,
+        }
+    }
+}
+
+impl fmt::Display for View {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            View::White => "white",
+            View::Black => "black",
+            View::Mover => "mover",
+            View::Omniscient => "omniscient",
+        };
+        f.write_str(name)
+    }
+}
+
+impl Unit {
+    /// Index of the unit into per-unit arrays.
+    pub fn index(&self) -> usize {
+        match self {
+            Unit::Pawn => 0,
+            Unit::Rook => 1,
+            Unit::Knight => 2,
+            Unit::Bishop => 3,
+            Unit::Queen => 4,
+            Unit::King => 5,
+        }
+    }
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Unit::Pawn => "pawn",
+            Unit::Rook => "rook",
+            Unit::Knight => "knight",
+            Unit::Bishop => "bishop",
+            Unit::Queen => "queen",
+            Unit::King => "king",
+        };
+        f.write_str(name)
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Report::Captured { unit, at } => {
+                write!(f, "you captured a {} on {}", unit, notation(*at))
+            }
+            Report::Lost { unit, at, by } => {
+                write!(f, "your {} on {} was captured", unit, notation(*at))?;
+                match by {
+                    Some(by) => write!(f, " by a {}", by),
+                    None => Ok(()),
+                }
+            }
+            Report::Sighted { unit, from, to } => match (from, to) {
+                (Some(from), Some(to)) => write!(
+                    f,
+                    "enemy {} moved from {} to {}",
+                    unit,
+                    notation(*from),
+                    notation(*to)
+                ),
+                (Some(from), None) => write!(f, "enemy {} left {}", unit, notation(*from)),
+                (None, Some(to)) => write!(f, "enemy {} appeared on {}", unit, notation(*to)),
+                (None, None) => write!(f, "enemy {} moved out of sight", unit),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Notice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}. {}", self.ply / 2 + 1, self.report)
+    }
+}
+
+// Run the match subcommand, printing each game and both bots' scores.
+fn run_match(args: &clap::ArgMatches, no_fog: bool, seed: Option<&str>) -> Result<(), String> {
+    let number = |name: &str| -> Result<u32, String> {
+        let value = args.value_of(name).unwrap_or_default();
+        value
+            .parse()
+            .map_err(|_| format!("--{} must be a number, got {:?}", name, value))
+    };
+    let config = arena::MatchConfig {
+        games: number("games")?,
+        max_plies: number("max-plies")?,
+        no_fog,
+        seed: seed
+            .map(|seed| {
+                seed.parse()
+                    .map_err(|_| format!("--seed must be a number, got {:?}", seed))
+            })
+            .transpose()?,
+    };
+    let (a, b) = (
+        args.value_of("a").unwrap_or_default(),
+        args.value_of("b").unwrap_or_default(),
+    );
+    let create = |flag: &str| -> Result<Option<File>, String> {
+        args.value_of(flag)
+            .map(|path| File::create(path).map_err(|err| format!("creating {}: {}", path, err)))
+            .transpose()
+    };
+    let (mut results, mut pgn) = (create("results")?, create("pgn")?);
+    let mut failed = None;
+    if let Some(results) = results.as_mut() {
+        writeln!(results, "{}", arena::CSV_HEADER)
+            .map_err(|err| format!("writing results: {}", err))?;
+    }
+    let records = arena::run(a, b, &config, |record| {
+        println!(
+            "game {}: {} vs {}: {} in {} plies ({})",
+            record.round,
+            record.white,
+            record.black,
+            record.outcome.pgn(),
+            record.plies,
+            record.termination
+        );
+        let written = results
+            .as_mut()
+            .map_or(Ok(()), |results| writeln!(results, "{}", record.csv()))
+            .and_then(|_| {
+                pgn.as_mut()
+                    .map_or(Ok(()), |pgn| write!(pgn, "{}", record.pgn))
+            });
+        if let Err(err) = written {
+            failed.get_or_insert(format!("writing game {}: {}", record.round, err));
+        }
+    })?;
+    if let Some(err) = failed {
+        return Err(err);
+    }
+    println!("{}", arena::Score::tally(a, &records));
+    if a != b {
+        println!("{}", arena::Score::tally(b, &records));
+    }
+    Ok(())
+}
+
+// Analyse the game for a replay, printing a summary, and return a comment
+// for each ply. A game that cannot be analysed is replayed without.
+fn analysed(game: &Game) -> Vec<Option<String>> {
+    println!("analysing {} plies", game.state.history.len());
+    match game.analyse() {
+        Ok(annotations) => {
+            print!("{}", analysis::summary(&annotations));
+            analysis::comments(&annotations)
+        }
+        Err(err) => {
+            eprintln!("analysing game: {}", err);
+            vec![]
+        }
+    }
+}
+
+// Run the analyse subcommand, printing every graded ply and a summary.
+fn run_analysis(args: &clap::ArgMatches) -> Result<(), String> {
+    let number = |name: &str| -> Result<u32, String> {
+        let value = args.value_of(name).unwrap_or_default();
+        value
+            .parse()
+            .map_err(|_| format!("--{} must be a number, got {:?}", name, value))
+    };
+    let path = args.value_of("pgn").expect("pgn is required");
+    let text = std::fs::read_to_string(path).map_err(|err| format!("reading {}: {}", path, err))?;
+    let saved = pgn::parse(&text)?;
+    let seed = match saved.tag("Seed") {
+        Some(seed) => seed.parse().map_err(|_| format!("bad seed {:?}", seed))?,
+        None => 0,
+    };
+    let mut game = Game::headless(Board::new(), saved.tag("Variant") == Some("Standard"), seed);
+    game.play_pgn(&saved)?;
+    let config = analysis::AnalysisConfig {
+        depth: number("depth")?,
+        samples: number("samples")?,
+        seed,
+        ..analysis::AnalysisConfig::default()
+    };
+    let annotations = analysis::analyse(&game, &config)?;
+    for (ii, (san, annotation)) in saved.moves.iter().zip(annotations.iter()).enumerate() {
+        if let Some(comment) = annotation.comment() {
+            println!("{}. {} {}", ii + 1, san, comment);
+        }
+    }
+    print!("{}", analysis::summary(&annotations));
+    if let Some(out) = args.value_of("out") {
+        let tags: Vec<(&str, String)> = saved
+            .tags
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.clone()))
+            .collect();
+        let result = if saved.result.is_empty() {
+            "*"
+        } else {
+            &saved.result
+        };
+        let text = pgn::export(
+            &tags,
+            &game.initial.board,
+            &game.state.history,
+            &analysis::comments(&annotations),
+            result,
+        );
+        std::fs::write(out, text).map_err(|err| format!("writing {}: {}", out, err))?;
+    }
+    Ok(())
+}
+
+// Run a mail subcommand. A new game is saved before its first file is
+// written, so it is never overwritten; after that, the game is saved after
+// writing the file to send, so a failed write can be retried.
+fn run_mail(args: &clap::ArgMatches) -> Result<(), String> {
+    use peer::Correspondence;
+    use std::path::Path;
+    let (command, args) = match args.subcommand() {
+        (command, Some(args)) => (command, args),
+        _ => return Err("missing command, see --help".into()),
+    };
+    let path = |name: &str| Path::new(args.value_of(name).unwrap_or_default());
+    let read = |name: &str| {
+        std::fs::read(path(name))
+            .map_err(|err| format!("reading {}: {}", path(name).display(), err))
+    };
+    let name = args.value_of("name").unwrap_or_default();
+    let (state, file, created) = match command {
+        "new" => {
+            let (state, invitation) = Correspondence::new(name);
+            (state, Some(invitation), true)
         }
-        Ok(())
-    }
-    // Draw the fog over war over the enemy pieces.
-    fn draw_fog(&self, ctx: &mut Context) -> GameResult<()> {
-        #[derive(Copy, Clone)]
-        enum Visibility {
-            Fog,
-            Clear,
+        "accept" => {
+            let (state, reply) = Correspondence::accept(&read("invitation")?, name)?;
+            (state, Some(reply), true)
         }
-        let mut mask = [[Visibility::Fog; 8]; 8];
-        let mut mb = MeshBuilder::new();
-        let (w, h) = self.cell_size(ctx);
-        for Position { x, y, piece } in self.state.board.iter() {
-            if let Some(Piece { player, .. }) = piece {
-                if self.is_enemy(player) {
-                    continue;
-                }
-                let (x, y) = (x as i32, y as i32);
-                for (x, y) in self.line_of_sight((x, y)).into_iter().chain(vec![(x, y)]) {
-                    // TODO: Better way to handle these bounds checks?
-                    // 1. Let trait define valid usize.
-                    // 2. Let board size be dynamic.
-                    if y >= 0 && x >= 0 && y < 8 && x < 8 {
-                        mask[y as usize][x as usize] = Visibility::Clear;
-                    }
+        command => {
+            let mut state = Correspondence::load(path("state"))?;
+            let file = match command {
+                "read" => {
+                    state.read(&read("file")?)?;
+                    None
                 }
-            }
-        }
-        for (y, row) in mask.iter().enumerate() {
-            for (x, visibility) in row.iter().enumerate() {
-                if let Visibility::Fog = visibility {
-                    let (x, y) = (x as f32, y as f32);
-                    mb.rectangle(
-                        graphics::DrawMode::fill(),
-                        graphics::Rect::new(x * w, y * h, w, h),
-                        graphics::Color::BLACK,
-                    );
+                "move" => {
+                    let moves = state.parse_move(args.value_of("move").unwrap_or_default())?;
+                    Some(state.play(moves, args.is_present("offer-draw"))?)
                 }
-            }
+                "draw" => Some(state.draw()?),
+                "resign" => Some(state.resign()?),
+                "reveal" => Some(state.reveal()?),
+                _ => None,
+            };
+            (state, file, false)
         }
-        let mut mesh = mb.build(ctx)?;
-        graphics::draw(ctx, &mut mesh, DrawParam::default())
+    };
+    if created {
+        state.save(path("state"), true)?;
     }
-    // Draw meta information useful for debugging.
-    fn draw_debug_stats(&self, ctx: &mut Context) -> GameResult<()> {
-        let (text_size, padding) = (20.0, 5.0);
-        let (width, height) = graphics::size(ctx);
-        let (w, h) = self.cell_size(ctx);
-        let stats = vec![
-            format!("window: {} x {}", width, height),
-            format!("  cell: {} x {}", w, h),
-        ];
-        for (ii, stat) in stats.iter().enumerate() {
-            self.text(
-                ctx,
-                stat,
-                (10.0, ii as f32 * text_size + padding),
-                text_size,
-                None,
-            );
-        }
-        Ok(())
+    if let Some(file) = file {
+        let out = path("out");
+        std::fs::write(out, &file).map_err(|err| format!("writing {}: {}", out.display(), err))?;
+        println!(
+            "wrote {} ({} bytes), send it to the other player",
+            out.display(),
+            file.len()
+        );
     }
-    fn is_enemy(&self, player: &Player) -> bool {
-        self.state.turn != *player
+    if !created && command != "show" {
+        state.save(path("state"), false)?;
     }
-    // Calculate cell size based on window size (width, height).
-    fn cell_size(&self, ctx: &mut Context) -> (f32, f32) {
-        let (w, h) = graphics::drawable_size(ctx);
-        ((w / 8.0), (h / 8.0))
+    print!("{}", state);
+    Ok(())
+}
+
+// Settings for a network game from the command line: the subcommand's
+// variant, time control and chat switch, and the global fog switch.
+fn game_settings(app: &clap::ArgMatches, args: &clap::ArgMatches) -> protocol::GameSettings {
+    let variant = args.value_of("variant").expect("variant has a default");
+    if Board::variant(variant).is_none() {
+        eprintln!("unknown variant {:?}", variant);
+        std::process::exit(1);
     }
-    fn text(
-        &self,
-        ctx: &mut Context,
-        text: &str,
-        coord: (f32, f32),
-        scale: f32,
-        color: Option<Color>,
-    ) {
-        let fragment: graphics::TextFragment = (text, self.state.font, scale).into();
-        graphics::queue_text(
-            ctx,
-            &Text::new(fragment),
-            [coord.0, coord.1],
-            color.or(Some(graphics::Color::BLACK)),
+    protocol::GameSettings {
+        variant: variant.into(),
+        fog: !app.is_present("no-fog"),
+        time_control: args.value_of("time-control").map(|text| {
+            protocol::TimeControl::parse(text).unwrap_or_else(|err| {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            })
+        }),
+        chat: !args.is_present("no-chat"),
+    }
+}
+
+// Run the tournament subcommand, printing each game and the final
+// crosstable.
+fn run_tournament(args: &clap::ArgMatches, no_fog: bool, seed: Option<&str>) -> Result<(), String> {
+    let number = |name: &str| -> Result<u64, String> {
+        let value = args.value_of(name).unwrap_or_default();
+        value
+            .parse()
+            .map_err(|_| format!("--{} must be a number, got {:?}", name, value))
+    };
+    let players: Vec<String> = args
+        .values_of("players")
+        .map(|values| values.map(String::from).collect())
+        .unwrap_or_default();
+    let format = tournament::Format::parse(args.value_of("format").unwrap_or_default())
+        .expect("format is validated by clap");
+    let rounds = match args.value_of("rounds") {
+        Some(_) => number("rounds")? as u32,
+        None => match format {
+            tournament::Format::RoundRobin => 1,
+            tournament::Format::Swiss => (players.len().max(2) as f64).log2().ceil() as u32,
+        },
+    };
+    let config = tournament::TournamentConfig {
+        format,
+        rounds,
+        max_plies: number("max-plies")? as u32,
+        no_fog,
+        seed: seed
+            .map(|seed| {
+                seed.parse()
+                    .map_err(|_| format!("--seed must be a number, got {:?}", seed))
+            })
+            .transpose()?,
+    };
+    let state = args.value_of("state").map(std::path::Path::new);
+    let mut tournament = match state {
+        Some(path) if path.exists() => {
+            let tournament = tournament::Tournament::load(path)?;
+            if !players.is_empty() && players != tournament.players {
+                return Err(format!(
+                    "{} holds a tournament between {}",
+                    path.display(),
+                    tournament.players.join(", ")
+                ));
+            }
+            // A resumed tournament keeps the settings it was started with,
+            // so refuse flags asking for different ones.
+            let saved = &tournament.config;
+            let conflicts = [
+                (
+                    "format",
+                    args.occurrences_of("format") > 0 && config.format != saved.format,
+                ),
+                (
+                    "rounds",
+                    args.is_present("rounds") && config.rounds != saved.rounds,
+                ),
+                (
+                    "max-plies",
+                    args.occurrences_of("max-plies") > 0 && config.max_plies != saved.max_plies,
+                ),
+                ("no-fog", no_fog && !saved.no_fog),
+                ("seed", config.seed.is_some() && config.seed != saved.seed),
+            ];
+            if let Some((flag, _)) = conflicts.iter().find(|(_, conflict)| *conflict) {
+                return Err(format!(
+                    "{} holds a tournament with different settings than --{}",
+                    path.display(),
+                    flag
+                ));
+            }
+            println!(
+                "resuming after {} games from {}",
+                tournament.records.len(),
+                path.display()
+            );
+            tournament
+        }
+        _ => tournament::Tournament::new(players, config)?,
+    };
+    let mut pgn = match args.value_of("pgn") {
+        Some(path) => Some((
+            path,
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|err| format!("opening {}: {}", path, err))?,
+        )),
+        None => None,
+    };
+    let mut failed = None;
+    tournament.run(|tournament, record| {
+        println!(
+            "round {}: {} vs {}: {} in {} plies ({})",
+            record.round,
+            record.white,
+            record.black,
+            record.outcome.pgn(),
+            record.plies,
+            record.termination
         );
+        if let Some((path, pgn)) = pgn.as_mut() {
+            if let Err(err) = write!(pgn, "{}", record.pgn) {
+                failed.get_or_insert(format!("writing {}: {}", path, err));
+            }
+        }
+        if let Some(path) = state {
+            if let Err(err) = tournament.save(path) {
+                failed.get_or_insert(err);
+            }
+        }
+    })?;
+    print!("{}", tournament.crosstable());
+    match failed {
+        Some(err) => Err(err),
+        None => Ok(()),
     }
-    // Translate pixel coordinates to grid cells.
-    fn pixels_to_grid(&self, ctx: &mut Context, coord: (f32, f32)) -> (i32, i32) {
-        let (x, y) = coord;
-        let (w, h) = self.cell_size(ctx);
-        ((x / w).floor() as i32, (y / h).floor() as i32)
+}
+
+/// Parse notation reads a board position in algebraic notation, such as
+/// `f3`.
+pub fn parse_notation(text: &str) -> Option<(i32, i32)> {
+    let bytes = text.as_bytes();
+    if bytes.len() != 2 {
+        return None;
     }
+    let pos = (
+        i32::from(bytes[0]) - i32::from(b'a'),
+        i32::from(bytes[1]) - i32::from(b'1'),
+    );
+    if in_bounds(pos) {
+        Some(pos)
+    } else {
+        None
+    }
+}
+
+/// In bounds checks whether the position lies on the board.
+pub fn in_bounds(pos: (i32, i32)) -> bool {
+    let (x, y) = pos;
+    x >= 0 && y >= 0 && x < 8 && y < 8
+}
+
+/// Notation names a board position in algebraic notation, for example `f3`.
+/// White's back rank is rank 1.
+pub fn notation(pos: (i32, i32)) -> String {
+    let (x, y) = pos;
+    format!("{}{}", (b'a' + x as u8) as char, y + 1)
 }
 
 impl Board {
@@ -852,6 +3179,14 @@ impl Board {
             ],
         ])
     }
+    /// Variant sets up the starting board of a named variant: `standard`, or
+    /// any scenario.
+    pub fn variant(name: &str) -> Option<Self> {
+        match name {
+            "standard" => Some(Board::new()),
+            _ => Board::scenario(name),
+        }
+    }
     /// scenario sets up a board for the given scenario, identified by name.
     pub fn scenario(title: &str) -> Option<Self> {
         match title {
@@ -900,6 +3235,274 @@ impl Board {
             self.0[y as usize][x as usize].as_ref()
         }
     }
+    /// Castle returns the king and rook moves of castling the king at the
+    /// first position with the rook at the second, if that castle is legal:
+    /// both are unmoved, share a rank, and every square between them is
+    /// empty. The king steps two squares towards the rook, which lands on
+    /// the square the king passed over.
+    pub fn castle(&self, king: (i32, i32), rook: (i32, i32)) -> Option<[Move; 2]> {
+        let (k, r) = match (self.get(king), self.get(rook)) {
+            (Some(k), Some(r)) => (k, r),
+            _ => return None,
+        };
+        if k.unit != Unit::King
+            || r.unit != Unit::Rook
+            || k.player != r.player
+            || k.moved > 0
+            || r.moved > 0
+            || king.1 != rook.1
+            || (rook.0 - king.0).abs() < 3
+        {
+            return None;
+        }
+        let dir = (rook.0 - king.0).signum();
+        let mut x = king.0 + dir;
+        while x != rook.0 {
+            if self.get((x, king.1)).is_some() {
+                return None;
+            }
+            x += dir;
+        }
+        Some([
+            (king, (king.0 + 2 * dir, king.1)),
+            (rook, (king.0 + dir, king.1)),
+        ])
+    }
+    /// Moves calculates all valid moves for the piece at the given position,
+    /// from the perspective of the player that owns it.
+    pub fn moves(&self, pos: (i32, i32)) -> Vec<(i32, i32)> {
+        let (x, y) = pos;
+        use Unit::*;
+        let owner = match self.get(pos) {
+            Some(piece) => piece.player,
+            None => return vec![],
+        };
+        match self.get((x, y)) {
+            Some(Piece {
+                unit,
+                player,
+                moved,
+            }) => match unit {
+                // Pawn can move in the direction of the player by 1 square.
+                // For the first move, a pawn can move up to 2 squares.
+                // Pawns can only attack diagonally in the direction of the
+                // player.
+                // Cannot attack straight ahead.
+                Pawn => {
+                    let mut moves = vec![];
+                    match player {
+                        // Clean: The only difference between these two
+                        // blocks is the direction.
+                        Player::White => {
+                            if self.contains_enemy_of(*player, (x - 1, y + 1)) {
+                                moves.push((x - 1, y + 1));
+                            }
+                            if self.contains_enemy_of(*player, (x + 1, y + 1)) {
+                                moves.push((x + 1, y + 1));
+                            }
+                            if y < 7 && self.get((x, y + 1)).is_none() {
+                                moves.push((x, y + 1));
+                                if *moved == 0 && y < 6 && self.get((x, y + 2)).is_none() {
+                                    moves.push((x, y + 2));
+                                }
+                            }
+                        }
+                        Player::Black => {
+                            if self.contains_enemy_of(*player, (x - 1, y - 1)) {
+                                moves.push((x - 1, y - 1));
+                            }
+                            if self.contains_enemy_of(*player, (x + 1, y - 1)) {
+                                moves.push((x + 1, y - 1));
+                            }
+                            if y > 0 && self.get((x, y - 1)).is_none() {
+                                moves.push((x, y - 1));
+                                if *moved == 0 && y > 1 && self.get((x, y - 2)).is_none() {
+                                    moves.push((x, y - 2));
+                                }
+                            }
+                        }
+                    };
+                    moves
+                }
+                // Knight moves in an L shape: two out, one across.
+                Knight => vec![
+                    (x + 2, y - 1),
+                    (x + 2, y + 1),
+                    (x - 2, y - 1),
+                    (x - 2, y + 1),
+                    (x + 1, y + 2),
+                    (x - 1, y + 2),
+                    (x + 1, y - 2),
+                    (x - 1, y - 2),
+                ],
+                // Rook moves in all non diagonal directions.
+                Rook => vec![]
+                    .into_iter()
+                    .chain(LineOfSight::new((1..8).map(|ii| (x + ii, y)), self))
+                    .chain(LineOfSight::new((1..8).map(|ii| (x - ii, y)), self))
+                    .chain(LineOfSight::new((1..8).map(|ii| (x, y + ii)), self))
+                    .chain(LineOfSight::new((1..8).map(|ii| (x, y - ii)), self))
+                    .collect(),
+                // Bishop moves all diagonal directions.
+                Bishop => vec![]
+                    .into_iter()
+                    .chain(LineOfSight::new((1..8).map(|ii| (x + ii, y + ii)), self))
+                    .chain(LineOfSight::new((1..8).map(|ii| (x - ii, y - ii)), self))
+                    .chain(LineOfSight::new((1..8).map(|ii| (x - ii, y + ii)), self))
+                    .chain(LineOfSight::new((1..8).map(|ii| (x + ii, y - ii)), self))
+                    .collect(),
+                // Queen moves in all eight directions.
+                Queen => vec![]
+                    .into_iter()
+                    .chain(LineOfSight::new((1..8).map(|ii| (x + ii, y)), self))
+                    .chain(LineOfSight::new((1..8).map(|ii| (x - ii, y)), self))
+                    .chain(LineOfSight::new((1..8).map(|ii| (x, y + ii)), self))
+                    .chain(LineOfSight::new((1..8).map(|ii| (x, y - ii)), self))
+                    .chain(LineOfSight::new((1..8).map(|ii| (x + ii, y + ii)), self))
+                    .chain(LineOfSight::new((1..8).map(|ii| (x - ii, y - ii)), self))
+                    .chain(LineOfSight::new((1..8).map(|ii| (x - ii, y + ii)), self))
+                    .chain(LineOfSight::new((1..8).map(|ii| (x + ii, y - ii)), self))
+                    .collect(),
+                // King can move to any adjacent cell that isn't occupied by
+                // a piece of the same player.
+                King => vec![
+                    (x + 1, y + 1),
+                    (x - 1, y - 1),
+                    (x + 1, y - 1),
+                    (x - 1, y + 1),
+                    (x + 1, y),
+                    (x - 1, y),
+                    (x, y + 1),
+                    (x, y - 1),
+                ],
+            },
+            None => vec![],
+        }
+        .into_iter()
+        .filter(|pos| in_bounds(*pos) && !self.contains_ally_of(owner, *pos))
+        .collect()
+    }
+    // Calculate line of sight for any piece at the given coordinate.
+    pub fn line_of_sight(&self, pos: (i32, i32)) -> Vec<(i32, i32)> {
+        let (x, y) = pos;
+        // Like sliding pieces, an unmoved pawn sees as far as its first move
+        // reaches, including any piece blocking it.
+        let lookahead = match self.get(pos) {
+            Some(Piece {
+                unit: Unit::Pawn,
+                player,
+                moved: 0,
+            }) => {
+                let dy = match player {
+                    Player::White => 1,
+                    Player::Black => -1,
+                };
+                if self.get((x, y + dy)).is_none() {
+                    vec![(x, y + dy * 2)]
+                } else {
+                    vec![]
+                }
+            }
+            _ => vec![],
+        };
+        self.moves(pos)
+            .into_iter()
+            .chain(lookahead)
+            .chain(vec![
+                (x + 1, y + 1),
+                (x - 1, y - 1),
+                (x + 1, y - 1),
+                (x - 1, y + 1),
+                (x + 1, y),
+                (x - 1, y),
+                (x, y + 1),
+                (x, y - 1),
+            ])
+            .collect()
+    }
+    /// Visibility calculates which squares the given player can currently see,
+    /// indexed by `[y][x]`.
+    pub fn visibility(&self, player: &Player) -> [[bool; 8]; 8] {
+        let mut mask = [[false; 8]; 8];
+        for Position { x, y, piece } in self.iter() {
+            if let Some(Piece { player: owner, .. }) = piece {
+                if owner != player {
+                    continue;
+                }
+                let (x, y) = (x as i32, y as i32);
+                for (x, y) in self.line_of_sight((x, y)).into_iter().chain(vec![(x, y)]) {
+                    // TODO: Let board size be dynamic.
+                    if in_bounds((x, y)) {
+                        mask[y as usize][x as usize] = true;
+                    }
+                }
+            }
+        }
+        mask
+    }
+    /// Moves for lists every move available to the given player's pieces.
+    pub fn moves_for(&self, player: &Player) -> Vec<Move> {
+        self.iter()
+            .filter(|Position { piece, .. }| matches!(piece, Some(p) if p.player == *player))
+            .flat_map(|Position { x, y, .. }| {
+                let from = (x as i32, y as i32);
+                self.moves(from).into_iter().map(move |to| (from, to))
+            })
+            .collect()
+    }
+    /// Winner is the player who has captured the opposing king, if any.
+    pub fn winner(&self) -> Option<Player> {
+        let kings: Vec<Player> = self
+            .iter()
+            .filter_map(|Position { piece, .. }| match piece {
+                Some(Piece {
+                    unit: Unit::King,
+                    player,
+                    ..
+                }) => Some(*player),
+                _ => None,
+            })
+            .collect();
+        match (
+            kings.contains(&Player::White),
+            kings.contains(&Player::Black),
+        ) {
+            (true, false) => Some(Player::White),
+            (false, true) => Some(Player::Black),
+            _ => None,
+        }
+    }
+    /// Coverage counts how many of the given player's pieces can see each
+    /// square, indexed by `[y][x]`. A piece's own square is not counted.
+    pub fn coverage(&self, player: &Player) -> [[u32; 8]; 8] {
+        let mut counts = [[0; 8]; 8];
+        for Position { x, y, piece } in self.iter() {
+            if let Some(Piece { player: owner, .. }) = piece {
+                if owner != player {
+                    continue;
+                }
+                let sight: HashSet<(i32, i32)> = self
+                    .line_of_sight((x as i32, y as i32))
+                    .into_iter()
+                    .filter(|pos| in_bounds(*pos))
+                    .collect();
+                for (x, y) in sight {
+                    counts[y as usize][x as usize] += 1;
+                }
+            }
+        }
+        counts
+    }
+    /// Contains ally of checks whether the specified position is occupied by
+    /// a piece owned by the given player.
+    pub fn contains_ally_of(&self, player: Player, pos: (i32, i32)) -> bool {
+        matches!(self.get(pos), Some(piece) if piece.player == player)
+    }
+    /// Contains enemy of checks whether the specified position is occupied by
+    /// a piece owned by the opponent of the given player.
+    pub fn contains_enemy_of(&self, player: Player, pos: (i32, i32)) -> bool {
+        matches!(self.get(pos), Some(piece) if piece.player != player)
+    }
     /// Set the piece to the specified (x, y) coordinate.
     /// Overwrites anything already at the location.
     /// Noop if the coordinates are out of bounds.
@@ -929,14 +3532,14 @@ impl Board {
             self.set(
                 (to.0, to.1),
                 Piece {
-                    unit: unit,
-                    player: player,
+                    unit,
+                    player,
                     moved: moved + 1,
                 },
             );
         }
     }
-    fn iter(&self) -> BoardIter {
+    fn iter(&self) -> BoardIter<'_> {
         BoardIter {
             pos: None,
             board: self,
@@ -1021,14 +3624,11 @@ impl<'a> Iterator for BoardIter<'a> {
         }
         if let Some((x, y)) = self.pos {
             match self.board.0.get(y) {
-                Some(cell) => match cell.get(x) {
-                    Some(piece) => Some(Position {
-                        piece: piece.as_ref(),
-                        x,
-                        y,
-                    }),
-                    None => None,
-                },
+                Some(cell) => cell.get(x).map(|piece| Position {
+                    piece: piece.as_ref(),
+                    x,
+                    y,
+                }),
                 None => None,
             }
         } else {
@@ -1036,3 +3636,234 @@ impl<'a> Iterator for BoardIter<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::seq::SliceRandom;
+
+    #[test]
+    fn unmoved_pawns_see_where_their_double_step_lands() {
+        let e4 = (4, 3);
+        let sees = |board: &Board| board.visibility(&Player::White)[e4.1 as usize][e4.0 as usize];
+        let mut board = Board::new();
+        assert!(sees(&board));
+        // A blocked pawn cannot step twice, and so sees no further.
+        let blocker = Piece {
+            unit: Unit::Pawn,
+            player: Player::Black,
+            moved: 1,
+        };
+        board.set((4, 2), blocker);
+        assert!(!sees(&board));
+        // Neither can a pawn that has already moved.
+        let mut board = Board::new();
+        let moved = Piece {
+            unit: Unit::Pawn,
+            player: Player::White,
+            moved: 1,
+        };
+        board.set((4, 1), moved);
+        assert!(!sees(&board));
+    }
+    #[test]
+    fn views_offer_exactly_the_legal_moves() {
+        let sorted = |mut moves: Vec<Move>| {
+            moves.sort();
+            moves
+        };
+        for seed in 0..20 {
+            let mut game = Game::headless(Board::new(), false, seed);
+            let mut rng = StdRng::seed_from_u64(seed);
+            while game.winner().is_none() && game.state.ply < 80 {
+                let view = game.view_for(&game.state.turn);
+                let legal = sorted(game.legal_moves());
+                assert_eq!(sorted(view.moves()), legal, "ply {}", game.state.ply);
+                match legal.choose(&mut rng) {
+                    Some(mv) => assert!(game.make_move(*mv)),
+                    None => break,
+                }
+            }
+        }
+    }
+    #[test]
+    fn queenside_castles_keep_both_pieces() {
+        let mut board = Board::new();
+        for x in 2..4 {
+            board.0[0][x] = None;
+        }
+        let mut game = Game::headless(board, true, 0);
+        // Squares between the king and rook must all be empty.
+        assert!(!game.castle(&[(4, 0), (0, 0)]));
+        game.state.board.0[0][1] = None;
+        assert!(game.castle(&[(0, 0), (4, 0)]));
+        let board = &game.state.board;
+        assert_eq!(board.get((2, 0)).map(|piece| piece.unit), Some(Unit::King));
+        assert_eq!(board.get((3, 0)).map(|piece| piece.unit), Some(Unit::Rook));
+        assert!(board.get((0, 0)).is_none());
+        assert!(board.get((4, 0)).is_none());
+    }
+    #[test]
+    fn seeded_games_replay_the_same() {
+        let play = |seed: u64| {
+            let mut white = bot::by_name("random", Some(seed)).unwrap();
+            let mut black = bot::by_name("random", Some(seed + 1)).unwrap();
+            let game = Game::headless(Board::new(), false, seed);
+            arena::play(&mut *white, &mut *black, game, 60).0
+        };
+        let (first, second) = (play(7), play(7));
+        assert_eq!(first.state.history, second.state.history);
+        // Loading the saved game replays it from its seed to the same state.
+        let outcome = arena::Outcome::Draw;
+        let record = arena::record(&first, 1, "random", "random", outcome, "test");
+        let saved = pgn::parse(&record.pgn).unwrap();
+        let seed = saved.tag("Seed").unwrap().parse().unwrap();
+        let mut loaded = Game::headless(Board::new(), false, seed);
+        loaded.play_pgn(&saved).unwrap();
+        assert_eq!(loaded.state.history, first.state.history);
+        assert_eq!(
+            (loaded.state.ply, loaded.state.turn),
+            (first.state.ply, first.state.turn)
+        );
+        for at in (0..64).map(|ii| (ii % 8, ii / 8)) {
+            let unit = |game: &Game| game.state.board.get(at).map(|p| (p.unit, p.player));
+            assert_eq!(unit(&loaded), unit(&first));
+        }
+        let (mut a, mut b) = (loaded.state.rng.clone(), first.state.rng.clone());
+        assert_eq!(a.gen::<u64>(), b.gen::<u64>());
+    }
+    // Play moves from the standard position, each for the side to move.
+    fn played(moves: &[Move]) -> Game {
+        let mut game = Game::headless(Board::new(), false, 0);
+        for mv in moves {
+            let player = game.state.turn;
+            assert!(game.apply(&Step {
+                player,
+                moves: vec![*mv],
+            }));
+        }
+        game
+    }
+
+    #[test]
+    fn notices_tell_only_what_was_seen() {
+        // 1. e4 d5 2. exd5
+        let game = played(&[((4, 1), (4, 3)), ((3, 6), (3, 4)), ((4, 3), (3, 4))]);
+        let reports = |player| -> Vec<(u32, Report)> {
+            game.notices(&player)
+                .map(|notice| (notice.ply, notice.report.clone()))
+                .collect()
+        };
+        // Black cannot see e4, so learns nothing of White's first move.
+        let sighted = Report::Sighted {
+            unit: Unit::Pawn,
+            from: None,
+            to: Some((3, 4)),
+        };
+        let captured = Report::Captured {
+            unit: Unit::Pawn,
+            at: (3, 4),
+        };
+        assert_eq!(reports(Player::White), vec![(1, sighted), (2, captured)]);
+        let lost = Report::Lost {
+            unit: Unit::Pawn,
+            at: (3, 4),
+            by: Some(Unit::Pawn),
+        };
+        assert_eq!(reports(Player::Black), vec![(2, lost)]);
+    }
+
+    #[test]
+    fn infosets_follow_hidden_moves_and_captures() {
+        // 1. e4 Nf6, out of White's sight.
+        let game = played(&[((4, 1), (4, 3)), ((6, 7), (5, 5))]);
+        let infoset = game.infoset(&Player::White);
+        assert_eq!(infoset.moves(), 1);
+        let knights = infoset.possible(Unit::Knight);
+        for at in [(5, 5), (7, 5), (6, 7), (1, 7)].iter() {
+            assert!(knights.contains(at), "{:?}", at);
+        }
+        // 2. e5 Nd5 3. e6 Nc3 4. dxc3, capturing the knight.
+        let game = played(&[
+            ((4, 1), (4, 3)),
+            ((6, 7), (5, 5)),
+            ((4, 3), (4, 4)),
+            ((5, 5), (3, 4)),
+            ((4, 4), (4, 5)),
+            ((3, 4), (2, 2)),
+            ((3, 1), (2, 2)),
+        ]);
+        let infoset = game.infoset(&Player::White);
+        assert_eq!(infoset.remaining(Unit::Knight), 1);
+        assert!(!infoset.possible(Unit::Knight).contains(&(2, 2)));
+    }
+
+    #[test]
+    fn the_overlay_shows_what_the_bot_believed() {
+        let mut game = played(&[((4, 1), (4, 3))]);
+        let config = bot::IsmctsConfig {
+            iterations: Some(50),
+            time: None,
+            ..bot::IsmctsConfig::default()
+        };
+        let opponent = Opponent::new(Player::Black, Box::new(bot::Ismcts::seeded(config, 1)));
+        game.opponent = Some(opponent.clone());
+        game.refresh_belief();
+        assert!(game.state.belief.is_none(), "the bot has not looked yet");
+
+        let view = game.view_for(&Player::Black);
+        opponent.bot.borrow_mut().choose(&view).unwrap();
+        game.refresh_belief();
+        let belief = game.state.belief.clone().expect("the bot's belief");
+        assert_eq!(belief.player, Player::Black);
+        assert_eq!(belief.samples, 50);
+        for unit in UNITS.iter() {
+            let odds = &belief.odds[unit.index()];
+            let squares = odds.iter().flatten().zip(view.visible.iter().flatten());
+            for (odds, visible) in squares {
+                assert!(!visible || *odds == 0.0);
+            }
+            // Every hidden unit is somewhere in the fog.
+            let seen = view
+                .board
+                .iter()
+                .filter(|p| matches!(p.piece, Some(piece) if piece.player == Player::White && piece.unit == *unit))
+                .count() as f32;
+            let hidden: f32 = odds.iter().flatten().sum();
+            let remaining = game.infoset(&Player::Black).remaining(*unit) as f32;
+            assert!((hidden + seen - remaining).abs() < 1e-3, "{}", unit);
+        }
+
+        // A bot that keeps no belief shows none.
+        game.opponent = Some(Opponent::new(
+            Player::Black,
+            Box::new(bot::AlphaBeta::default()),
+        ));
+        game.refresh_belief();
+        assert!(game.state.belief.is_none());
+    }
+
+    #[test]
+    fn hints_are_limited_per_player_and_not_given_to_bots() {
+        let mut game = played(&[]);
+        game.state.hint_limit = 1;
+        let hint = game.hint().unwrap();
+        // Asking again shows the same hint without charging for it.
+        assert_eq!(game.hint(), Ok(hint));
+        assert_eq!(game.state.hints_used, [1, 0]);
+        assert!(game.make_move(hint));
+        let reply = game.hint().unwrap();
+        assert_eq!(game.state.hints_used, [1, 1]);
+        assert!(game.make_move(reply));
+        assert_eq!(game.hint(), Err("none left"));
+
+        let mut game = played(&[((4, 1), (4, 3))]);
+        game.state.hint_limit = 1;
+        game.opponent = Some(Opponent::new(
+            Player::Black,
+            Box::new(bot::RandomBot::seeded(1)),
+        ));
+        assert_eq!(game.hint(), Err("the bot is to move"));
+        assert_eq!(game.state.hints_used, [0, 0]);
+    }
+}
@@ -0,0 +1,408 @@
+//! Networked play. The server owns the only full copy of each game and sends
+//! each client just the squares its player can see, so a modified client has
+//! nothing hidden to reveal. Clients send moves, which the server validates
+//! with the rules engine before playing them.
+//!
+//! One server hosts any number of games, listed in its lobby. Messages are
+//! framed as described in `crate::protocol`, or carried as JSON over a
+//! WebSocket for the browser client.
+
+use crate::protocol::{self, read_frame, write_frame, GameInfo, GameSettings, Message};
+use crate::{Move, Player};
+use std::cell::Cell;
+use std::io::{self, BufReader};
+use std::net::{Shutdown, SocketAddr, TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+mod host;
+mod lobby;
+mod web;
+
+pub use lobby::serve;
+
+// How long a connecting client has to complete the handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often a client tries to resume a lost connection.
+pub const RETRY_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Agent names this program in handshakes.
+pub const AGENT: &str = concat!("fog-of-chess ", env!("CARGO_PKG_VERSION"));
+
+/// Plies spectators of a fog game are kept behind play when the server sets
+/// no delay of its own.
+pub const FOG_SPECTATOR_DELAY: u32 = 10;
+
+/// Settings for the server.
+#[derive(Clone, Debug)]
+pub struct ServerConfig {
+    /// Settings for games created for clients that join without choosing.
+    pub defaults: GameSettings,
+    /// Seeds every hosted game, making them reproducible.
+    pub seed: Option<u64>,
+    /// How long a disconnected player has to resume before forfeiting.
+    pub reconnect_timeout: Duration,
+    /// Stops both clocks while a player is disconnected, rather than leaving
+    /// the running clock to run.
+    pub pause_clock: bool,
+    /// Plies spectators are kept behind play, so they cannot pass on what
+    /// the players cannot see. Unset, fog games are watched
+    /// `FOG_SPECTATOR_DELAY` plies behind and others as they are played.
+    pub spectator_delay: Option<u32>,
+    /// Address to serve the browser client on, with its WebSocket bridge.
+    pub web: Option<String>,
+    /// Directory to write an audit log of each game to.
+    pub audit: Option<PathBuf>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            defaults: GameSettings::default(),
+            seed: None,
+            reconnect_timeout: Duration::from_secs(60),
+            pause_clock: false,
+            spectator_delay: None,
+            web: None,
+            audit: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Delay for is how many plies spectators of a game with the settings
+    /// are kept behind play.
+    pub fn delay_for(&self, settings: &GameSettings) -> u32 {
+        match self.spectator_delay {
+            Some(delay) => delay,
+            None if settings.fog => FOG_SPECTATOR_DELAY,
+            None => 0,
+        }
+    }
+}
+
+/// Clocks tracks the time each player has left.
+#[derive(Clone, Debug)]
+pub struct Clocks {
+    /// Time left when the running clock was started, indexed by
+    /// `Player::index`.
+    pub remaining: [Duration; 2],
+    pub running: Option<Player>,
+    /// When the running clock was started, or the reading was taken.
+    pub since: Instant,
+}
+
+impl Clocks {
+    fn new(control: protocol::TimeControl, turn: Player) -> Self {
+        Clocks {
+            remaining: [control.initial; 2],
+            running: Some(turn),
+            since: Instant::now(),
+        }
+    }
+    /// Reading reconstructs clocks from a `Clock` message.
+    pub fn reading(remaining_ms: [u64; 2], running: Option<Player>) -> Self {
+        Clocks {
+            remaining: [
+                Duration::from_millis(remaining_ms[0]),
+                Duration::from_millis(remaining_ms[1]),
+            ],
+            running,
+            since: Instant::now(),
+        }
+    }
+    /// Left is the time the player has right now.
+    pub fn left(&self, player: Player) -> Duration {
+        let remaining = self.remaining[player.index()];
+        if self.running == Some(player) {
+            remaining.saturating_sub(self.since.elapsed())
+        } else {
+            remaining
+        }
+    }
+    // Stop the mover's clock, add their increment and start the opponent's.
+    fn press(&mut self, mover: Player, increment: Duration) {
+        self.remaining[mover.index()] = self.left(mover) + increment;
+        self.running = Some(mover.opponent());
+        self.since = Instant::now();
+    }
+    // Stop the running clock.
+    fn pause(&mut self) {
+        if let Some(player) = self.running {
+            self.remaining[player.index()] = self.left(player);
+            self.running = None;
+        }
+    }
+    // Start the mover's clock again if it was paused.
+    fn resume(&mut self, turn: Player) {
+        if self.running.is_none() {
+            self.running = Some(turn);
+            self.since = Instant::now();
+        }
+    }
+    fn message(&self) -> Message {
+        let ms = |player| self.left(player).as_millis() as u64;
+        Message::Clock {
+            remaining_ms: [ms(Player::White), ms(Player::Black)],
+            running: self.running,
+        }
+    }
+}
+
+/// List games asks the server at the address for the games in its lobby.
+pub fn list_games(addr: impl ToSocketAddrs) -> io::Result<Vec<GameInfo>> {
+    let (stream, mut reader, version) = handshake(addr)?;
+    if version < protocol::LOBBY_VERSION {
+        return Err(invalid("the server has no lobby".into()));
+    }
+    write_frame(&stream, &Message::ListGames)?;
+    let games = match read_frame(&mut reader)? {
+        Some(Message::Games { games }) => games,
+        Some(Message::ChatGames { mut games, chat }) if chat.len() == games.len() => {
+            for (info, chat) in games.iter_mut().zip(chat) {
+                info.settings.chat = chat;
+            }
+            games
+        }
+        _ => return Err(invalid("expected a list of games".into())),
+    };
+    let _ = stream.shutdown(Shutdown::Both);
+    Ok(games)
+}
+
+// Connect to the server and agree a protocol version with it.
+fn handshake(addr: impl ToSocketAddrs) -> io::Result<(TcpStream, BufReader<TcpStream>, u16)> {
+    let stream = TcpStream::connect(addr)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    write_frame(&stream, &Message::hello(AGENT))?;
+    match read_frame(&mut reader)? {
+        Some(Message::Hello { max_version, .. }) => Ok((stream, reader, max_version)),
+        Some(Message::GameOver { reason, .. }) => Err(invalid(reason)),
+        _ => Err(invalid("expected a hello from the server".into())),
+    }
+}
+
+fn invalid(reason: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+/// Remote is a client's connection to the server.
+pub struct Remote {
+    /// The side played, or `None` when spectating.
+    pub player: Option<Player>,
+    /// Session token for resuming the seat, if the server issued one.
+    pub token: Option<String>,
+    addr: SocketAddr,
+    stream: TcpStream,
+    inbox: Receiver<Message>,
+    // When the connection was found lost, or last retried.
+    lost: Cell<Option<Instant>>,
+}
+
+impl Remote {
+    /// Connect takes a seat in the oldest game at the address still waiting
+    /// for a player, or in a new game with the server's default settings.
+    pub fn connect(addr: impl ToSocketAddrs, name: &str) -> io::Result<Self> {
+        let join = Message::Join {
+            name: name.into(),
+            player: None,
+        };
+        Remote::open(addr, join)
+    }
+    /// Create starts a game with the settings and takes a seat in it.
+    pub fn create(
+        addr: impl ToSocketAddrs,
+        name: &str,
+        settings: GameSettings,
+    ) -> io::Result<Self> {
+        // Games pass chat on unless asked otherwise, which older servers
+        // cannot be.
+        let create = if settings.chat {
+            Message::CreateGame {
+                name: name.into(),
+                settings,
+            }
+        } else {
+            Message::CreateChatGame {
+                name: name.into(),
+                chat: false,
+                settings,
+            }
+        };
+        Remote::open(addr, create)
+    }
+    /// Join game takes a seat in the game with the id.
+    pub fn join_game(addr: impl ToSocketAddrs, game: u64, name: &str) -> io::Result<Self> {
+        let join = Message::JoinGame {
+            game,
+            name: name.into(),
+        };
+        Remote::open(addr, join)
+    }
+    /// Spectate watches the game with the id.
+    pub fn spectate(addr: impl ToSocketAddrs, game: u64) -> io::Result<Self> {
+        Remote::open(addr, Message::Spectate { game })
+    }
+    /// Resume takes back the seat a session token was issued for.
+    pub fn resume(addr: impl ToSocketAddrs, token: &str) -> io::Result<Self> {
+        Remote::open(
+            addr,
+            Message::Resume {
+                token: token.into(),
+            },
+        )
+    }
+    fn open(addr: impl ToSocketAddrs, request: Message) -> io::Result<Self> {
+        let (stream, mut reader, version) = handshake(addr)?;
+        let needed = match request {
+            Message::Resume { .. } => protocol::RESUME_VERSION,
+            Message::CreateChatGame { .. } => protocol::CHAT_VERSION,
+            Message::CreateGame { .. } | Message::JoinGame { .. } | Message::Spectate { .. } => {
+                protocol::LOBBY_VERSION
+            }
+            _ => protocol::MIN_VERSION,
+        };
+        if version < needed {
+            return Err(invalid(format!(
+                "the server speaks protocol version {}, this needs {}",
+                version, needed
+            )));
+        }
+        let spectating = matches!(request, Message::Spectate { .. });
+        write_frame(&stream, &request)?;
+        let player = match read_frame(&mut reader)? {
+            Some(Message::Join {
+                player: Some(player),
+                ..
+            }) if !spectating => Some(player),
+            Some(Message::Spectate { .. }) if spectating => None,
+            Some(Message::GameOver { reason, .. }) => return Err(invalid(reason)),
+            _ => return Err(invalid("expected the server to seat us".into())),
+        };
+        let token = if player.is_some() && version >= protocol::RESUME_VERSION {
+            match read_frame(&mut reader)? {
+                Some(Message::Session { token }) => Some(token),
+                _ => return Err(invalid("expected a session token".into())),
+            }
+        } else {
+            None
+        };
+        let (messages, inbox) = mpsc::channel();
+        thread::spawn(move || {
+            while let Ok(Some(message)) = read_frame(&mut reader) {
+                if messages.send(message).is_err() {
+                    return;
+                }
+            }
+        });
+        Ok(Remote {
+            player,
+            token,
+            addr: stream.peer_addr()?,
+            stream,
+            inbox,
+            lost: Cell::new(None),
+        })
+    }
+    // The side played, or an error for spectators.
+    fn seated(&self) -> io::Result<Player> {
+        self.player.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::PermissionDenied, "spectators cannot play")
+        })
+    }
+    /// Send a ply to the server to be validated and played.
+    pub fn play(&self, moves: Vec<Move>) -> io::Result<()> {
+        self.seated()?;
+        write_frame(&self.stream, &Message::Move { moves })
+    }
+    /// Resign concedes the game.
+    pub fn resign(&self) -> io::Result<()> {
+        self.seated()?;
+        write_frame(&self.stream, &Message::Resign)
+    }
+    /// Offer a draw, or accept the opponent's offer.
+    pub fn offer_draw(&self) -> io::Result<()> {
+        let player = self.seated()?;
+        write_frame(&self.stream, &Message::DrawOffer { player })
+    }
+    /// Chat sends a line of chat to the other player.
+    pub fn chat(&self, text: &str) -> io::Result<()> {
+        self.seated()?;
+        let chat = Message::Chat {
+            from: String::new(),
+            text: text.into(),
+        };
+        write_frame(&self.stream, &chat)
+    }
+    /// Poll returns the next message from the server, if one has arrived.
+    pub fn poll(&self) -> Option<Message> {
+        match self.inbox.try_recv() {
+            Ok(message) => Some(message),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => {
+                if self.lost.get().is_none() {
+                    // Try straight away.
+                    let now = Instant::now();
+                    self.lost
+                        .set(Some(now.checked_sub(RETRY_INTERVAL).unwrap_or(now)));
+                }
+                None
+            }
+        }
+    }
+    /// Retry tries to resume a lost connection, at most once every
+    /// `RETRY_INTERVAL`. Returns `None` while there is nothing to try.
+    pub fn retry(&self) -> Option<io::Result<Remote>> {
+        let token = self.token.as_ref()?;
+        if self.lost.get()?.elapsed() < RETRY_INTERVAL {
+            return None;
+        }
+        self.lost.set(Some(Instant::now()));
+        Some(Remote::resume(self.addr, token))
+    }
+}
+
+impl Drop for Remote {
+    fn drop(&mut self) {
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn lost_connections_are_resumed() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || lobby::host(listener, ServerConfig::default()));
+        let anna = Remote::connect(addr, "anna").unwrap();
+        let _ben = Remote::connect(addr, "ben").unwrap();
+        assert!(anna.retry().is_none(), "retried a live connection");
+
+        anna.stream.shutdown(Shutdown::Both).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while anna.lost.get().is_none() {
+            anna.poll();
+            assert!(Instant::now() < deadline, "the loss went unnoticed");
+            thread::sleep(Duration::from_millis(10));
+        }
+        let resumed = anna.retry().expect("nothing to retry").unwrap();
+        assert_eq!(resumed.player, anna.player);
+        assert_eq!(resumed.token, anna.token);
+        assert!(anna.retry().is_none(), "retried within the interval");
+        loop {
+            if let Some(Message::Update(view)) = resumed.poll() {
+                assert_eq!(Some(view.player), anna.player);
+                break;
+            }
+            assert!(Instant::now() < deadline, "the view was not resent");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
@@ -0,0 +1,1059 @@
+//! Host runs a single game for the lobby: it seats the players, validates
+//! their moves and keeps the game going while they reconnect.
+
+use super::lobby::{lock, refuse, Lobby};
+use super::{Clocks, ServerConfig};
+use crate::audit::{self, Entry};
+use crate::protocol::{self, read_frame, write_frame, GameSettings, Message};
+use crate::{Board, Game, Player, PlayerView, Step};
+use std::collections::VecDeque;
+use std::io::{self, BufReader};
+use std::net::{IpAddr, Shutdown, TcpStream};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Arrival is a connection that has completed the handshake, with the
+/// request it chose a game with.
+pub struct Arrival {
+    pub stream: TcpStream,
+    pub version: u16,
+    pub request: Message,
+}
+
+/// Events delivered to a game. Those from a client carry the number of the
+/// connection they came from, so that a connection replaced by a resumed one
+/// can no longer act for its player.
+pub enum Event {
+    Arrived(Arrival),
+    Message(Player, u64, Message),
+    Malformed(Player, u64, String),
+    Left(Player, u64),
+    Unwatched(u64),
+}
+
+// A player's place in the game, kept while they are disconnected.
+struct Seat {
+    name: String,
+    token: String,
+    version: u16,
+    stream: Option<TcpStream>,
+    connection: u64,
+    // Address the player last connected from, kept while they are away.
+    addr: Option<IpAddr>,
+    // When the player disconnected, while they are away.
+    away: Option<Instant>,
+    // When the player's chat messages within the last `CHAT_WINDOW` were
+    // passed on, oldest first.
+    chatted: VecDeque<Instant>,
+}
+
+// A connection watching the game.
+struct Spectator {
+    connection: u64,
+    version: u16,
+    stream: TcpStream,
+}
+
+// How a game ended: the winner, if any, and why.
+type Ending = (Option<Player>, &'static str);
+
+const PLAYERS: [Player; 2] = [Player::White, Player::Black];
+
+// Most chat messages a player may send in any `CHAT_WINDOW`.
+const CHAT_BURST: usize = 5;
+const CHAT_WINDOW: Duration = Duration::from_secs(10);
+
+/// Host runs a game for two seated players and any number of spectators.
+pub struct Host {
+    id: u64,
+    game: Game,
+    settings: GameSettings,
+    config: ServerConfig,
+    lobby: Arc<Mutex<Lobby>>,
+    seats: [Option<Seat>; 2],
+    spectators: Vec<Spectator>,
+    // Every position the game has been in and the side to move in it, from
+    // which spectators are shown the game behind play.
+    positions: Vec<(Player, Board)>,
+    // Set once both seats have first been filled.
+    started: bool,
+    clocks: Option<Clocks>,
+    // Player whose draw offer stands.
+    offer: Option<Player>,
+    // Connections seated so far, numbering each one.
+    connections: u64,
+    // Where everything the game's players do and are shown is recorded.
+    audit: Option<audit::Log>,
+}
+
+impl Host {
+    /// New hosts the game listed in the lobby under the id.
+    pub fn new(
+        id: u64,
+        board: Board,
+        settings: GameSettings,
+        config: ServerConfig,
+        lobby: Arc<Mutex<Lobby>>,
+    ) -> Self {
+        let seed = config
+            .seed
+            .map_or_else(rand::random, |seed| crate::arena::mix(seed, id));
+        let audit = config.audit.as_ref().and_then(|dir| {
+            let started = Entry::Started {
+                game: id,
+                settings: settings.clone(),
+                seed,
+                board: board.clone(),
+                spectator_delay: config.delay_for(&settings),
+            };
+            // Game ids start again with the server, so name logs by when
+            // they were started too.
+            let since = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            let path = dir.join(format!("game-{}-{}.log", since, id));
+            match audit::Log::create(&path, started) {
+                Ok(log) => Some(log),
+                Err(err) => {
+                    println!("game {} will not be audited: {}", id, err);
+                    None
+                }
+            }
+        });
+        let game = Game::headless(board, !settings.fog, seed);
+        let positions = vec![(game.state.turn, game.state.board.clone())];
+        Host {
+            id,
+            game,
+            settings,
+            config,
+            lobby,
+            seats: [None, None],
+            spectators: vec![],
+            positions,
+            started: false,
+            clocks: None,
+            offer: None,
+            connections: 0,
+            audit,
+        }
+    }
+
+    /// Run plays the game out, then takes it out of the lobby.
+    pub fn run(mut self, inbox: Receiver<Event>, events: Sender<Event>) {
+        if let Some(ending) = self.play(&inbox, &events) {
+            self.finish(ending);
+        }
+        lock(&self.lobby).tables.remove(&self.id);
+    }
+
+    // Handle events until the game ends, returning how it did.
+    fn play(&mut self, inbox: &Receiver<Event>, events: &Sender<Event>) -> Option<Ending> {
+        loop {
+            let event = match self.deadline() {
+                Some(deadline) => {
+                    match inbox.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                        Ok(event) => Some(event),
+                        Err(RecvTimeoutError::Timeout) => None,
+                        Err(RecvTimeoutError::Disconnected) => return None,
+                    }
+                }
+                None => match inbox.recv() {
+                    Ok(event) => Some(event),
+                    Err(_) => return None,
+                },
+            };
+            if let Some(ending) = self.expired() {
+                return Some(ending);
+            }
+            let ending = match event {
+                Some(Event::Arrived(arrival)) => {
+                    self.arrive(arrival, events);
+                    None
+                }
+                Some(Event::Message(player, connection, message))
+                    if self.current(player, connection) =>
+                {
+                    self.receive(player, message)
+                }
+                Some(Event::Malformed(player, connection, reason))
+                    if self.current(player, connection) =>
+                {
+                    let rejected = Message::MoveRejected {
+                        moves: vec![],
+                        reason,
+                    };
+                    self.send(player, &rejected);
+                    None
+                }
+                Some(Event::Left(player, connection)) if self.current(player, connection) => {
+                    self.leave(player)
+                }
+                Some(Event::Unwatched(connection)) => {
+                    self.spectators
+                        .retain(|spectator| spectator.connection != connection);
+                    self.count_spectators();
+                    None
+                }
+                // Left over from a connection that has since been replaced.
+                _ => None,
+            };
+            if ending.is_some() {
+                return ending;
+            }
+        }
+    }
+
+    // When the game loop must next wake up on its own: when the running
+    // clock flags, or an absent player runs out of time to resume.
+    fn deadline(&self) -> Option<Instant> {
+        let flag = self.clocks.as_ref().and_then(|clocks| {
+            clocks
+                .running
+                .map(|player| Instant::now() + clocks.left(player))
+        });
+        let abandon = self
+            .seats
+            .iter()
+            .flatten()
+            .filter_map(|seat| seat.away)
+            .map(|away| away + self.config.reconnect_timeout);
+        flag.into_iter().chain(abandon).min()
+    }
+
+    // Whether the game has been lost on time or by abandonment.
+    fn expired(&self) -> Option<Ending> {
+        if let Some(clocks) = &self.clocks {
+            if let Some(player) = clocks.running {
+                if clocks.left(player) == Duration::from_secs(0) {
+                    return Some((Some(player.opponent()), "time"));
+                }
+            }
+        }
+        for (player, seat) in PLAYERS.iter().zip(self.seats.iter()) {
+            if let Some(away) = seat.as_ref().and_then(|seat| seat.away) {
+                if away.elapsed() >= self.config.reconnect_timeout {
+                    return Some((Some(player.opponent()), "abandoned"));
+                }
+            }
+        }
+        None
+    }
+
+    // Whether the connection is the one the player is seated on.
+    fn current(&self, player: Player, connection: u64) -> bool {
+        self.seats[player.index()]
+            .as_ref()
+            .is_some_and(|seat| seat.stream.is_some() && seat.connection == connection)
+    }
+
+    // Seat a newly arrived client, either in a free seat or, with a valid
+    // session token, back in the seat it left, or add it to the spectators.
+    fn arrive(&mut self, arrival: Arrival, events: &Sender<Event>) {
+        let Arrival {
+            stream,
+            version,
+            request,
+        } = arrival;
+        let id = self.id;
+        let seats = &mut self.seats;
+        let player = match request {
+            Message::Spectate { .. } => return self.watch(stream, version, events),
+            Message::Join { name, .. }
+            | Message::JoinGame { name, .. }
+            | Message::CreateGame { name, .. }
+            | Message::CreateChatGame { name, .. } => {
+                let player = match PLAYERS
+                    .iter()
+                    .find(|player| seats[player.index()].is_none())
+                {
+                    Some(player) => *player,
+                    None => return refuse(&stream, "the game is full"),
+                };
+                let name = match name.trim() {
+                    "" => format!("{:?}", player),
+                    name => name.into(),
+                };
+                seats[player.index()] = Some(Seat {
+                    name,
+                    token: format!(
+                        "{}-{:016x}{:016x}",
+                        id,
+                        rand::random::<u64>(),
+                        rand::random::<u64>()
+                    ),
+                    version,
+                    stream: None,
+                    connection: 0,
+                    addr: None,
+                    away: None,
+                    chatted: VecDeque::new(),
+                });
+                player
+            }
+            Message::Resume { token } => match PLAYERS.iter().find(|player| {
+                seats[player.index()]
+                    .as_ref()
+                    .is_some_and(|seat| seat.token == token)
+            }) {
+                Some(player) => *player,
+                None => return refuse(&stream, "unknown session"),
+            },
+            _ => return,
+        };
+        self.connections += 1;
+        let seat = self.seats[player.index()]
+            .as_mut()
+            .expect("seat was just found or taken");
+        if let Some(old) = seat.stream.take() {
+            let _ = old.shutdown(Shutdown::Both);
+        }
+        let resumed = seat.away.take().is_some();
+        seat.version = version;
+        seat.connection = self.connections;
+        seat.addr = stream.peer_addr().ok().map(|addr| addr.ip());
+        let mut greeting = vec![Message::Join {
+            name: seat.name.clone(),
+            player: Some(player),
+        }];
+        if version >= protocol::RESUME_VERSION {
+            greeting.push(Message::Session {
+                token: seat.token.clone(),
+            });
+        }
+        if version >= protocol::SPECTATOR_VERSION {
+            greeting.push(Message::Spectators {
+                count: self.spectators.len() as u32,
+            });
+        }
+        for message in greeting.iter() {
+            let _ = write_frame(&stream, message);
+        }
+        match stream.try_clone() {
+            Ok(reader) => listen(player, self.connections, reader, events.clone()),
+            Err(err) => println!("listening to {}: {}", seat.name, err),
+        }
+        seat.stream = Some(stream);
+        let seated = Entry::Seated {
+            player,
+            name: seat.name.clone(),
+        };
+        println!(
+            "{} {} game {} as {:?}",
+            seat.name,
+            if resumed { "resumed" } else { "joined" },
+            id,
+            player
+        );
+        self.record(seated);
+        self.publish();
+        if !self.started && self.seats.iter().all(Option::is_some) {
+            self.started = true;
+            self.publish();
+            self.clocks = self
+                .settings
+                .time_control
+                .map(|control| Clocks::new(control, self.game.state.turn));
+            for player in PLAYERS.iter() {
+                self.update(*player);
+            }
+        } else if self.started {
+            if self.seats.iter().flatten().all(|seat| seat.away.is_none()) {
+                if let Some(clocks) = &mut self.clocks {
+                    clocks.resume(self.game.state.turn);
+                }
+            }
+            self.update(player);
+        }
+    }
+
+    // Add a spectator, showing them the game so far. Players may not watch
+    // their own game, which would show them the whole board.
+    fn watch(&mut self, stream: TcpStream, version: u16, events: &Sender<Event>) {
+        let addr = stream.peer_addr().ok().map(|addr| addr.ip());
+        if addr.is_some() && self.seats.iter().flatten().any(|seat| seat.addr == addr) {
+            return refuse(&stream, "players cannot watch their own game");
+        }
+        self.connections += 1;
+        let connection = self.connections;
+        let _ = write_frame(&stream, &Message::Spectate { game: self.id });
+        match stream.try_clone() {
+            Ok(mut reader) => {
+                let events = events.clone();
+                // Spectators have nothing to say, so read only to notice
+                // them leave.
+                thread::spawn(move || {
+                    let _ = io::copy(&mut reader, &mut io::sink());
+                    let _ = events.send(Event::Unwatched(connection));
+                });
+            }
+            Err(err) => println!("watching game {}: {}", self.id, err),
+        }
+        let spectator = Spectator {
+            connection,
+            version,
+            stream,
+        };
+        self.show(Some(&spectator));
+        self.spectators.push(spectator);
+        self.count_spectators();
+    }
+
+    // Keep the seat of a disconnected player for them to resume, or free it
+    // if the game has not yet started, abandoning the game once nobody is
+    // left in it.
+    fn leave(&mut self, player: Player) -> Option<Ending> {
+        self.record(Entry::Left { player });
+        if !self.started {
+            self.seats[player.index()] = None;
+            println!("{:?} left game {} before it started", player, self.id);
+            self.publish();
+            if self.seats.iter().all(Option::is_none) {
+                return Some((None, "abandoned"));
+            }
+            return None;
+        }
+        if let Some(seat) = &mut self.seats[player.index()] {
+            seat.stream = None;
+            seat.away = Some(Instant::now());
+            println!(
+                "{} disconnected, {}s to resume",
+                seat.name,
+                self.config.reconnect_timeout.as_secs()
+            );
+        }
+        if self.config.pause_clock {
+            if let Some(clocks) = &mut self.clocks {
+                clocks.pause();
+            }
+        }
+        None
+    }
+
+    // Whether the player may send another chat message now, counting it if
+    // so.
+    fn may_chat(&mut self, player: Player) -> bool {
+        let seat = match &mut self.seats[player.index()] {
+            Some(seat) => seat,
+            None => return false,
+        };
+        while seat
+            .chatted
+            .front()
+            .is_some_and(|sent| sent.elapsed() >= CHAT_WINDOW)
+        {
+            seat.chatted.pop_front();
+        }
+        if seat.chatted.len() >= CHAT_BURST {
+            return false;
+        }
+        seat.chatted.push_back(Instant::now());
+        true
+    }
+
+    // Act on a message from a seated player, returning how the game ended if
+    // it did.
+    fn receive(&mut self, player: Player, message: Message) -> Option<Ending> {
+        match message {
+            Message::Move { moves } => {
+                let reason = if self.game.state.turn != player || !self.started {
+                    "not your turn"
+                } else if self.game.apply(&Step {
+                    player,
+                    moves: moves.clone(),
+                }) {
+                    // Record the ply as the engine played it, not as sent.
+                    let played = self
+                        .game
+                        .state
+                        .history
+                        .last()
+                        .map(|step| step.moves.clone());
+                    self.record(Entry::Moved {
+                        player,
+                        moves: played.unwrap_or(moves),
+                    });
+                    if let (Some(clocks), Some(control)) =
+                        (&mut self.clocks, self.settings.time_control)
+                    {
+                        clocks.press(player, control.increment);
+                        if self.config.pause_clock
+                            && self.seats.iter().flatten().any(|seat| seat.away.is_some())
+                        {
+                            clocks.pause();
+                        }
+                    }
+                    self.offer = None;
+                    self.positions
+                        .push((self.game.state.turn, self.game.state.board.clone()));
+                    for player in PLAYERS.iter() {
+                        self.update(*player);
+                    }
+                    self.show(None);
+                    return self
+                        .game
+                        .winner()
+                        .map(|winner| (Some(winner), "king captured"));
+                } else {
+                    "illegal move"
+                };
+                self.record(Entry::Rejected {
+                    player,
+                    moves: moves.clone(),
+                    reason: reason.into(),
+                });
+                let rejected = Message::MoveRejected {
+                    moves,
+                    reason: reason.into(),
+                };
+                self.send(player, &rejected);
+            }
+            Message::Chat { text, .. } => {
+                let refusal = if !self.settings.chat {
+                    Some("chat is disabled in this game".to_string())
+                } else if !self.may_chat(player) {
+                    Some(format!(
+                        "slow down, at most {} messages every {} seconds",
+                        CHAT_BURST,
+                        CHAT_WINDOW.as_secs()
+                    ))
+                } else {
+                    None
+                };
+                if let Some(text) = refusal {
+                    let notice = Message::Chat {
+                        from: String::new(),
+                        text,
+                    };
+                    self.send(player, &notice);
+                    return None;
+                }
+                let chat = Message::Chat {
+                    from: self.seats[player.index()]
+                        .as_ref()
+                        .map_or_else(String::new, |seat| seat.name.clone()),
+                    text: text.chars().take(protocol::MAX_CHAT).collect(),
+                };
+                for player in PLAYERS.iter() {
+                    self.send(*player, &chat);
+                }
+            }
+            Message::Resign => return Some((Some(player.opponent()), "resignation")),
+            Message::DrawOffer { .. } => {
+                if self.offer == Some(player.opponent()) {
+                    return Some((None, "agreement"));
+                }
+                self.offer = Some(player);
+                self.send(player.opponent(), &Message::DrawOffer { player });
+            }
+            _ => println!("ignoring unexpected message from {:?}", player),
+        }
+        None
+    }
+
+    // Send a message to a player if they are connected. A failed write shows
+    // up as the connection leaving, so it needs no handling here.
+    fn send(&self, player: Player, message: &Message) {
+        if let Some(stream) = self.seats[player.index()]
+            .as_ref()
+            .and_then(|seat| seat.stream.as_ref())
+        {
+            let _ = write_frame(stream, message);
+        }
+    }
+
+    // Send a player their view, and the clocks in a timed game.
+    fn update(&mut self, player: Player) {
+        let view = redact(self.game.view_for(&player));
+        self.record(Entry::Sent {
+            player,
+            ply: view.ply,
+            board: view.board.clone(),
+        });
+        self.send(player, &Message::Update(Box::new(view)));
+        if let Some(clocks) = &self.clocks {
+            let clock = clocks.message();
+            if let Message::Clock {
+                remaining_ms,
+                running,
+            } = clock
+            {
+                self.record(Entry::Clock {
+                    remaining_ms,
+                    running,
+                });
+            }
+            self.send(player, &clock);
+        }
+    }
+
+    // Append to the game's audit log, if it has one. A log that cannot be
+    // written to is given up on rather than left with gaps.
+    fn record(&mut self, entry: Entry) {
+        if let Some(log) = &mut self.audit {
+            if let Err(err) = log.record(entry) {
+                println!("game {} is no longer audited: {}", self.id, err);
+                self.audit = None;
+            }
+        }
+    }
+
+    // Send spectators, or just the one given, the whole board as it stood
+    // the configured delay behind play, and the clocks in a timed game.
+    fn show(&mut self, only: Option<&Spectator>) {
+        let delay = self.config.delay_for(&self.settings);
+        let ply = (self.positions.len() - 1).saturating_sub(delay as usize);
+        let (turn, board) = self.positions[ply].clone();
+        self.record(Entry::Shown {
+            ply: ply as u32,
+            delay,
+            board: board.clone(),
+        });
+        let mut messages = vec![Message::Position {
+            ply: ply as u32,
+            turn,
+            board: Box::new(board),
+        }];
+        if let Some(clocks) = &self.clocks {
+            messages.push(clocks.message());
+        }
+        let spectators = match only {
+            Some(spectator) => std::slice::from_ref(spectator),
+            None => &self.spectators,
+        };
+        for spectator in spectators {
+            for message in messages.iter() {
+                let _ = write_frame(&spectator.stream, message);
+            }
+        }
+    }
+
+    // Tell everyone in the game how many are watching it.
+    fn count_spectators(&self) {
+        let count = Message::Spectators {
+            count: self.spectators.len() as u32,
+        };
+        for seat in self.seats.iter().flatten() {
+            if seat.version >= protocol::SPECTATOR_VERSION {
+                if let Some(stream) = &seat.stream {
+                    let _ = write_frame(stream, &count);
+                }
+            }
+        }
+        for spectator in self.spectators.iter() {
+            if spectator.version >= protocol::SPECTATOR_VERSION {
+                let _ = write_frame(&spectator.stream, &count);
+            }
+        }
+    }
+
+    // Show the seated players in the lobby's listing.
+    fn publish(&self) {
+        let name = |player: usize| self.seats[player].as_ref().map(|seat| seat.name.clone());
+        if let Some(table) = lock(&self.lobby).tables.get_mut(&self.id) {
+            table.info.players = [name(0), name(1)];
+            table.info.started = self.started;
+        }
+    }
+
+    // Announce the end of the game to everyone connected, showing
+    // spectators the final position now there is nothing left to hide.
+    fn finish(&mut self, (winner, reason): Ending) {
+        println!("game {} over: {}", self.id, reason);
+        self.record(Entry::Ended {
+            winner,
+            reason: reason.into(),
+        });
+        if let Some(log) = &self.audit {
+            println!("game {} audit log ends with {}", self.id, log.last_hash());
+        }
+        let over = Message::GameOver {
+            winner,
+            reason: reason.into(),
+        };
+        for player in PLAYERS.iter() {
+            self.send(*player, &over);
+        }
+        let position = Message::Position {
+            ply: self.game.state.ply,
+            turn: self.game.state.turn,
+            board: Box::new(self.game.state.board.clone()),
+        };
+        for spectator in self.spectators.iter() {
+            let _ = write_frame(&spectator.stream, &position);
+            let _ = write_frame(&spectator.stream, &over);
+        }
+    }
+}
+
+// Forward messages from a client connection to the game loop until it
+// closes.
+fn listen(player: Player, connection: u64, stream: TcpStream, events: Sender<Event>) {
+    thread::spawn(move || {
+        let mut reader = BufReader::new(stream);
+        loop {
+            let event = match read_frame(&mut reader) {
+                Ok(Some(message)) => Event::Message(player, connection, message),
+                Ok(None) => Event::Left(player, connection),
+                Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                    Event::Malformed(player, connection, format!("malformed message: {}", err))
+                }
+                Err(_) => Event::Left(player, connection),
+            };
+            let left = matches!(event, Event::Left(..));
+            if events.send(event).is_err() || left {
+                return;
+            }
+        }
+    });
+}
+
+// Redact strips what the view's board would otherwise give away about enemy
+// pieces beyond where they stand.
+fn redact(mut view: PlayerView) -> PlayerView {
+    let mut board = Board::default();
+    for y in 0..8 {
+        for x in 0..8 {
+            if let Some(mut piece) = view.board.get((x, y)).cloned() {
+                if piece.player != view.player {
+                    piece.moved = 0;
+                }
+                board.set((x, y), piece);
+            }
+        }
+    }
+    view.board = board;
+    view
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    // Host a game on the board with both seats filled, returning the host and
+    // each player's end of their connection.
+    fn seated(board: Board) -> (Host, [TcpStream; 2]) {
+        timed(board, None, ServerConfig::default())
+    }
+
+    // Host a game as `seated` does, on the clock if given a time control.
+    fn timed(board: Board, control: Option<&str>, config: ServerConfig) -> (Host, [TcpStream; 2]) {
+        let lobby = Arc::new(Mutex::new(Lobby::default()));
+        let settings = GameSettings {
+            time_control: control.map(|control| protocol::TimeControl::parse(control).unwrap()),
+            ..GameSettings::default()
+        };
+        let mut host = Host::new(1, board, settings, config, lobby);
+        let clients = [
+            arrive(
+                &mut host,
+                Message::Join {
+                    name: "anna".into(),
+                    player: None,
+                },
+            ),
+            arrive(
+                &mut host,
+                Message::Join {
+                    name: "ben".into(),
+                    player: None,
+                },
+            ),
+        ];
+        (host, clients)
+    }
+
+    // Connect a client and pass its connection to the host with the request.
+    fn arrive(host: &mut Host, request: Message) -> TcpStream {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let (events, _inbox) = mpsc::channel();
+        let arrival = Arrival {
+            stream,
+            version: protocol::VERSION,
+            request,
+        };
+        host.arrive(arrival, &events);
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        client
+    }
+
+    // Read what the client is sent until the connection goes quiet.
+    fn received(client: &TcpStream) -> Vec<Message> {
+        client
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        let mut messages = vec![];
+        while let Ok(Some(message)) = read_frame(client) {
+            messages.push(message);
+        }
+        messages
+    }
+
+    // Read what the client is sent until its move is rejected, returning why.
+    fn rejection(client: &TcpStream) -> String {
+        loop {
+            match read_frame(client).unwrap() {
+                Some(Message::MoveRejected { reason, .. }) => return reason,
+                Some(_) => continue,
+                None => panic!("the connection closed"),
+            }
+        }
+    }
+
+    // Connect a client and pass its connection to the host as a spectator.
+    fn spectate(host: &mut Host) -> TcpStream {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let (events, _inbox) = mpsc::channel();
+        host.watch(stream, protocol::VERSION, &events);
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        client
+    }
+
+    #[test]
+    fn players_cannot_watch_their_own_game() {
+        let (mut host, _clients) = seated(Board::new());
+        let client = spectate(&mut host);
+        let refusal = match read_frame(&client).unwrap() {
+            Some(Message::GameOver { reason, .. }) => reason,
+            _ => panic!("expected a refusal"),
+        };
+        assert_eq!(refusal, "players cannot watch their own game");
+        assert!(host.spectators.is_empty());
+    }
+
+    #[test]
+    fn spectators_of_fog_games_are_kept_behind_play() {
+        let (mut host, _clients) = seated(Board::new());
+        // Seat the players somewhere else, so this address may watch.
+        for seat in host.seats.iter_mut().flatten() {
+            seat.addr = Some(IpAddr::from([192, 0, 2, 1]));
+        }
+        let client = spectate(&mut host);
+        let knights = [
+            ((6, 0), (5, 2)),
+            ((6, 7), (5, 5)),
+            ((5, 2), (6, 0)),
+            ((5, 5), (6, 7)),
+        ];
+        let position = || loop {
+            match read_frame(&client).unwrap() {
+                Some(Message::Position { ply, .. }) => return ply,
+                Some(_) => continue,
+                None => panic!("the connection closed"),
+            }
+        };
+        assert_eq!(position(), 0);
+        for mv in knights.iter().cycle().take(14) {
+            let player = host.game.state.turn;
+            host.receive(player, Message::Move { moves: vec![*mv] });
+            let ply = position();
+            let current = host.game.state.ply;
+            assert!(ply < current);
+            assert_eq!(ply, current.saturating_sub(crate::net::FOG_SPECTATOR_DELAY));
+        }
+        assert_eq!(host.game.state.ply, 14);
+    }
+
+    #[test]
+    fn castles_are_played_as_the_rules_have_them() {
+        let (mut host, clients) = seated(Board::variant("castle").unwrap());
+        let castle = vec![((3, 0), (1, 0)), ((0, 0), (2, 0))];
+        // The rook may not be sent anywhere but beside the king.
+        let forged = vec![((3, 0), (1, 0)), ((0, 0), (0, 7))];
+        host.receive(Player::White, Message::Move { moves: forged });
+        assert_eq!(rejection(&clients[0]), "illegal move");
+        assert!(host.game.state.history.is_empty());
+        host.receive(Player::White, Message::Move { moves: castle });
+        assert_eq!(host.game.state.history.len(), 1);
+        let board = &host.game.state.board;
+        assert!(matches!(board.get((1, 0)), Some(piece) if piece.unit == crate::Unit::King));
+        assert!(matches!(board.get((2, 0)), Some(piece) if piece.unit == crate::Unit::Rook));
+    }
+
+    #[test]
+    fn malformed_castles_are_rejected() {
+        let (mut host, clients) = seated(Board::variant("castle").unwrap());
+        let malformed = [
+            // Rook first.
+            vec![((0, 0), (2, 0)), ((3, 0), (1, 0))],
+            // The king twice.
+            vec![((3, 0), (1, 0)), ((3, 0), (1, 0))],
+            // A third move on top of the castle.
+            vec![((3, 0), (1, 0)), ((0, 0), (2, 0)), ((1, 0), (1, 1))],
+            vec![],
+        ];
+        for moves in malformed.iter() {
+            host.receive(
+                Player::White,
+                Message::Move {
+                    moves: moves.clone(),
+                },
+            );
+            assert_eq!(rejection(&clients[0]), "illegal move", "{:?}", moves);
+        }
+        assert!(host.game.state.history.is_empty());
+    }
+
+    #[test]
+    fn players_resume_their_seat_with_its_token() {
+        let (mut host, _clients) = timed(Board::new(), Some("5+0"), ServerConfig::default());
+        let token = host.seats[0].as_ref().unwrap().token.clone();
+        host.leave(Player::White);
+        let client = arrive(&mut host, Message::Resume { token });
+        let messages = received(&client);
+        assert!(matches!(
+            messages.first(),
+            Some(Message::Join {
+                player: Some(Player::White),
+                ..
+            })
+        ));
+        assert!(messages.iter().any(|message| matches!(
+            message,
+            Message::Update(view) if view.player == Player::White
+        )));
+        assert!(messages
+            .iter()
+            .any(|message| matches!(message, Message::Clock { .. })));
+        assert!(host.seats[0].as_ref().unwrap().away.is_none());
+        assert!(host.expired().is_none());
+    }
+
+    #[test]
+    fn unknown_tokens_are_refused() {
+        let (mut host, _clients) = seated(Board::new());
+        host.leave(Player::White);
+        let client = arrive(
+            &mut host,
+            Message::Resume {
+                token: "1-0123456789abcdef".into(),
+            },
+        );
+        match read_frame(&client).unwrap() {
+            Some(Message::GameOver { reason, .. }) => assert_eq!(reason, "unknown session"),
+            _ => panic!("expected a refusal"),
+        }
+        assert!(host.seats[0].as_ref().unwrap().away.is_some());
+    }
+
+    #[test]
+    fn players_who_never_return_forfeit() {
+        let config = ServerConfig {
+            reconnect_timeout: Duration::from_millis(50),
+            ..ServerConfig::default()
+        };
+        let (mut host, _clients) = timed(Board::new(), None, config);
+        host.leave(Player::Black);
+        assert!(host.expired().is_none());
+        assert!(host.deadline().is_some());
+        thread::sleep(Duration::from_millis(60));
+        assert_eq!(host.expired(), Some((Some(Player::White), "abandoned")));
+    }
+
+    #[test]
+    fn clocks_pause_while_a_player_is_away() {
+        let config = ServerConfig {
+            pause_clock: true,
+            ..ServerConfig::default()
+        };
+        let (mut host, _clients) = timed(Board::new(), Some("5+0"), config);
+        let running = |host: &Host| host.clocks.as_ref().unwrap().running;
+        assert_eq!(running(&host), Some(Player::White));
+        let token = host.seats[0].as_ref().unwrap().token.clone();
+        host.leave(Player::White);
+        assert_eq!(running(&host), None);
+        arrive(&mut host, Message::Resume { token });
+        assert_eq!(running(&host), Some(Player::White));
+
+        // Without pausing, the absent player's clock runs on.
+        let (mut host, _clients) = timed(Board::new(), Some("5+0"), ServerConfig::default());
+        host.leave(Player::White);
+        assert_eq!(running(&host), Some(Player::White));
+    }
+
+    // Chat lines among the messages, as (from, text).
+    fn chats(messages: Vec<Message>) -> Vec<(String, String)> {
+        messages
+            .into_iter()
+            .filter_map(|message| match message {
+                Message::Chat { from, text } => Some((from, text)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn chat_is_limited_to_bursts() {
+        let (mut host, clients) = seated(Board::new());
+        received(&clients[0]);
+        received(&clients[1]);
+        let say = |host: &mut Host, text: &str| {
+            let text = text.into();
+            host.receive(
+                Player::White,
+                Message::Chat {
+                    from: "".into(),
+                    text,
+                },
+            );
+        };
+        for _ in 0..CHAT_BURST {
+            say(&mut host, "hi");
+        }
+        let passed = vec![("anna".to_string(), "hi".to_string()); CHAT_BURST];
+        assert_eq!(chats(received(&clients[1])), passed);
+        say(&mut host, "hi");
+        let refused = chats(received(&clients[0]));
+        assert_eq!(refused.len(), CHAT_BURST + 1);
+        assert_eq!(
+            refused[CHAT_BURST],
+            (
+                String::new(),
+                "slow down, at most 5 messages every 10 seconds".to_string()
+            )
+        );
+        assert!(chats(received(&clients[1])).is_empty());
+        // Once the window has passed the player may talk again, and long
+        // messages are cut short.
+        let seat = host.seats[0].as_mut().unwrap();
+        for sent in seat.chatted.iter_mut() {
+            *sent -= CHAT_WINDOW;
+        }
+        say(&mut host, &"a".repeat(protocol::MAX_CHAT + 10));
+        let passed = chats(received(&clients[1]));
+        assert_eq!(passed.len(), 1);
+        assert_eq!(passed[0].1.len(), protocol::MAX_CHAT);
+    }
+
+    #[test]
+    fn chat_is_refused_in_games_without_it() {
+        let (mut host, clients) = seated(Board::new());
+        host.settings.chat = false;
+        received(&clients[0]);
+        received(&clients[1]);
+        let text = "hi".into();
+        host.receive(
+            Player::White,
+            Message::Chat {
+                from: "".into(),
+                text,
+            },
+        );
+        let refusal = (String::new(), "chat is disabled in this game".to_string());
+        assert_eq!(chats(received(&clients[0])), vec![refusal]);
+        assert!(chats(received(&clients[1])).is_empty());
+    }
+}
@@ -0,0 +1,470 @@
+//! Lobby admits connections to the server and routes each one to the game it
+//! asks for, creating games on request.
+
+use super::host::{Arrival, Event, Host};
+use super::{web, ServerConfig, AGENT, HANDSHAKE_TIMEOUT};
+use crate::protocol::{self, read_frame, write_frame, GameInfo, GameSettings, Message};
+use crate::Board;
+use std::collections::BTreeMap;
+use std::io;
+use std::net::{Ipv4Addr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, SendError, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+
+/// Lobby is the directory of games hosted by the server.
+#[derive(Default)]
+pub struct Lobby {
+    // Id given to the next game created.
+    next: u64,
+    pub tables: BTreeMap<u64, Table>,
+}
+
+/// Table is a hosted game as the lobby sees it.
+pub struct Table {
+    pub info: GameInfo,
+    events: Sender<Event>,
+}
+
+/// Lock the lobby, carrying on past a game thread that panicked while
+/// holding it.
+pub fn lock(lobby: &Mutex<Lobby>) -> MutexGuard<'_, Lobby> {
+    lobby
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Serve hosts games for any number of clients, each game on its own thread,
+/// and the browser client too if the config asks for it. Runs until the
+/// listener fails.
+pub fn serve(addr: impl ToSocketAddrs, config: ServerConfig) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    println!("listening on {}", listener.local_addr()?);
+    if let Some(web) = &config.web {
+        let web = TcpListener::bind(web)?;
+        println!("serving the browser client on http://{}", web.local_addr()?);
+        let mut upstream = listener.local_addr()?;
+        if upstream.ip().is_unspecified() {
+            upstream.set_ip(Ipv4Addr::LOCALHOST.into());
+        }
+        thread::spawn(move || {
+            if let Err(err) = web::bridge(web, upstream) {
+                println!("browser client stopped: {}", err);
+            }
+        });
+    }
+    host(listener, config)
+}
+
+/// Host admits clients from the listener to the games they ask for, until
+/// the listener fails.
+pub fn host(listener: TcpListener, config: ServerConfig) -> io::Result<()> {
+    let lobby = Arc::new(Mutex::new(Lobby::default()));
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let (lobby, config) = (lobby.clone(), config.clone());
+        // Admit each connection on its own thread, so a slow client holds up
+        // nobody else.
+        thread::spawn(move || {
+            if let Err(err) = admit(&stream, &lobby, &config) {
+                match stream.peer_addr() {
+                    Ok(peer) => println!("{} failed to join: {}", peer, err),
+                    Err(_) => println!("a client failed to join: {}", err),
+                }
+            }
+        });
+    }
+    Ok(())
+}
+
+// Greet a new connection, answer its questions about the games on offer and
+// pass it to the game it chooses.
+fn admit(stream: &TcpStream, lobby: &Arc<Mutex<Lobby>>, config: &ServerConfig) -> io::Result<()> {
+    let version = greet(stream)?;
+    let invalid = |reason: &str| io::Error::new(io::ErrorKind::InvalidData, reason);
+    let lobbying = version >= protocol::LOBBY_VERSION;
+    loop {
+        let request = match read_frame(stream)? {
+            Some(request) => request,
+            // Leaving after looking at the games is fine.
+            None => return Ok(()),
+        };
+        let game = match &request {
+            Message::ListGames if lobbying => {
+                let games: Vec<_> = lock(lobby)
+                    .tables
+                    .values()
+                    .map(|table| table.info.clone())
+                    .collect();
+                let listing = if version >= protocol::CHAT_VERSION {
+                    Message::ChatGames {
+                        chat: games.iter().map(|info| info.settings.chat).collect(),
+                        games,
+                    }
+                } else {
+                    Message::Games { games }
+                };
+                write_frame(stream, &listing)?;
+                continue;
+            }
+            Message::CreateGame { settings, .. } | Message::CreateChatGame { settings, .. }
+                if lobbying =>
+            {
+                let mut settings = settings.clone();
+                if let Message::CreateChatGame { chat, .. } = &request {
+                    settings.chat = *chat;
+                }
+                match Board::variant(&settings.variant) {
+                    Some(board) => create(lobby, config, board, settings),
+                    None => {
+                        refuse(stream, &format!("unknown variant {:?}", settings.variant));
+                        return Err(invalid("unknown variant"));
+                    }
+                }
+            }
+            // Watching a fog game as it is played would show the whole board
+            // to anyone, players included.
+            Message::Spectate { game } if lobbying => {
+                let unsafe_to_watch = lock(lobby).tables.get(game).is_some_and(|table| {
+                    table.info.settings.fog && config.delay_for(&table.info.settings) == 0
+                });
+                if unsafe_to_watch {
+                    let reason = "fog games cannot be watched without a spectator delay";
+                    refuse(stream, reason);
+                    return Err(invalid(reason));
+                }
+                *game
+            }
+            Message::JoinGame { game, .. } if lobbying => *game,
+            Message::Join { .. } => {
+                let open = lock(lobby)
+                    .tables
+                    .values()
+                    .find(|table| {
+                        !table.info.started && table.info.players.iter().any(Option::is_none)
+                    })
+                    .map(|table| table.info.id);
+                match open {
+                    Some(game) => game,
+                    None => {
+                        let settings = config.defaults.clone();
+                        let board = Board::variant(&settings.variant)
+                            .ok_or_else(|| invalid("unknown default variant"))?;
+                        create(lobby, config, board, settings)
+                    }
+                }
+            }
+            // Tokens start with the id of the game they were issued for.
+            Message::Resume { token } if version >= protocol::RESUME_VERSION => {
+                match token.split('-').next().and_then(|id| id.parse().ok()) {
+                    Some(game) => game,
+                    None => {
+                        refuse(stream, "unknown session");
+                        return Err(invalid("unknown session"));
+                    }
+                }
+            }
+            _ => return Err(invalid("expected a game to join")),
+        };
+        let arrival = Arrival {
+            stream: stream.try_clone()?,
+            version,
+            request,
+        };
+        return route(lobby, game, arrival);
+    }
+}
+
+// Negotiate a protocol version with a newly connected client.
+fn greet(stream: &TcpStream) -> io::Result<u16> {
+    let invalid = |reason: &str| io::Error::new(io::ErrorKind::InvalidData, reason);
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let version = match read_frame(stream)? {
+        Some(Message::Hello {
+            min_version,
+            max_version,
+            ..
+        }) => protocol::negotiate(min_version, max_version),
+        _ => return Err(invalid("expected a hello")),
+    };
+    let version = match version {
+        Some(version) => version,
+        None => {
+            let reason = format!(
+                "no common protocol version, the server speaks {} to {}",
+                protocol::MIN_VERSION,
+                protocol::VERSION
+            );
+            refuse(stream, &reason);
+            return Err(invalid(&reason));
+        }
+    };
+    let hello = Message::Hello {
+        min_version: version,
+        max_version: version,
+        agent: AGENT.into(),
+    };
+    write_frame(stream, &hello)?;
+    stream.set_read_timeout(None)?;
+    Ok(version)
+}
+
+// Start hosting a game, returning its id.
+fn create(
+    lobby: &Arc<Mutex<Lobby>>,
+    config: &ServerConfig,
+    board: Board,
+    settings: GameSettings,
+) -> u64 {
+    let (events, inbox) = mpsc::channel();
+    let id = {
+        let mut directory = lock(lobby);
+        directory.next += 1;
+        let id = directory.next;
+        let info = GameInfo {
+            id,
+            settings: settings.clone(),
+            players: [None, None],
+            started: false,
+        };
+        let table = Table {
+            info,
+            events: events.clone(),
+        };
+        directory.tables.insert(id, table);
+        id
+    };
+    println!("game {} created: {}", id, settings);
+    let (lobby, config) = (lobby.clone(), config.clone());
+    thread::spawn(move || Host::new(id, board, settings, config, lobby).run(inbox, events));
+    id
+}
+
+// Hand a connection over to the game with the id.
+fn route(lobby: &Mutex<Lobby>, game: u64, arrival: Arrival) -> io::Result<()> {
+    let events = lock(lobby)
+        .tables
+        .get(&game)
+        .map(|table| table.events.clone());
+    let unsent = match events {
+        Some(events) => match events.send(Event::Arrived(arrival)) {
+            Ok(()) => return Ok(()),
+            Err(SendError(unsent)) => unsent,
+        },
+        None => Event::Arrived(arrival),
+    };
+    if let Event::Arrived(arrival) = unsent {
+        refuse(&arrival.stream, &format!("no game {}", game));
+    }
+    Err(io::Error::new(io::ErrorKind::NotFound, "no such game"))
+}
+
+/// Refuse turns a client away, telling it why.
+pub fn refuse(stream: &TcpStream, reason: &str) {
+    let over = Message::GameOver {
+        winner: None,
+        reason: reason.into(),
+    };
+    let _ = write_frame(stream, &over);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::{list_games, Remote};
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    fn start() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || host(listener, ServerConfig::default()));
+        addr
+    }
+
+    // Connect speaking only the version given.
+    fn client(addr: SocketAddr, version: u16) -> TcpStream {
+        let stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let hello = Message::Hello {
+            min_version: version,
+            max_version: version,
+            agent: "test".into(),
+        };
+        write_frame(&stream, &hello).unwrap();
+        match read_frame(&stream).unwrap() {
+            Some(Message::Hello { max_version, .. }) => assert_eq!(max_version, version),
+            _ => panic!("expected a hello"),
+        }
+        stream
+    }
+
+    #[test]
+    fn older_clients_still_list_and_create_games() {
+        let addr = start();
+        let old = client(addr, 5);
+        let create = Message::CreateGame {
+            name: "old".into(),
+            settings: GameSettings::default(),
+        };
+        write_frame(&old, &create).unwrap();
+        assert!(matches!(
+            read_frame(&old).unwrap(),
+            Some(Message::Join {
+                player: Some(_),
+                ..
+            })
+        ));
+        let _quiet = Remote::create(
+            addr,
+            "new",
+            GameSettings {
+                chat: false,
+                ..GameSettings::default()
+            },
+        )
+        .unwrap();
+
+        let lister = client(addr, 5);
+        write_frame(&lister, &Message::ListGames).unwrap();
+        match read_frame(&lister).unwrap() {
+            Some(Message::Games { games }) => assert_eq!(games.len(), 2),
+            _ => panic!("expected a list of games"),
+        }
+        let chat: Vec<bool> = list_games(addr)
+            .unwrap()
+            .iter()
+            .map(|info| info.settings.chat)
+            .collect();
+        assert_eq!(chat, vec![true, false]);
+    }
+
+    // Send a request and return why the server turned the client away.
+    fn refusal(addr: SocketAddr, request: Message) -> String {
+        let stream = client(addr, protocol::VERSION);
+        write_frame(&stream, &request).unwrap();
+        match read_frame(&stream).unwrap() {
+            Some(Message::GameOver {
+                winner: None,
+                reason,
+            }) => reason,
+            _ => panic!("expected a refusal"),
+        }
+    }
+
+    // Ask to play, returning the connection once the player is seated.
+    fn seat(addr: SocketAddr, request: Message) -> TcpStream {
+        let stream = client(addr, protocol::VERSION);
+        write_frame(&stream, &request).unwrap();
+        match read_frame(&stream).unwrap() {
+            Some(Message::Join {
+                player: Some(_), ..
+            }) => stream,
+            _ => panic!("expected a seat"),
+        }
+    }
+
+    // How many players sit at each game, by id.
+    fn seated(addr: SocketAddr) -> Vec<(u64, usize)> {
+        list_games(addr)
+            .unwrap()
+            .iter()
+            .map(|info| (info.id, info.players.iter().flatten().count()))
+            .collect()
+    }
+
+    // Wait for the lobby to show this many players seated in all, as games
+    // publish their seats just after greeting the player.
+    fn settle(addr: SocketAddr, players: usize) {
+        for _ in 0..100 {
+            if seated(addr).iter().map(|(_, count)| count).sum::<usize>() == players {
+                return;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        panic!("expected {} players seated", players);
+    }
+
+    #[test]
+    fn games_are_listed() {
+        let addr = start();
+        let stream = client(addr, protocol::VERSION);
+        write_frame(&stream, &Message::ListGames).unwrap();
+        assert!(matches!(
+            read_frame(&stream).unwrap(),
+            Some(Message::ChatGames { games, chat }) if games.is_empty() && chat.is_empty()
+        ));
+        let _anna = seat(
+            addr,
+            Message::CreateGame {
+                name: "anna".into(),
+                settings: GameSettings::default(),
+            },
+        );
+        settle(addr, 1);
+        // The same connection may list again once something changed.
+        write_frame(&stream, &Message::ListGames).unwrap();
+        match read_frame(&stream).unwrap() {
+            Some(Message::ChatGames { games, chat }) => {
+                assert_eq!(games.len(), 1);
+                assert_eq!(games[0].settings.variant, "standard");
+                assert_eq!(games[0].players[0].as_deref(), Some("anna"));
+                assert_eq!(chat, vec![true]);
+            }
+            _ => panic!("expected a list of games"),
+        }
+    }
+
+    #[test]
+    fn unknown_variants_are_refused() {
+        let addr = start();
+        let settings = GameSettings {
+            variant: "nope".into(),
+            ..GameSettings::default()
+        };
+        let create = Message::CreateGame {
+            name: "anna".into(),
+            settings,
+        };
+        assert_eq!(refusal(addr, create), "unknown variant \"nope\"");
+        assert!(seated(addr).is_empty());
+    }
+
+    #[test]
+    fn missing_games_are_refused() {
+        let addr = start();
+        let join = Message::JoinGame {
+            game: 42,
+            name: "anna".into(),
+        };
+        assert_eq!(refusal(addr, join), "no game 42");
+        assert!(seated(addr).is_empty());
+    }
+
+    #[test]
+    fn players_join_the_oldest_open_game() {
+        let addr = start();
+        let create = |name: &str| Message::CreateGame {
+            name: name.into(),
+            settings: GameSettings::default(),
+        };
+        let join = |name: &str| Message::Join {
+            name: name.into(),
+            player: None,
+        };
+        let mut players = vec![];
+        for (count, request) in [create("anna"), create("ben"), join("cleo"), join("dan")]
+            .iter()
+            .enumerate()
+        {
+            players.push(seat(addr, request.clone()));
+            settle(addr, count + 1);
+        }
+        // With every game full a new one is opened.
+        players.push(seat(addr, join("eve")));
+        settle(addr, 5);
+        assert_eq!(seated(addr), vec![(1, 2), (2, 2), (3, 1)]);
+    }
+}
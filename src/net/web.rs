@@ -0,0 +1,281 @@
+//! Web serves the browser client and bridges its WebSocket connections to the
+//! game server. Browsers speak the protocol in `crate::protocol`, but send each
+//! `Message` as JSON in a text message rather than as a binary frame. The
+//! bridge translates between the two, so to the server a browser is just
+//! another client.
+
+use super::{invalid, HANDSHAKE_TIMEOUT};
+use crate::protocol::{read_frame, write_frame, Message};
+use std::io::{self, BufReader, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::{self, TryRecvError};
+use std::thread;
+use std::time::Duration;
+use tungstenite::protocol::frame::coding::CloseCode;
+use tungstenite::protocol::CloseFrame;
+use tungstenite::{Message as Frame, WebSocket};
+
+// The browser client, a single self-contained page.
+const CLIENT: &str = include_str!("../../web/index.html");
+
+// Longest HTTP request head accepted.
+const MAX_HEAD: usize = 8 * 1024;
+
+// How long the bridge waits on the browser before passing on what the server
+// has sent.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Bridge serves the browser client on the listener and carries WebSocket
+/// connections through to the game server at `upstream`. Runs until the
+/// listener fails.
+pub fn bridge(listener: TcpListener, upstream: SocketAddr) -> io::Result<()> {
+    for stream in listener.incoming() {
+        let stream = stream?;
+        thread::spawn(move || {
+            if let Err(err) = visit(stream, upstream) {
+                println!("browser client failed: {}", err);
+            }
+        });
+    }
+    Ok(())
+}
+
+// Answer one HTTP request: upgrade it to a WebSocket, or serve the page.
+fn visit(mut stream: TcpStream, upstream: SocketAddr) -> io::Result<()> {
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let head = peek_head(&stream)?;
+    if head.to_ascii_lowercase().contains("upgrade: websocket") {
+        // The handshake reads the request itself.
+        let socket = tungstenite::accept(stream).map_err(|err| invalid(err.to_string()))?;
+        return relay(socket, upstream);
+    }
+    stream.read_exact(&mut vec![0; head.len()])?;
+    let mut request = head.split_whitespace();
+    let (method, target) = (request.next(), request.next().unwrap_or(""));
+    let path = target.split('?').next().unwrap_or("");
+    let response = if method == Some("GET") && (path == "/" || path == "/index.html") {
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            CLIENT.len(),
+            CLIENT
+        )
+    } else {
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 10\r\nConnection: close\r\n\r\nnot found\n"
+            .into()
+    };
+    stream.write_all(response.as_bytes())?;
+    stream.shutdown(Shutdown::Both)
+}
+
+// Wait for a whole request head to arrive, without consuming it.
+fn peek_head(stream: &TcpStream) -> io::Result<String> {
+    let mut buf = vec![0; MAX_HEAD];
+    loop {
+        let n = stream.peek(&mut buf)?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        if let Some(end) = buf[..n].windows(4).position(|window| window == b"\r\n\r\n") {
+            return Ok(String::from_utf8_lossy(&buf[..end + 4]).into_owned());
+        }
+        if n == MAX_HEAD {
+            return Err(invalid("request head too long".into()));
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+// Carry messages between a browser and the game server until either leaves.
+fn relay(mut socket: WebSocket<TcpStream>, upstream: SocketAddr) -> io::Result<()> {
+    let server = TcpStream::connect(upstream)?;
+    let mut reader = BufReader::new(server.try_clone()?);
+    let (messages, inbox) = mpsc::channel();
+    thread::spawn(move || {
+        while let Ok(Some(message)) = read_frame(&mut reader) {
+            if messages.send(message).is_err() {
+                return;
+            }
+        }
+    });
+    socket.get_mut().set_read_timeout(Some(POLL_INTERVAL))?;
+    let result = pump(&mut socket, &server, &inbox);
+    let _ = server.shutdown(Shutdown::Both);
+    if let Err(err) = &result {
+        let close = CloseFrame {
+            code: CloseCode::Invalid,
+            reason: err.to_string().into(),
+        };
+        let _ = socket.close(Some(close));
+        let _ = socket.flush();
+    }
+    result
+}
+
+// Pass the server's messages to the browser and the browser's to the server.
+fn pump(
+    socket: &mut WebSocket<TcpStream>,
+    server: &TcpStream,
+    inbox: &mpsc::Receiver<Message>,
+) -> io::Result<()> {
+    let broken = |err: tungstenite::Error| invalid(err.to_string());
+    loop {
+        loop {
+            match inbox.try_recv() {
+                Ok(message) => {
+                    let text =
+                        serde_json::to_string(&message).map_err(|err| invalid(err.to_string()))?;
+                    socket.send(Frame::Text(text)).map_err(broken)?;
+                }
+                Err(TryRecvError::Empty) => break,
+                // The server hung up, having said all it had to.
+                Err(TryRecvError::Disconnected) => {
+                    let _ = socket.close(None);
+                    let _ = socket.flush();
+                    return Ok(());
+                }
+            }
+        }
+        match socket.read() {
+            Ok(Frame::Text(text)) => {
+                let message: Message = serde_json::from_str(&text)
+                    .map_err(|err| invalid(format!("malformed message: {}", err)))?;
+                write_frame(server, &message)?;
+            }
+            Ok(Frame::Binary(_)) => return Err(invalid("expected JSON text messages".into())),
+            Ok(Frame::Close(_)) => return Ok(()),
+            // Pings are answered by the socket itself.
+            Ok(_) => {}
+            Err(tungstenite::Error::Io(err))
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) => {}
+            Err(tungstenite::Error::ConnectionClosed) | Err(tungstenite::Error::AlreadyClosed) => {
+                return Ok(())
+            }
+            Err(err) => return Err(broken(err)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::{lobby, Remote, ServerConfig};
+    use crate::Player;
+    use serde_json::{json, Value};
+    use std::time::Instant;
+    use tungstenite::stream::MaybeTlsStream;
+
+    type Browser = WebSocket<MaybeTlsStream<TcpStream>>;
+
+    // Start a server and its bridge on free ports, returning their addresses.
+    fn start() -> (SocketAddr, SocketAddr) {
+        let games = TcpListener::bind("127.0.0.1:0").unwrap();
+        let web = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (upstream, page) = (games.local_addr().unwrap(), web.local_addr().unwrap());
+        thread::spawn(move || lobby::host(games, ServerConfig::default()));
+        thread::spawn(move || bridge(web, upstream));
+        (upstream, page)
+    }
+
+    fn open(page: SocketAddr) -> Browser {
+        let (socket, _) = tungstenite::connect(format!("ws://{}/ws", page)).unwrap();
+        if let MaybeTlsStream::Plain(stream) = socket.get_ref() {
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+        }
+        socket
+    }
+
+    fn send(socket: &mut Browser, message: Value) {
+        socket.send(Frame::Text(message.to_string())).unwrap();
+    }
+
+    fn receive(socket: &mut Browser) -> Value {
+        loop {
+            match socket.read().unwrap() {
+                Frame::Text(text) => return serde_json::from_str(&text).unwrap(),
+                Frame::Close(_) => panic!("the bridge closed the socket"),
+                _ => {}
+            }
+        }
+    }
+
+    fn get(page: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(page).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: {}\r\n\r\n", path, page).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn serves_the_client() {
+        let (_, page) = start();
+        let response = get(page, "/?game=1");
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.contains("<title>Fog of Chess</title>"));
+        assert!(get(page, "/secrets").starts_with("HTTP/1.1 404"));
+    }
+
+    #[test]
+    fn browsers_play_over_websockets() {
+        let (upstream, page) = start();
+        let mut browser = open(page);
+        send(
+            &mut browser,
+            json!({"Hello": {"min_version": 1, "max_version": 3, "agent": "test"}}),
+        );
+        assert_eq!(receive(&mut browser)["Hello"]["max_version"], 3);
+        send(
+            &mut browser,
+            json!({"Join": {"name": "browser", "player": null}}),
+        );
+        assert_eq!(receive(&mut browser)["Join"]["player"], "White");
+        assert!(receive(&mut browser)["Session"]["token"].is_string());
+
+        // A native client takes the other seat, starting the game.
+        let remote = Remote::connect(upstream, "native").unwrap();
+        assert_eq!(remote.player, Some(Player::Black));
+        let view = receive(&mut browser);
+        let view = &view["Update"];
+        assert_eq!(view["player"], "White");
+        // Black's king is in fog, so the browser is never told about it.
+        assert_eq!(view["visible"][7][4], false);
+        assert!(view["board"][7][4].is_null());
+        assert_eq!(view["board"][0][4]["unit"], "King");
+
+        send(&mut browser, json!({"Move": {"moves": [[[4, 1], [4, 3]]]}}));
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            if let Some(crate::protocol::Message::Update(view)) = remote.poll() {
+                if view.ply == 1 {
+                    assert_eq!(view.turn, Player::Black);
+                    break;
+                }
+            }
+            assert!(Instant::now() < deadline, "the move never arrived");
+            thread::sleep(POLL_INTERVAL);
+        }
+        assert_eq!(receive(&mut browser)["Update"]["ply"], 1);
+    }
+
+    #[test]
+    fn malformed_json_closes_the_socket() {
+        let (_, page) = start();
+        let mut browser = open(page);
+        browser.send(Frame::Text("{\"Hello\": 3}".into())).unwrap();
+        loop {
+            match browser.read() {
+                Ok(Frame::Close(Some(close))) => {
+                    assert_eq!(close.code, CloseCode::Invalid);
+                    break;
+                }
+                Ok(_) => {}
+                Err(err) => panic!("expected a close frame, got {}", err),
+            }
+        }
+    }
+}
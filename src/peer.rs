@@ -0,0 +1,1145 @@
+//! Peer plays a fog game directly against another peer, without a server
+//! that sees the whole board. Each peer keeps only its own pieces, and learns
+//! the enemy pieces it can see through commitments and oblivious transfer,
+//! as described in `crypto`. Neither can learn what the other cannot see
+//! without being caught.
+//!
+//! # Exchange
+//!
+//! The peer that listens plays White and is sent a `Hello` and `Join` by the
+//! one that connects, answering with version 5 and a `Join` carrying its own
+//! name and Black as the joining side.
+//!
+//! Before the first ply and after every ply, each peer sends `Commit`,
+//! committing to the contents of every square on its side of the board.
+//! Each then learns what it can see in `ROUNDS` rounds of `Query` and
+//! `Answer`. A round asks for the squares in sight given what has been
+//! opened so far, with every square not yet opened taken to block sight,
+//! since what can be seen past a square depends on what stands on it. Seven
+//! rounds see along the longest line, and every round is exchanged even when
+//! nothing more is wanted, so their number gives nothing away. Openings are
+//! checked against the commitments as they arrive.
+//!
+//! The mover plays a ply on its own pieces and those it can see, and sends
+//! only `Played` with the square it captured on, if any. Either peer may
+//! send `Chat`, `Resign` and `DrawOffer` between plies.
+//!
+//! # Verification
+//!
+//! When the game ends, by a king's capture, resignation or agreement, both
+//! peers send `Reveal` with their plies and seed. Each then replays the
+//! whole game with the rules engine and checks what the other sent along
+//! the way: that every ply was legal and every capture reported, that every
+//! commitment matched its pieces, and that every square it chose to open was
+//! one it could see. A peer caught cheating loses the game.
+//!
+//! Cheating is caught, not prevented: a peer can look at squares outside its
+//! vision, or play an illegal ply, and will only be found out at the end. A
+//! peer that leaves before revealing cannot be checked at all, and loses.
+//!
+//! Games by mail, in `mail`, are played the same way through signed files
+//! instead of a connection.
+
+use crate::infoset::InfoSet;
+use crate::net::AGENT;
+use crate::protocol::{self, read_frame, write_frame, Message};
+use crate::{notation, Board, Game, Move, Notice, Piece, Player, PlayerView, Report, Step, Unit};
+use crypto::Seed;
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, BufReader};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+mod crypto;
+mod mail;
+
+pub use mail::Correspondence;
+
+/// Rounds of queries each ply, enough to see along the longest line.
+pub const ROUNDS: u8 = 7;
+
+// Squares on the board, indexed by `y * 8 + x`.
+const SQUARES: usize = 64;
+
+// How long the other peer has to complete the handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// How long the other peer has to reveal once the game is over.
+const REVEAL_TIMEOUT: Duration = Duration::from_secs(30);
+
+// How the game ended: the winner, if any, and why.
+type Ending = (Option<Player>, String);
+
+/// Host waits on the listener for another peer to play against, as White.
+/// Returns the address the local client connects to, as it would to a
+/// server, to play the game.
+pub fn host(listener: TcpListener, name: &str) -> io::Result<SocketAddr> {
+    let (stream, _) = listener.accept()?;
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    match read_frame(&mut reader)? {
+        Some(Message::Hello {
+            min_version,
+            max_version,
+            ..
+        }) if (min_version..=max_version).contains(&protocol::PEER_VERSION) => {
+            let hello = Message::Hello {
+                min_version: protocol::PEER_VERSION,
+                max_version: protocol::PEER_VERSION,
+                agent: AGENT.into(),
+            };
+            write_frame(&stream, &hello)?;
+        }
+        Some(Message::Hello { .. }) => {
+            let refusal = Message::GameOver {
+                winner: None,
+                reason: format!(
+                    "playing without a server needs protocol version {}",
+                    protocol::PEER_VERSION
+                ),
+            };
+            let _ = write_frame(&stream, &refusal);
+            return Err(invalid("the peer cannot play without a server".into()));
+        }
+        _ => return Err(invalid("expected a hello from the peer".into())),
+    }
+    let opponent = match read_frame(&mut reader)? {
+        Some(Message::Join { name, .. }) => name,
+        _ => return Err(invalid("expected the peer to join".into())),
+    };
+    let join = Message::Join {
+        name: name.into(),
+        player: Some(Player::Black),
+    };
+    write_frame(&stream, &join)?;
+    start(stream, reader, Player::White, name, opponent)
+}
+
+/// Join connects to a peer waiting at the address, playing Black. Returns
+/// the address the local client connects to.
+pub fn join(addr: impl ToSocketAddrs, name: &str) -> io::Result<SocketAddr> {
+    let stream = TcpStream::connect(addr)?;
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let hello = Message::Hello {
+        min_version: protocol::PEER_VERSION,
+        max_version: protocol::PEER_VERSION,
+        agent: AGENT.into(),
+    };
+    write_frame(&stream, &hello)?;
+    match read_frame(&mut reader)? {
+        Some(Message::Hello { max_version, .. }) if max_version == protocol::PEER_VERSION => {}
+        Some(Message::GameOver { reason, .. }) => return Err(invalid(reason)),
+        _ => return Err(invalid("expected a hello from the peer".into())),
+    }
+    let request = Message::Join {
+        name: name.into(),
+        player: None,
+    };
+    write_frame(&stream, &request)?;
+    let opponent = match read_frame(&mut reader)? {
+        Some(Message::Join {
+            name,
+            player: Some(Player::Black),
+        }) => name,
+        _ => return Err(invalid("expected the peer to seat us as Black".into())),
+    };
+    start(stream, reader, Player::Black, name, opponent)
+}
+
+fn invalid(reason: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+// Open the local endpoint and play the game behind it on a thread of its
+// own.
+fn start(
+    stream: TcpStream,
+    reader: BufReader<TcpStream>,
+    player: Player,
+    name: &str,
+    opponent: String,
+) -> io::Result<SocketAddr> {
+    stream.set_read_timeout(None)?;
+    let local = TcpListener::bind("127.0.0.1:0")?;
+    let addr = local.local_addr()?;
+    let name = name.to_string();
+    thread::spawn(move || {
+        let client = match local.accept().and_then(|(client, _)| {
+            greet(&client, player, &name)?;
+            Ok(client)
+        }) {
+            Ok(client) => client,
+            Err(err) => {
+                println!("local client failed: {}", err);
+                return;
+            }
+        };
+        let (events, inbox) = mpsc::channel();
+        forward(reader, events.clone(), Event::Peer, Event::PeerLeft);
+        match client.try_clone() {
+            Ok(clone) => forward(
+                BufReader::new(clone),
+                events,
+                Event::Local,
+                Event::LocalLeft,
+            ),
+            Err(_) => return,
+        }
+        Peer::new(player, name, opponent, stream, client, inbox).run();
+    });
+    Ok(addr)
+}
+
+// Answer the local client's handshake. It is spoken to as a server speaking
+// the first version, which has no sessions to resume.
+fn greet(client: &TcpStream, player: Player, name: &str) -> io::Result<()> {
+    client.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let mut reader = BufReader::new(client.try_clone()?);
+    match read_frame(&mut reader)? {
+        Some(Message::Hello { min_version, .. }) if min_version <= protocol::MIN_VERSION => {
+            let hello = Message::Hello {
+                min_version: protocol::MIN_VERSION,
+                max_version: protocol::MIN_VERSION,
+                agent: AGENT.into(),
+            };
+            write_frame(client, &hello)?;
+        }
+        _ => return Err(invalid("expected a hello from the local client".into())),
+    }
+    match read_frame(&mut reader)? {
+        Some(Message::Join { .. }) => {
+            let join = Message::Join {
+                name: name.into(),
+                player: Some(player),
+            };
+            write_frame(client, &join)?;
+        }
+        _ => return Err(invalid("expected the local client to join".into())),
+    }
+    client.set_read_timeout(None)
+}
+
+// Something for the peer to act on.
+enum Event {
+    /// A message from the local client.
+    Local(Message),
+    /// A message from the other peer.
+    Peer(Message),
+    LocalLeft,
+    PeerLeft,
+}
+
+// Pass messages from a connection on as events until it closes.
+fn forward(
+    mut reader: BufReader<TcpStream>,
+    events: Sender<Event>,
+    wrap: fn(Message) -> Event,
+    left: Event,
+) {
+    thread::spawn(move || {
+        while let Ok(Some(message)) = read_frame(&mut reader) {
+            if events.send(wrap(message)).is_err() {
+                return;
+            }
+        }
+        let _ = events.send(left);
+    });
+}
+
+// What the other peer committed to, asked for and claimed, kept to be
+// checked once it reveals its seed.
+#[derive(Default)]
+struct Transcript {
+    // Commitments and transfer key by ply.
+    commits: BTreeMap<u32, (Vec<[u8; 32]>, [u8; 32])>,
+    // Queries by ply and round.
+    queries: BTreeMap<(u32, u8), Vec<[u8; 32]>>,
+    // The square it said it captured on, by the ply it played.
+    captures: BTreeMap<u32, Option<(i32, i32)>>,
+}
+
+// One side of a game between peers.
+struct Peer {
+    player: Player,
+    name: String,
+    opponent: String,
+    seed: Seed,
+    stream: TcpStream,
+    client: TcpStream,
+    inbox: Receiver<Event>,
+    // Local messages that arrived while waiting on the other peer.
+    waiting: VecDeque<Message>,
+    // The other peer's seed and plies, if revealed before they were asked for.
+    revealed: Option<(Seed, Vec<Vec<Move>>)>,
+    // Set once the other peer has gone, leaving nothing to verify.
+    gone: bool,
+    // The player's own pieces.
+    own: Board,
+    // Their own pieces and the enemy pieces they can see.
+    known: Board,
+    visible: [[bool; 8]; 8],
+    turn: Player,
+    ply: u32,
+    infoset: InfoSet,
+    notices: Vec<Notice>,
+    // Who has a draw offer standing.
+    offer: Option<Player>,
+    // The plies played, in order.
+    moves: Vec<Vec<Move>>,
+    transcript: Transcript,
+}
+
+impl Peer {
+    fn new(
+        player: Player,
+        name: String,
+        opponent: String,
+        stream: TcpStream,
+        client: TcpStream,
+        inbox: Receiver<Event>,
+    ) -> Self {
+        let board = Board::new();
+        let mut own = Board::default();
+        for y in 0..8 {
+            for x in 0..8 {
+                if let Some(piece) = board.get((x, y)).filter(|piece| piece.player == player) {
+                    own.set((x, y), piece.clone());
+                }
+            }
+        }
+        Peer {
+            player,
+            name,
+            opponent,
+            seed: rand::random(),
+            stream,
+            client,
+            inbox,
+            waiting: VecDeque::new(),
+            revealed: None,
+            gone: false,
+            known: own.clone(),
+            own,
+            visible: [[false; 8]; 8],
+            turn: Player::White,
+            ply: 0,
+            infoset: InfoSet::new(player, &board),
+            notices: vec![],
+            offer: None,
+            moves: vec![],
+            transcript: Transcript::default(),
+        }
+    }
+
+    fn run(mut self) {
+        println!("playing {:?} against {}", self.player, self.opponent);
+        let ending = self.play();
+        let (winner, reason) = self.conclude(ending);
+        println!("game over: {}", reason);
+        self.tell(&Message::GameOver { winner, reason });
+        let _ = self.stream.shutdown(Shutdown::Both);
+        let _ = self.client.shutdown(Shutdown::Both);
+    }
+
+    // Play until the game ends.
+    fn play(&mut self) -> Ending {
+        if let Err(ending) = self.refresh(false, &[]) {
+            return ending;
+        }
+        self.update();
+        loop {
+            let event = match self.waiting.pop_front() {
+                Some(message) => Event::Local(message),
+                None => match self.inbox.recv() {
+                    Ok(event) => event,
+                    Err(_) => return (None, "both connections closed".into()),
+                },
+            };
+            let result = match event {
+                Event::Local(message) => self.local(message),
+                Event::Peer(message) => self.remote(message),
+                Event::LocalLeft => Err(self.resign()),
+                Event::PeerLeft => Err(self.left()),
+            };
+            if let Err(ending) = result {
+                return ending;
+            }
+        }
+    }
+
+    // Act on a message from the local client.
+    fn local(&mut self, message: Message) -> Result<(), Ending> {
+        match message {
+            Message::Move { moves } if self.turn == self.player => self.play_ply(moves),
+            Message::Move { moves } => {
+                let reason = "not your turn".into();
+                self.tell(&Message::MoveRejected { moves, reason });
+                Ok(())
+            }
+            Message::Resign => Err(self.resign()),
+            Message::DrawOffer { .. } => {
+                let player = self.player;
+                self.send(&Message::DrawOffer { player })?;
+                if self.offer == Some(player.opponent()) {
+                    return Err((None, "agreement".into()));
+                }
+                self.offer = Some(player);
+                Ok(())
+            }
+            Message::Chat { text, .. } => {
+                let chat = Message::Chat {
+                    from: self.name.clone(),
+                    text: text.chars().take(protocol::MAX_CHAT).collect(),
+                };
+                self.send(&chat)?;
+                self.tell(&chat);
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Act on a message from the other peer between plies.
+    fn remote(&mut self, message: Message) -> Result<(), Ending> {
+        let opponent = self.player.opponent();
+        match message {
+            Message::Played { ply, captured } if self.turn == opponent && ply == self.ply + 1 => {
+                self.transcript.captures.insert(self.ply, captured);
+                let lost = match captured {
+                    Some(at) => match self.own.get(at).map(|piece| piece.unit) {
+                        Some(unit) => {
+                            self.own = without(&self.own, at);
+                            Some((unit, at))
+                        }
+                        None => {
+                            return Err(self.cheated(format!(
+                                "it claimed a capture on {}, where nothing of ours stood",
+                                notation(at)
+                            )))
+                        }
+                    },
+                    None => None,
+                };
+                self.advance();
+                if let Some((Unit::King, _)) = lost {
+                    return Err((Some(opponent), "king captured".into()));
+                }
+                self.refresh(true, &[])?;
+                if let Some((unit, at)) = lost {
+                    let by = self.known.get(at).map(|piece| piece.unit);
+                    self.notice(Report::Lost { unit, at, by });
+                }
+                self.update();
+                Ok(())
+            }
+            Message::Resign => Err((Some(self.player), "resignation".into())),
+            Message::DrawOffer { .. } => {
+                if self.offer == Some(self.player) {
+                    return Err((None, "agreement".into()));
+                }
+                self.offer = Some(opponent);
+                self.tell(&Message::DrawOffer { player: opponent });
+                Ok(())
+            }
+            Message::Chat { text, .. } => {
+                let chat = Message::Chat {
+                    from: self.opponent.clone(),
+                    text: text.chars().take(protocol::MAX_CHAT).collect(),
+                };
+                self.tell(&chat);
+                Ok(())
+            }
+            Message::Reveal { seed, moves } => {
+                self.revealed = Some((seed, moves));
+                Err((Some(self.player), "the opponent stopped playing".into()))
+            }
+            _ => Err(self.broken()),
+        }
+    }
+
+    // Play a ply for the local player, if it is legal on what they know.
+    fn play_ply(&mut self, moves: Vec<Move>) -> Result<(), Ending> {
+        let mut game = Game::headless(self.known.clone(), false, 0);
+        game.state.turn = self.player;
+        let step = Step {
+            player: self.player,
+            moves: moves.clone(),
+        };
+        if !game.apply(&step) {
+            let reason = "illegal move".into();
+            self.tell(&Message::MoveRejected { moves, reason });
+            return Ok(());
+        }
+        let captured = match moves.as_slice() {
+            [(_, to)] => self
+                .known
+                .get(*to)
+                .filter(|piece| piece.player != self.player)
+                .map(|piece| (piece.unit, *to)),
+            _ => None,
+        };
+        self.own = mine(&game.state.board, self.player);
+        self.moves.push(moves);
+        self.advance();
+        let ply = self.ply;
+        self.send(&Message::Played {
+            ply,
+            captured: captured.map(|(_, at)| at),
+        })?;
+        if let Some((unit, at)) = captured {
+            self.notices.push(Notice {
+                ply: ply - 1,
+                player: self.player,
+                report: Report::Captured { unit, at },
+            });
+            if unit == Unit::King {
+                return Err((Some(self.player), "king captured".into()));
+            }
+        }
+        let taken: Vec<Unit> = captured.iter().map(|(unit, _)| *unit).collect();
+        self.refresh(false, &taken)?;
+        self.update();
+        Ok(())
+    }
+
+    // Move on to the next ply.
+    fn advance(&mut self) {
+        self.ply += 1;
+        self.turn = self.turn.opponent();
+        self.offer = None;
+    }
+
+    // Commit to the board and learn what can be seen of the other side of
+    // it, for the ply just reached.
+    fn refresh(&mut self, opponent_moved: bool, taken: &[Unit]) -> Result<(), Ending> {
+        let ply = self.ply;
+        let openings: Vec<_> = (0..SQUARES)
+            .map(|index| {
+                let unit = self.own.get(square(index)).map(|piece| piece.unit);
+                crypto::opening(&self.seed, ply, index, unit)
+            })
+            .collect();
+        let squares = openings
+            .iter()
+            .enumerate()
+            .map(|(index, opening)| crypto::commit(ply, index, opening))
+            .collect();
+        let (secret, key) = crypto::sender_key(&self.seed, ply);
+        self.send(&Message::Commit { ply, squares, key })?;
+        let (committed, sender) = match self.receive()? {
+            Message::Commit {
+                ply: at,
+                squares,
+                key,
+            } if at == ply && squares.len() == SQUARES => (squares, key),
+            _ => return Err(self.broken()),
+        };
+        self.transcript
+            .commits
+            .insert(ply, (committed.clone(), sender));
+        let mut opened = vec![None; SQUARES];
+        for round in 0..ROUNDS {
+            let wanted = wanted(self.player, &self.own, &opened);
+            let points = (0..SQUARES)
+                .map(|index| {
+                    crypto::choose(
+                        &self.seed,
+                        &context(ply, round, index),
+                        &sender,
+                        wanted[index] as u8,
+                    )
+                })
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|err| self.cheated(format!("its key for ply {} is {}", ply, err)))?;
+            self.send(&Message::Query { ply, round, points })?;
+            let points = match self.receive()? {
+                Message::Query {
+                    ply: at,
+                    round: number,
+                    points,
+                } if at == ply && number == round && points.len() == SQUARES => points,
+                _ => return Err(self.broken()),
+            };
+            let sealed = points
+                .iter()
+                .enumerate()
+                .map(|(index, point)| {
+                    crypto::seal(
+                        &secret,
+                        &context(ply, round, index),
+                        point,
+                        &openings[index],
+                    )
+                })
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|err| self.cheated(format!("its query at ply {} is {}", ply, err)))?;
+            self.transcript.queries.insert((ply, round), points);
+            self.send(&Message::Answer { ply, round, sealed })?;
+            let sealed = match self.receive()? {
+                Message::Answer {
+                    ply: at,
+                    round: number,
+                    sealed,
+                } if at == ply && number == round && sealed.len() == SQUARES => sealed,
+                _ => return Err(self.broken()),
+            };
+            for index in (0..SQUARES).filter(|index| wanted[*index]) {
+                let opening = crypto::unseal(
+                    &self.seed,
+                    &context(ply, round, index),
+                    &sender,
+                    &sealed[index],
+                )
+                .and_then(|opening| {
+                    if crypto::commit(ply, index, &opening) == committed[index] {
+                        crypto::contents(&opening)
+                    } else {
+                        Err("not what it committed to".into())
+                    }
+                })
+                .map_err(|err| {
+                    self.cheated(format!(
+                        "its opening of {} at ply {} is {}",
+                        notation(square(index)),
+                        ply,
+                        err
+                    ))
+                })?;
+                opened[index] = Some(opening);
+            }
+        }
+        let mut known = self.own.clone();
+        for (index, opened) in opened.iter().enumerate() {
+            if let Some(Some(unit)) = opened {
+                known.set(square(index), enemy(self.player, *unit, square(index)));
+            }
+        }
+        self.visible = known.visibility(&self.player);
+        self.infoset
+            .observe(&self.known, &known, opponent_moved, taken);
+        self.known = known;
+        Ok(())
+    }
+
+    // The next message from the other peer during the exchange of a ply.
+    // Anything else it may send between plies is acted on, and messages
+    // from the local client are kept for later.
+    fn receive(&mut self) -> Result<Message, Ending> {
+        loop {
+            match self.inbox.recv() {
+                Ok(Event::Peer(message)) => match message {
+                    Message::Commit { .. } | Message::Query { .. } | Message::Answer { .. } => {
+                        return Ok(message)
+                    }
+                    Message::Played { .. } => return Err(self.broken()),
+                    message => self.remote(message)?,
+                },
+                Ok(Event::Local(message)) => self.waiting.push_back(message),
+                Ok(Event::LocalLeft) => return Err(self.resign()),
+                Ok(Event::PeerLeft) | Err(_) => return Err(self.left()),
+            }
+        }
+    }
+
+    // Exchange reveals with the other peer and check its side of the game,
+    // returning how the game really ended.
+    fn conclude(&mut self, ending: Ending) -> Ending {
+        if self.gone {
+            return ending;
+        }
+        let reveal = Message::Reveal {
+            seed: self.seed,
+            moves: self.moves.clone(),
+        };
+        if self.send(&reveal).is_err() {
+            return ending;
+        }
+        let deadline = Instant::now() + REVEAL_TIMEOUT;
+        let (seed, moves) = loop {
+            if let Some(revealed) = self.revealed.take() {
+                break revealed;
+            }
+            let wait = deadline.saturating_duration_since(Instant::now());
+            match self.inbox.recv_timeout(wait) {
+                Ok(Event::Peer(Message::Reveal { seed, moves })) => break (seed, moves),
+                Ok(Event::PeerLeft)
+                | Err(RecvTimeoutError::Timeout)
+                | Err(RecvTimeoutError::Disconnected) => {
+                    let reason = format!("{}, but the opponent never revealed its game", ending.1);
+                    return (Some(self.player), reason);
+                }
+                Ok(_) => {}
+            }
+        };
+        let checked = verify(
+            self.player,
+            &self.seed,
+            &self.moves,
+            &seed,
+            &moves,
+            &self.transcript,
+        );
+        match checked {
+            Ok(()) => ending,
+            Err(err) => (Some(self.player), format!("the opponent cheated: {}", err)),
+        }
+    }
+
+    // Send a message to the other peer, ending the game if it has gone.
+    fn send(&mut self, message: &Message) -> Result<(), Ending> {
+        write_frame(&self.stream, message).map_err(|_| self.left())
+    }
+
+    // Send a message to the local client. A failed write shows up as the
+    // client leaving, so it needs no handling here.
+    fn tell(&self, message: &Message) {
+        let _ = write_frame(&self.client, message);
+    }
+
+    // Send the local client its view.
+    fn update(&self) {
+        let view = PlayerView {
+            player: self.player,
+            turn: self.turn,
+            ply: self.ply,
+            board: self.known.clone(),
+            visible: self.visible,
+            infoset: self.infoset.clone(),
+            notices: self.notices.clone(),
+        };
+        self.tell(&Message::Update(Box::new(view)));
+    }
+
+    fn notice(&mut self, report: Report) {
+        self.notices.push(Notice {
+            ply: self.ply - 1,
+            player: self.player,
+            report,
+        });
+    }
+
+    fn resign(&mut self) -> Ending {
+        let _ = write_frame(&self.stream, &Message::Resign);
+        (Some(self.player.opponent()), "resignation".into())
+    }
+
+    fn left(&mut self) -> Ending {
+        self.gone = true;
+        (Some(self.player), "the opponent left".into())
+    }
+
+    fn broken(&self) -> Ending {
+        let reason = "the opponent broke the protocol".into();
+        (Some(self.player), reason)
+    }
+
+    fn cheated(&self, reason: String) -> Ending {
+        (
+            Some(self.player),
+            format!("the opponent cheated: {}", reason),
+        )
+    }
+}
+
+// Names the transfer of a square's opening in one round of a ply.
+fn context(ply: u32, round: u8, index: usize) -> [u8; 6] {
+    let ply = ply.to_le_bytes();
+    [ply[0], ply[1], ply[2], ply[3], round, index as u8]
+}
+
+/// Square is the position of the square at `index`, `y * 8 + x`.
+fn square(index: usize) -> (i32, i32) {
+    ((index % 8) as i32, (index / 8) as i32)
+}
+
+// An enemy piece seen on a square. How often it has moved is hidden, so
+// pawns on their starting rank are taken not to have.
+fn enemy(player: Player, unit: Unit, pos: (i32, i32)) -> Piece {
+    let owner = player.opponent();
+    Piece {
+        unit,
+        player: owner,
+        moved: if unit == Unit::Pawn && pos.1 == owner.pawn_rank() {
+            0
+        } else {
+            1
+        },
+    }
+}
+
+// The player's own pieces on a board.
+fn mine(board: &Board, player: Player) -> Board {
+    let mut own = Board::default();
+    for index in 0..SQUARES {
+        let pos = square(index);
+        if let Some(piece) = board.get(pos).filter(|piece| piece.player == player) {
+            own.set(pos, piece.clone());
+        }
+    }
+    own
+}
+
+// A board with the square emptied.
+fn without(board: &Board, at: (i32, i32)) -> Board {
+    let mut emptied = Board::default();
+    for index in 0..SQUARES {
+        let pos = square(index);
+        if let Some(piece) = board.get(pos).filter(|_| pos != at) {
+            emptied.set(pos, piece.clone());
+        }
+    }
+    emptied
+}
+
+/// Wanted is which squares to ask the other peer to open next: those the
+/// player can see, given their own pieces and what has been opened so far,
+/// that are not theirs and not yet opened. Every square not yet opened is
+/// taken to hold an enemy piece, so that sight stops there until it is.
+pub fn wanted(player: Player, own: &Board, opened: &[Option<Option<Unit>>]) -> Vec<bool> {
+    let mut board = own.clone();
+    for (index, opened) in opened.iter().enumerate() {
+        let pos = square(index);
+        if own.get(pos).is_some() {
+            continue;
+        }
+        match opened {
+            Some(Some(unit)) => board.set(pos, enemy(player, *unit, pos)),
+            Some(None) => {}
+            None => board.set(pos, enemy(player, Unit::Pawn, pos)),
+        }
+    }
+    let visible = board.visibility(&player);
+    (0..SQUARES)
+        .map(|index| {
+            let (x, y) = square(index);
+            visible[y as usize][x as usize] && own.get((x, y)).is_none() && opened[index].is_none()
+        })
+        .collect()
+}
+
+/// Verify replays a finished game from both peers' revealed plies and checks
+/// everything the other peer sent against it: that its plies were legal and
+/// its captures reported, that its commitments matched its pieces, and that
+/// it only ever chose to open squares it could see. Returns the first
+/// problem found.
+fn verify(
+    player: Player,
+    seed: &Seed,
+    mine: &[Vec<Move>],
+    their_seed: &Seed,
+    theirs: &[Vec<Move>],
+    transcript: &Transcript,
+) -> Result<(), String> {
+    let opponent = player.opponent();
+    replay(player, mine, theirs, &transcript.captures, |ply, board| {
+        let (squares, key) = match transcript.commits.get(&ply) {
+            Some(commit) => commit,
+            None => return Ok(()),
+        };
+        committed(opponent, their_seed, ply, board, squares, key)?;
+        let visible = board.visibility(&opponent);
+        let (_, ours) = crypto::sender_key(seed, ply);
+        for round in 0..ROUNDS {
+            let points = match transcript.queries.get(&(ply, round)) {
+                Some(points) => points,
+                None => continue,
+            };
+            for (index, point) in points.iter().enumerate() {
+                let (x, y) = square(index);
+                match crypto::chosen(their_seed, &context(ply, round, index), &ours, point, 2) {
+                    None => {
+                        return Err(format!(
+                            "its query for {} at ply {} is not from its seed",
+                            notation((x, y)),
+                            ply
+                        ))
+                    }
+                    Some(1) if !visible[y as usize][x as usize] => {
+                        return Err(format!(
+                            "it looked at {} at ply {}, outside its vision",
+                            notation((x, y)),
+                            ply
+                        ))
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+        Ok(())
+    })
+}
+
+// Check that the other peer's commitments and transfer key for a ply match
+// its pieces on the board and its seed.
+fn committed(
+    owner: Player,
+    seed: &Seed,
+    ply: u32,
+    board: &Board,
+    squares: &[[u8; 32]],
+    key: &[u8; 32],
+) -> Result<(), String> {
+    if crypto::sender_key(seed, ply).1 != *key {
+        return Err(format!("its key at ply {} is not from its seed", ply));
+    }
+    for (index, commitment) in squares.iter().enumerate() {
+        let unit = board
+            .get(square(index))
+            .filter(|piece| piece.player == owner)
+            .map(|piece| piece.unit);
+        let opening = crypto::opening(seed, ply, index, unit);
+        if crypto::commit(ply, index, &opening) != *commitment {
+            return Err(format!(
+                "its commitment to {} at ply {} does not match its pieces",
+                notation(square(index)),
+                ply
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Replay a finished game from both sides' revealed plies, checking that the
+// other side's plies were legal and its captures, by the ply they were
+// played at, reported truly. `check` is called with the board before every
+// ply and after the last.
+fn replay(
+    player: Player,
+    mine: &[Vec<Move>],
+    theirs: &[Vec<Move>],
+    captures: &BTreeMap<u32, Option<(i32, i32)>>,
+    mut check: impl FnMut(u32, &Board) -> Result<(), String>,
+) -> Result<(), String> {
+    let opponent = player.opponent();
+    let mut game = Game::headless(Board::new(), false, 0);
+    let (mut mine, mut theirs) = (mine.iter(), theirs.iter());
+    loop {
+        let (ply, board) = (game.state.ply, &game.state.board);
+        check(ply, board)?;
+        if board.winner().is_some() {
+            break;
+        }
+        let turn = game.state.turn;
+        let moves = match if turn == player {
+            mine.next()
+        } else {
+            theirs.next()
+        } {
+            Some(moves) => moves,
+            None => break,
+        };
+        let captured = match moves.as_slice() {
+            [(_, to)] if board.contains_enemy_of(turn, *to) => Some(*to),
+            _ => None,
+        };
+        let step = Step {
+            player: turn,
+            moves: moves.clone(),
+        };
+        if !game.apply(&step) {
+            return Err(format!("ply {} by {:?} is illegal", ply + 1, turn));
+        }
+        if turn == opponent {
+            if let Some(claimed) = captures.get(&ply) {
+                if *claimed != captured {
+                    return Err(format!("it misreported its capture at ply {}", ply + 1));
+                }
+            }
+        }
+    }
+    match captures.keys().next_back() {
+        Some(ply) if *ply >= game.state.ply => Err("it revealed fewer plies than it played".into()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::Remote;
+
+    // Simulate what an honest peer playing `player` sends the other during
+    // a ply: its commitments, and queries for what it can see on the board.
+    fn exchange(
+        transcript: &mut Transcript,
+        board: &Board,
+        player: Player,
+        seed: &Seed,
+        other: &Seed,
+    ) {
+        let ply = 0;
+        let own = mine(board, player);
+        let squares = (0..SQUARES)
+            .map(|index| {
+                let unit = own.get(square(index)).map(|piece| piece.unit);
+                crypto::commit(ply, index, &crypto::opening(seed, ply, index, unit))
+            })
+            .collect();
+        let key = crypto::sender_key(seed, ply).1;
+        transcript.commits.insert(ply, (squares, key));
+        let (_, sender) = crypto::sender_key(other, ply);
+        let mut opened = vec![None; SQUARES];
+        for round in 0..ROUNDS {
+            let wanted = wanted(player, &own, &opened);
+            let points = (0..SQUARES)
+                .map(|index| {
+                    crypto::choose(
+                        seed,
+                        &context(ply, round, index),
+                        &sender,
+                        wanted[index] as u8,
+                    )
+                    .unwrap()
+                })
+                .collect();
+            transcript.queries.insert((ply, round), points);
+            for index in (0..SQUARES).filter(|index| wanted[*index]) {
+                opened[index] = Some(board.get(square(index)).map(|piece| piece.unit));
+            }
+        }
+    }
+
+    #[test]
+    fn honest_peers_verify() {
+        let (white, black) = ([1; 32], [2; 32]);
+        let mut transcript = Transcript::default();
+        exchange(
+            &mut transcript,
+            &Board::new(),
+            Player::Black,
+            &black,
+            &white,
+        );
+        assert_eq!(
+            verify(Player::White, &white, &[], &black, &[], &transcript),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn peeking_is_caught() {
+        let (white, black) = ([1; 32], [2; 32]);
+        let mut transcript = Transcript::default();
+        exchange(
+            &mut transcript,
+            &Board::new(),
+            Player::Black,
+            &black,
+            &white,
+        );
+        // Black asks for White's king, which it cannot see.
+        let (_, sender) = crypto::sender_key(&white, 0);
+        let index = 4;
+        transcript.queries.get_mut(&(0, 0)).unwrap()[index] =
+            crypto::choose(&black, &context(0, 0, index), &sender, 1).unwrap();
+        let err = verify(Player::White, &white, &[], &black, &[], &transcript).unwrap_err();
+        assert!(err.contains("it looked at e1"), "{}", err);
+    }
+
+    #[test]
+    fn illegal_plies_are_caught() {
+        let (white, black) = ([1; 32], [2; 32]);
+        let mut transcript = Transcript::default();
+        exchange(
+            &mut transcript,
+            &Board::new(),
+            Player::Black,
+            &black,
+            &white,
+        );
+        let mine = [vec![((4, 1), (4, 3))]];
+        let theirs = [vec![((3, 7), (3, 1))]];
+        transcript.captures.insert(1, Some((3, 1)));
+        let err = verify(Player::White, &white, &mine, &black, &theirs, &transcript).unwrap_err();
+        assert!(err.contains("ply 2 by Black is illegal"), "{}", err);
+    }
+
+    // Wait for a message the test is interested in.
+    fn next(remote: &Remote, want: impl Fn(&Message) -> bool) -> Message {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            if let Some(message) = remote.poll() {
+                if want(&message) {
+                    return message;
+                }
+            }
+            assert!(Instant::now() < deadline, "no message arrived");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn view(remote: &Remote, ply: u32) -> PlayerView {
+        match next(
+            remote,
+            |message| matches!(message, Message::Update(view) if view.ply == ply),
+        ) {
+            Message::Update(view) => *view,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn peers_play_without_a_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let hosting = thread::spawn(move || host(listener, "anna").unwrap());
+        let black = Remote::connect(join(addr, "ben").unwrap(), "ben").unwrap();
+        let white = Remote::connect(hosting.join().unwrap(), "anna").unwrap();
+        assert_eq!(
+            (white.player, black.player),
+            (Some(Player::White), Some(Player::Black))
+        );
+
+        // Neither side starts out seeing the other.
+        let seen = |view: &PlayerView| {
+            (0..SQUARES)
+                .filter(|index| matches!(view.board.get(square(*index)), Some(piece) if piece.player != view.player))
+                .count()
+        };
+        assert_eq!(seen(&view(&white, 0)), 0);
+        assert_eq!(seen(&view(&black, 0)), 0);
+
+        white.play(vec![((4, 1), (4, 3))]).unwrap();
+        assert_eq!(seen(&view(&black, 1)), 0);
+        black.play(vec![((4, 6), (4, 4))]).unwrap();
+        // The pawns now face each other, and each sees the other's.
+        let (white_view, black_view) = (view(&white, 2), view(&black, 2));
+        assert_eq!(
+            white_view.board.get((4, 4)).map(|piece| piece.unit),
+            Some(Unit::Pawn)
+        );
+        assert_eq!(
+            black_view.board.get((4, 3)).map(|piece| piece.unit),
+            Some(Unit::Pawn)
+        );
+        assert_eq!(seen(&white_view), seen(&black_view));
+
+        // Black's pawn blocks the way.
+        white.play(vec![((4, 3), (4, 4))]).unwrap();
+        assert!(matches!(
+            next(&white, |message| matches!(
+                message,
+                Message::MoveRejected { .. }
+            )),
+            Message::MoveRejected { .. }
+        ));
+
+        white.resign().unwrap();
+        for remote in [&white, &black] {
+            match next(remote, |message| {
+                matches!(message, Message::GameOver { .. })
+            }) {
+                Message::GameOver { winner, reason } => {
+                    assert_eq!(
+                        (winner, reason.as_str()),
+                        (Some(Player::Black), "resignation")
+                    );
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+}
@@ -0,0 +1,269 @@
+//! Commitments and oblivious transfer, the two primitives serverless play is
+//! built on.
+//!
+//! A commitment binds a peer to what stands on a square without showing it:
+//! the SHA-256 hash of the square's opening, which is the square's contents
+//! followed by a nonce. Oblivious transfer lets one peer pick one of several
+//! messages the other offers, while the other learns nothing of which was
+//! picked. It is the "simplest" protocol of Chou and Orlandi over the
+//! Ristretto group: the sender publishes `A = aG` for the ply, the receiver
+//! sends `B = cA + bG` to pick message `c`, and the sender seals message `j`
+//! under a key derived from `a(B - jA)`, which the receiver can only compute,
+//! as `bA`, for the message it picked.
+//!
+//! Every secret a peer uses, its nonces and both kinds of scalar, is derived
+//! from a single seed. Revealing the seed at the end of the game lets the
+//! other peer recompute them all, and so check every commitment and every
+//! choice the peer made.
+
+use crate::{Unit, UNITS};
+use curve25519_dalek::ristretto::{CompressedRistretto, RistrettoPoint};
+use curve25519_dalek::scalar::Scalar;
+use sha2::{Digest, Sha256, Sha512};
+
+/// Seed is the secret a peer derives its nonces and transfer keys from.
+pub type Seed = [u8; 32];
+
+/// Opening shows what a committed square holds: 0 for empty or one more
+/// than the unit's index, followed by the square's nonce.
+pub type Opening = [u8; 32];
+
+// Separates the uses of the seed, and of the hash, from one another.
+const DOMAIN: &[u8] = b"fog-of-chess peer";
+
+/// Hash combines the parts under a label saying what the hash is for.
+pub fn hash(label: &[u8], parts: &[&[u8]]) -> [u8; 32] {
+    let mut hasher = Sha256::new().chain_update(DOMAIN).chain_update(label);
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+/// Secret derives a value only the holder of the seed can compute.
+pub fn secret(seed: &Seed, label: &[u8], parts: &[&[u8]]) -> [u8; 32] {
+    let mut all = vec![&seed[..]];
+    all.extend_from_slice(parts);
+    hash(label, &all)
+}
+
+// A scalar only the holder of the seed can compute.
+fn scalar(seed: &Seed, label: &[u8], parts: &[&[u8]]) -> Scalar {
+    let mut hasher = Sha512::new()
+        .chain_update(DOMAIN)
+        .chain_update(label)
+        .chain_update(seed);
+    for part in parts {
+        hasher.update(part);
+    }
+    Scalar::from_bytes_mod_order_wide(&hasher.finalize().into())
+}
+
+fn point(bytes: &[u8; 32]) -> Result<RistrettoPoint, String> {
+    CompressedRistretto(*bytes)
+        .decompress()
+        .ok_or_else(|| "not a valid group element".to_string())
+}
+
+/// Valid checks that a key or query is a group element, as an honest one
+/// always is.
+pub fn valid(bytes: &[u8; 32]) -> bool {
+    point(bytes).is_ok()
+}
+
+/// Xor combines two blocks, sealing one under the other or unsealing it.
+pub fn xor(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut out = [0; 32];
+    for (out, (left, right)) in out.iter_mut().zip(left.iter().zip(right.iter())) {
+        *out = left ^ right;
+    }
+    out
+}
+
+/// Opening for the square at index `square`, `y * 8 + x`, after `ply` plies.
+pub fn opening(seed: &Seed, ply: u32, square: usize, unit: Option<Unit>) -> Opening {
+    let mut opening = secret(seed, b"nonce", &[&ply.to_le_bytes(), &[square as u8]]);
+    opening[0] = unit.map_or(0, |unit| unit.index() as u8 + 1);
+    opening
+}
+
+/// Contents reads what an opening says stands on its square.
+pub fn contents(opening: &Opening) -> Result<Option<Unit>, String> {
+    match opening[0] {
+        0 => Ok(None),
+        n => UNITS
+            .get(n as usize - 1)
+            .map(|unit| Some(*unit))
+            .ok_or_else(|| format!("no unit numbered {}", n)),
+    }
+}
+
+/// Commit binds an opening to its square and ply.
+pub fn commit(ply: u32, square: usize, opening: &Opening) -> [u8; 32] {
+    hash(b"commit", &[&ply.to_le_bytes(), &[square as u8], opening])
+}
+
+/// Sender key is the peer's secret for answering queries during a ply, and
+/// the public key it commits to.
+pub fn sender_key(seed: &Seed, ply: u32) -> (Scalar, [u8; 32]) {
+    let secret = scalar(seed, b"sender", &[&ply.to_le_bytes()]);
+    let public = RistrettoPoint::mul_base(&secret).compress().to_bytes();
+    (secret, public)
+}
+
+// The receiver's scalar for one transfer.
+fn receiver_scalar(seed: &Seed, context: &[u8]) -> Scalar {
+    scalar(seed, b"receiver", &[context])
+}
+
+// Key sealing one message of a transfer.
+fn key(context: &[u8], query: &[u8; 32], shared: &RistrettoPoint) -> [u8; 32] {
+    hash(b"key", &[context, query, shared.compress().as_bytes()])
+}
+
+/// Choose is the receiver's query picking message `choice` of the transfer
+/// named by `context`, hidden from the sender with key `sender`.
+pub fn choose(
+    seed: &Seed,
+    context: &[u8],
+    sender: &[u8; 32],
+    choice: u8,
+) -> Result<[u8; 32], String> {
+    let blind = RistrettoPoint::mul_base(&receiver_scalar(seed, context));
+    let query = Scalar::from(choice) * point(sender)? + blind;
+    Ok(query.compress().to_bytes())
+}
+
+/// Keys are the sender's keys for each of `count` messages, given the
+/// receiver's query. Only the one it chose can be computed by the receiver.
+pub fn keys(
+    secret: &Scalar,
+    context: &[u8],
+    query: &[u8; 32],
+    count: u8,
+) -> Result<Vec<[u8; 32]>, String> {
+    let mut shared = secret * point(query)?;
+    let step = secret * RistrettoPoint::mul_base(secret);
+    let mut keys = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        keys.push(key(context, query, &shared));
+        shared -= step;
+    }
+    Ok(keys)
+}
+
+/// Receiver key is the key for the message the receiver chose.
+pub fn receiver_key(
+    seed: &Seed,
+    context: &[u8],
+    sender: &[u8; 32],
+    choice: u8,
+) -> Result<[u8; 32], String> {
+    let query = choose(seed, context, sender, choice)?;
+    let shared = receiver_scalar(seed, context) * point(sender)?;
+    Ok(key(context, &query, &shared))
+}
+
+/// Chosen recovers, from the receiver's revealed seed, which of `count`
+/// messages a query chose. `None` means the query was not derived from the
+/// seed.
+pub fn chosen(
+    seed: &Seed,
+    context: &[u8],
+    sender: &[u8; 32],
+    query: &[u8; 32],
+    count: u8,
+) -> Option<u8> {
+    let sender = point(sender).ok()?;
+    let mut candidate = RistrettoPoint::mul_base(&receiver_scalar(seed, context));
+    for choice in 0..count {
+        if candidate.compress().as_bytes() == query {
+            return Some(choice);
+        }
+        candidate += sender;
+    }
+    None
+}
+
+/// Seal answers a query for a single opening, so that it can only be
+/// unsealed if the receiver chose it. The other message is empty.
+pub fn seal(
+    secret: &Scalar,
+    context: &[u8],
+    query: &[u8; 32],
+    opening: &Opening,
+) -> Result<[[u8; 32]; 2], String> {
+    let keys = keys(secret, context, query, 2)?;
+    Ok([keys[0], xor(&keys[1], opening)])
+}
+
+/// Unseal recovers an opening the receiver chose.
+pub fn unseal(
+    seed: &Seed,
+    context: &[u8],
+    sender: &[u8; 32],
+    sealed: &[[u8; 32]; 2],
+) -> Result<Opening, String> {
+    Ok(xor(&sealed[1], &receiver_key(seed, context, sender, 1)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SENDER: Seed = [1; 32];
+    const RECEIVER: Seed = [2; 32];
+
+    #[test]
+    fn chosen_squares_open_and_others_do_not() {
+        let (secret, public) = sender_key(&SENDER, 3);
+        let message = opening(&SENDER, 3, 12, Some(Unit::Knight));
+        for wanted in 0..2 {
+            let query = choose(&RECEIVER, b"12", &public, wanted).unwrap();
+            let sealed = seal(&secret, b"12", &query, &message).unwrap();
+            let unsealed = unseal(&RECEIVER, b"12", &public, &sealed).unwrap();
+            assert_eq!(unsealed == message, wanted == 1);
+            assert_eq!(chosen(&RECEIVER, b"12", &public, &query, 2), Some(wanted));
+        }
+        let forged = choose(&[9; 32], b"12", &public, 1).unwrap();
+        assert_eq!(chosen(&RECEIVER, b"12", &public, &forged, 2), None);
+    }
+
+    #[test]
+    fn one_of_many_keys_is_shared() {
+        let (secret, public) = sender_key(&SENDER, 0);
+        for choice in 0..8 {
+            let query = choose(&RECEIVER, b"ray", &public, choice).unwrap();
+            let keys = keys(&secret, b"ray", &query, 8).unwrap();
+            let key = receiver_key(&RECEIVER, b"ray", &public, choice).unwrap();
+            for (index, sealed) in keys.iter().enumerate() {
+                assert_eq!(*sealed == key, index == choice as usize);
+            }
+            assert_eq!(chosen(&RECEIVER, b"ray", &public, &query, 8), Some(choice));
+        }
+    }
+
+    #[test]
+    fn queries_hide_the_choice() {
+        let (_, public) = sender_key(&SENDER, 0);
+        let declined = choose(&RECEIVER, b"5", &public, 0).unwrap();
+        let wanted = choose(&RECEIVER, b"5", &public, 1).unwrap();
+        assert_ne!(declined, wanted);
+        // Both are valid group elements, so neither stands out.
+        assert!(point(&declined).is_ok() && point(&wanted).is_ok());
+    }
+
+    #[test]
+    fn commitments_bind_their_contents() {
+        let queen = opening(&SENDER, 4, 27, Some(Unit::Queen));
+        assert_eq!(contents(&queen), Ok(Some(Unit::Queen)));
+        let empty = opening(&SENDER, 4, 27, None);
+        assert_eq!(contents(&empty), Ok(None));
+        assert_ne!(commit(4, 27, &queen), commit(4, 27, &empty));
+        assert_ne!(commit(4, 27, &queen), commit(5, 27, &queen));
+        assert_ne!(commit(4, 27, &queen), commit(4, 28, &queen));
+        let mut garbage = queen;
+        garbage[0] = 9;
+        assert!(contents(&garbage).is_err());
+    }
+}
@@ -0,0 +1,1257 @@
+//! Mail plays a fog game by correspondence. Each player plays their ply with
+//! a command that writes a small signed file, sends it to the other however
+//! they like, and reads the file sent back with another. Nothing is ever
+//! online. A player's side of the game, with its seed and signing key, is
+//! kept between commands in a private state file.
+//!
+//! # Files
+//!
+//! As between peers, each file commits to the sender's pieces without
+//! showing them, and the receiver learns the enemy pieces it can see by
+//! oblivious transfer, as described in `crypto`. There is no going back and
+//! forth within a ply by mail, so every file carries both halves of a
+//! transfer: answers to the queries in the other player's last file, and
+//! queries for what the sender will see once the other has moved.
+//!
+//! White starts the game with an invitation committing to its pieces. Black
+//! accepts it, committing to its own and asking what it will see after
+//! White's first ply. From then on the player to move sends one file a ply,
+//! which carries:
+//!
+//! - the square the ply captured on, if any;
+//! - commitments to every square after the ply, and a new transfer key;
+//! - answers, from those squares, to the other player's last queries;
+//! - queries, against the other player's last key, for what the sender's
+//!   pieces will see once the other has replied;
+//! - a draw offer, if the sender makes one.
+//!
+//! Instead of moving, the player to move may resign or accept a standing
+//! offer.
+//!
+//! # Sight in one transfer
+//!
+//! What can be seen past a square depends on what stands on it, which a
+//! single transfer cannot wait to learn. Instead there is a query for every
+//! line a piece might look along: for each square and direction, choosing
+//! how far along the line to look, and for each square, choosing whether to
+//! open the knight's jumps from it. The answer for a line opens its squares
+//! in order, each keyed on the opening of the one before it when that was
+//! empty, so sight stops at the first enemy piece whatever was chosen. The
+//! receiver's own pieces stop it by its choice, which is checked at the end.
+//! Every line is asked about, looked along or not, so the queries give
+//! nothing away.
+//!
+//! # Verification
+//!
+//! Files are signed with a key made for the game, pinned from the first
+//! file, and carry the hash of the file before them, so neither player can
+//! change, drop or replay one. Openings are checked against the commitments
+//! as they arrive. When the game ends both players send their seed and plies,
+//! and each replays the game to check everything the other sent, as between
+//! peers, down to how far along every line it chose to look. A player caught
+//! cheating loses. A player who never reveals cannot be checked.
+
+use super::{committed, crypto, enemy, mine, replay, square, without, Ending, SQUARES};
+use crate::{
+    in_bounds, notation, parse_notation, Board, Game, Move, Notice, Player, Report, Step, Unit,
+};
+use crypto::{Opening, Seed};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+// Directions a piece can look along: straight, then diagonal.
+const DIRECTIONS: [(i32, i32); 8] = [
+    (1, 0),
+    (-1, 0),
+    (0, 1),
+    (0, -1),
+    (1, 1),
+    (-1, -1),
+    (1, -1),
+    (-1, 1),
+];
+
+// A knight's jumps.
+const JUMPS: [(i32, i32); 8] = [
+    (1, 2),
+    (2, 1),
+    (2, -1),
+    (1, -2),
+    (-1, -2),
+    (-2, -1),
+    (-2, 1),
+    (-1, 2),
+];
+
+// A line a piece may look along, asked about in a single transfer.
+enum Line {
+    // The squares from a square in a direction, to the edge of the board.
+    Ray {
+        from: (i32, i32),
+        step: (i32, i32),
+        squares: Vec<(i32, i32)>,
+    },
+    // The squares a knight on a square jumps to.
+    Jumps {
+        from: (i32, i32),
+        squares: Vec<(i32, i32)>,
+    },
+}
+
+// Every line, always in the same order.
+fn lines() -> Vec<Line> {
+    let mut lines = vec![];
+    for index in 0..SQUARES {
+        let from = square(index);
+        for step in DIRECTIONS.iter() {
+            let squares: Vec<_> = (1..8)
+                .map(|n| (from.0 + step.0 * n, from.1 + step.1 * n))
+                .take_while(|at| in_bounds(*at))
+                .collect();
+            if !squares.is_empty() {
+                let step = *step;
+                lines.push(Line::Ray {
+                    from,
+                    step,
+                    squares,
+                });
+            }
+        }
+        let squares = JUMPS
+            .iter()
+            .map(|jump| (from.0 + jump.0, from.1 + jump.1))
+            .filter(|at| in_bounds(*at))
+            .collect();
+        lines.push(Line::Jumps { from, squares });
+    }
+    lines
+}
+
+impl Line {
+    fn from(&self) -> (i32, i32) {
+        match self {
+            Line::Ray { from, .. } | Line::Jumps { from, .. } => *from,
+        }
+    }
+
+    // How many choices the line's transfer offers.
+    fn choices(&self) -> u8 {
+        match self {
+            Line::Ray { squares, .. } => squares.len() as u8 + 1,
+            Line::Jumps { .. } => 2,
+        }
+    }
+
+    // How many blocks the answer for the line takes: a depth token and a
+    // sealed opening for each square of a ray, and a sealed opening for
+    // each jump.
+    fn size(&self) -> usize {
+        match self {
+            Line::Ray { squares, .. } => squares.len() * 2,
+            Line::Jumps { squares, .. } => squares.len(),
+        }
+    }
+
+    // How far along the line the pieces on `own` can see, before any enemy
+    // piece stops them: 0 for not at all, and 1 to open a knight's jumps.
+    fn reach(&self, own: &Board) -> u8 {
+        let piece = match own.get(self.from()) {
+            Some(piece) => piece,
+            None => return 0,
+        };
+        let (step, squares) = match self {
+            Line::Jumps { .. } => return (piece.unit == Unit::Knight) as u8,
+            Line::Ray { step, squares, .. } => (step, squares),
+        };
+        let straight = step.0 == 0 || step.1 == 0;
+        let forward = (0, if piece.player == Player::White { 1 } else { -1 });
+        let far = match piece.unit {
+            Unit::Queen => squares.len(),
+            Unit::Rook if straight => squares.len(),
+            Unit::Bishop if !straight => squares.len(),
+            // An unmoved pawn sees as far as its first move reaches.
+            Unit::Pawn if piece.moved == 0 && *step == forward => 2,
+            _ => 1,
+        }
+        .min(squares.len());
+        // The player's own pieces stop sight, and are seen.
+        squares
+            .iter()
+            .take(far)
+            .position(|at| own.get(*at).is_some())
+            .map_or(far, |blocked| blocked + 1) as u8
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Line::Ray { from, squares, .. } => write!(
+                f,
+                "from {} towards {}",
+                notation(*from),
+                notation(*squares.last().expect("rays are never empty"))
+            ),
+            Line::Jumps { from, .. } => write!(f, "at the jumps from {}", notation(*from)),
+        }
+    }
+}
+
+// Names the transfer for a line, asked about from the pieces at a ply.
+fn context(ply: u32, line: usize) -> [u8; 6] {
+    let ply = ply.to_le_bytes();
+    let line = (line as u16).to_le_bytes();
+    [ply[0], ply[1], ply[2], ply[3], line[0], line[1]]
+}
+
+// Keys for each square along a ray, each derived from the next so that a
+// receiver that can look so far can look any less far. The last only comes
+// from the seed.
+fn depths(seed: &Seed, context: &[u8], length: usize) -> Vec<[u8; 32]> {
+    let mut depth = crypto::secret(seed, b"depth", &[context]);
+    let mut depths = vec![depth];
+    for _ in 1..length {
+        depth = crypto::hash(b"depth", &[&depth]);
+        depths.push(depth);
+    }
+    depths.reverse();
+    depths
+}
+
+// What one file says.
+#[derive(Deserialize, Serialize)]
+struct Turn {
+    game: u64,
+    // Files sent before this one in the game, and the hash of the last.
+    seq: u32,
+    previous: [u8; 32],
+    player: Player,
+    name: String,
+    // The key the file is signed with.
+    key: [u8; 32],
+    // The square the ply played captured on, if any, when one was played.
+    played: Option<Option<(i32, i32)>>,
+    commit: Option<Commit>,
+    answers: Vec<[u8; 32]>,
+    queries: Vec<[u8; 32]>,
+    // Offers a draw with a ply, or accepts one instead.
+    offer: bool,
+    resign: bool,
+    // The sender's seed and plies, once the game is over.
+    reveal: Option<(Seed, Vec<Vec<Move>>)>,
+}
+
+// Commitments to every square at a ply, and the transfer key for it.
+#[derive(Clone, Deserialize, Serialize)]
+struct Commit {
+    ply: u32,
+    squares: Vec<[u8; 32]>,
+    key: [u8; 32],
+}
+
+// A file: a turn and the signature over it.
+#[derive(Deserialize, Serialize)]
+struct Signed {
+    turn: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+// Queries the player made from their pieces at a ply, against the
+// opponent's key.
+#[derive(Deserialize, Serialize)]
+struct Asked {
+    ply: u32,
+    sender: [u8; 32],
+    own: Board,
+}
+
+// Queries the opponent made from its pieces at a ply, against the player's
+// key for the ply `key`, still to be answered.
+#[derive(Deserialize, Serialize)]
+struct Pending {
+    ply: u32,
+    key: u32,
+    points: Vec<[u8; 32]>,
+}
+
+// What the opponent committed to, asked for and claimed, kept to be checked
+// once it reveals its seed.
+#[derive(Default, Deserialize, Serialize)]
+struct Transcript {
+    // Commitments and transfer key by ply.
+    commits: BTreeMap<u32, ([u8; 32], Vec<[u8; 32]>)>,
+    // Queries by the ply of the pieces they were made from, with the ply
+    // of the player's key they were made against.
+    queries: BTreeMap<u32, (u32, Vec<[u8; 32]>)>,
+    // The square it said it captured on, by the ply it played.
+    captures: BTreeMap<u32, Option<(i32, i32)>>,
+}
+
+/// Correspondence is one player's side of a game played by mail.
+#[derive(Deserialize, Serialize)]
+pub struct Correspondence {
+    pub player: Player,
+    name: String,
+    // Set from the opponent's first file.
+    opponent: Option<String>,
+    game: u64,
+    seed: Seed,
+    // Signs the player's files.
+    signing: [u8; 32],
+    // The key the opponent signs with, pinned from its first file.
+    their_key: Option<[u8; 32]>,
+    // Files sent so far by both players, and the hash of the last.
+    files: u32,
+    last: [u8; 32],
+    // The player's own pieces.
+    own: Board,
+    // Their own pieces and the enemy pieces they last saw.
+    known: Board,
+    visible: [[bool; 8]; 8],
+    turn: Player,
+    ply: u32,
+    notices: Vec<Notice>,
+    // Who has a draw offer standing.
+    offer: Option<Player>,
+    // The plies played, in order.
+    moves: Vec<Vec<Move>>,
+    // The ply the player last committed to, whose key the opponent's next
+    // queries are made against.
+    committed: u32,
+    // The opponent's last transfer key.
+    their_sender: Option<[u8; 32]>,
+    pending: Option<Pending>,
+    asked: Option<Asked>,
+    ending: Option<Ending>,
+    revealed: bool,
+    their_reveal: Option<(Seed, Vec<Vec<Move>>)>,
+    // Whether the opponent's side of the finished game checked out.
+    verified: Option<bool>,
+    transcript: Transcript,
+}
+
+impl Correspondence {
+    fn start(player: Player, name: &str, game: u64) -> Self {
+        let board = Board::new();
+        let own = mine(&board, player);
+        Correspondence {
+            player,
+            name: name.into(),
+            opponent: None,
+            game,
+            seed: rand::random(),
+            signing: rand::random(),
+            their_key: None,
+            files: 0,
+            last: [0; 32],
+            known: own.clone(),
+            own,
+            visible: board.visibility(&player),
+            turn: Player::White,
+            ply: 0,
+            notices: vec![],
+            offer: None,
+            moves: vec![],
+            committed: 0,
+            their_sender: None,
+            pending: None,
+            asked: None,
+            ending: None,
+            revealed: false,
+            their_reveal: None,
+            verified: None,
+            transcript: Transcript::default(),
+        }
+    }
+
+    /// New starts a game as White, returning the player's side of it and the
+    /// invitation to send.
+    pub fn new(name: &str) -> (Self, Vec<u8>) {
+        let mut state = Self::start(Player::White, name, rand::random());
+        let mut turn = state.turn();
+        turn.commit = Some(state.commit());
+        let file = state.sign(turn);
+        (state, file)
+    }
+
+    /// Accept takes up an invitation as Black, returning the player's side
+    /// of the game and the reply to send.
+    pub fn accept(invitation: &[u8], name: &str) -> Result<(Self, Vec<u8>), String> {
+        let (_, turn) = unpack(invitation)?;
+        let mut state = Self::start(Player::Black, name, turn.game);
+        state.read(invitation)?;
+        if let Some((_, reason)) = &state.ending {
+            return Err(format!("bad invitation: {}", reason));
+        }
+        let mut turn = state.turn();
+        turn.commit = Some(state.commit());
+        turn.queries = state.ask()?;
+        let file = state.sign(turn);
+        Ok((state, file))
+    }
+
+    /// Load reads the player's side of a game from its state file.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let bytes = fs::read(path).map_err(|err| format!("reading {}: {}", path.display(), err))?;
+        bincode::deserialize(&bytes).map_err(|_| format!("{} is not a game", path.display()))
+    }
+
+    /// Save writes the player's side of the game to its state file. A new
+    /// file is only ever created, never overwritten, so a game in progress
+    /// cannot be lost to a mistyped command. An existing file is replaced
+    /// whole, by way of a partial file, so a failed save leaves the last
+    /// one intact. The file holds secrets, so only its owner may read it.
+    pub fn save(&self, path: &Path, create: bool) -> Result<(), String> {
+        let bytes = bincode::serialize(self).map_err(|err| err.to_string())?;
+        let failed = |err: io::Error| format!("writing {}: {}", path.display(), err);
+        if create {
+            return write_private(path, &bytes).map_err(failed);
+        }
+        let partial = path.with_extension("partial");
+        let _ = fs::remove_file(&partial);
+        write_private(&partial, &bytes)
+            .and_then(|_| fs::rename(&partial, path))
+            .map_err(failed)
+    }
+
+    /// Parse move reads a ply as the squares it moves from and to, such as
+    /// `e2e4`. A castle is written as the king's square then the rook's.
+    pub fn parse_move(&self, text: &str) -> Result<Vec<Move>, String> {
+        let squares = if text.len() == 4 && text.is_ascii() {
+            parse_notation(&text[..2]).zip(parse_notation(&text[2..]))
+        } else {
+            None
+        };
+        let (from, to) = squares.ok_or_else(|| format!("{:?} is not a move such as e2e4", text))?;
+        let unit = |at| self.own.get(at).map(|piece| piece.unit);
+        Ok(match (unit(from), unit(to)) {
+            (Some(Unit::King), Some(Unit::Rook)) => self
+                .own
+                .castle(from, to)
+                .ok_or_else(|| format!("{} cannot castle", text))?
+                .to_vec(),
+            _ => vec![(from, to)],
+        })
+    }
+
+    /// Play plays a ply, if it is legal on what the player can see, and
+    /// returns the file to send.
+    pub fn play(&mut self, moves: Vec<Move>, offer: bool) -> Result<Vec<u8>, String> {
+        self.to_move()?;
+        let mut game = Game::headless(self.known.clone(), false, 0);
+        game.state.turn = self.player;
+        let step = Step {
+            player: self.player,
+            moves: moves.clone(),
+        };
+        if !game.apply(&step) {
+            return Err("illegal move".into());
+        }
+        let captured = match moves.as_slice() {
+            [(_, to)] => self
+                .known
+                .get(*to)
+                .filter(|piece| piece.player != self.player)
+                .map(|piece| (piece.unit, *to)),
+            _ => None,
+        };
+        let played = game.state.history.last().expect("a ply was played");
+        self.moves.push(played.moves.clone());
+        self.own = mine(&game.state.board, self.player);
+        self.known = game.state.board;
+        self.advance();
+        let mut turn = self.turn();
+        turn.played = Some(captured.map(|(_, at)| at));
+        if let Some((unit, at)) = captured {
+            self.notice(Report::Captured { unit, at });
+            if unit == Unit::King {
+                self.ending = Some((Some(self.player), "king captured".into()));
+                turn.reveal = Some(self.reveal_now());
+                return Ok(self.sign(turn));
+            }
+        }
+        turn.answers = self.answer(captured.map(|(_, at)| at))?;
+        turn.commit = Some(self.commit());
+        turn.queries = self.ask()?;
+        if offer {
+            turn.offer = true;
+            self.offer = Some(self.player);
+        }
+        Ok(self.sign(turn))
+    }
+
+    /// Draw accepts the opponent's standing draw offer instead of moving,
+    /// and returns the file to send.
+    pub fn draw(&mut self) -> Result<Vec<u8>, String> {
+        self.to_move()?;
+        if self.offer != Some(self.player.opponent()) {
+            return Err("there is no draw offer to accept".into());
+        }
+        self.ending = Some((None, "agreement".into()));
+        let mut turn = self.turn();
+        turn.offer = true;
+        turn.reveal = Some(self.reveal_now());
+        Ok(self.sign(turn))
+    }
+
+    /// Resign gives up the game instead of moving, and returns the file to
+    /// send.
+    pub fn resign(&mut self) -> Result<Vec<u8>, String> {
+        self.to_move()?;
+        self.ending = Some((Some(self.player.opponent()), "resignation".into()));
+        let mut turn = self.turn();
+        turn.resign = true;
+        turn.reveal = Some(self.reveal_now());
+        Ok(self.sign(turn))
+    }
+
+    /// Reveal answers the end of the game with the player's seed and plies,
+    /// so the opponent can check them, and returns the file to send.
+    pub fn reveal(&mut self) -> Result<Vec<u8>, String> {
+        if self.ending.is_none() {
+            return Err("the game is not over".into());
+        }
+        if self.revealed {
+            return Err("you have already revealed your game".into());
+        }
+        let mut turn = self.turn();
+        turn.reveal = Some(self.reveal_now());
+        Ok(self.sign(turn))
+    }
+
+    /// Read takes in the next file from the opponent. A file that is not
+    /// theirs, or out of order, is refused and changes nothing. Anything
+    /// false in one of theirs ends the game in the player's favour.
+    pub fn read(&mut self, file: &[u8]) -> Result<(), String> {
+        let (key, turn) = unpack(file)?;
+        if turn.game != self.game {
+            return Err("the file is from another game".into());
+        }
+        if turn.player == self.player {
+            return Err("the file is one of yours".into());
+        }
+        if self.their_key.is_some_and(|pinned| pinned != key) {
+            return Err("the file is not signed by your opponent".into());
+        }
+        if turn.seq != self.files || turn.previous != self.last {
+            return Err(format!(
+                "the file is number {} of the game, but number {} is next",
+                turn.seq + 1,
+                self.files + 1
+            ));
+        }
+        self.their_key = Some(key);
+        self.opponent = Some(turn.name.clone());
+        self.files += 1;
+        self.last = crypto::hash(b"file", &[file]);
+        if let Some(reveal) = turn.reveal.clone() {
+            self.their_reveal = Some(reveal);
+        }
+        if let Err(reason) = self.take(turn) {
+            let reason = format!("the opponent cheated: {}", reason);
+            self.ending = Some((Some(self.player), reason));
+        }
+        if let (Some(_), None, Some((seed, moves))) =
+            (&self.ending, self.verified, &self.their_reveal)
+        {
+            let checked = self.verify(seed, moves);
+            self.verified = Some(checked.is_ok());
+            if let Err(reason) = checked {
+                let reason = format!("the opponent cheated: {}", reason);
+                self.ending = Some((Some(self.player), reason));
+            }
+        }
+        Ok(())
+    }
+
+    // Act on what a file from the opponent says.
+    fn take(&mut self, turn: Turn) -> Result<(), String> {
+        let opponent = self.player.opponent();
+        if self.ending.is_some() {
+            return match turn {
+                Turn {
+                    played: None,
+                    commit: None,
+                    reveal: Some(_),
+                    ..
+                } => Ok(()),
+                _ => Err("it kept playing after the game ended".into()),
+            };
+        }
+        if turn.resign {
+            self.ending = Some((Some(self.player), "resignation".into()));
+            return Ok(());
+        }
+        let mut captured = None;
+        match turn.played {
+            Some(_) if self.turn != opponent => return Err("it played out of turn".into()),
+            Some(at) => {
+                self.transcript.captures.insert(self.ply, at);
+                let lost = match at {
+                    Some(at) => match self.own.get(at).map(|piece| piece.unit) {
+                        Some(unit) => {
+                            self.own = without(&self.own, at);
+                            Some((unit, at))
+                        }
+                        None => {
+                            return Err(format!(
+                                "it claimed a capture on {}, where nothing of ours stood",
+                                notation(at)
+                            ))
+                        }
+                    },
+                    None => None,
+                };
+                self.advance();
+                if let Some((Unit::King, _)) = lost {
+                    self.ending = Some((Some(opponent), "king captured".into()));
+                    return Ok(());
+                }
+                captured = lost;
+                if turn.offer {
+                    self.offer = Some(opponent);
+                }
+            }
+            None if turn.offer && self.offer == Some(self.player) => {
+                self.ending = Some((None, "agreement".into()));
+                return Ok(());
+            }
+            None if turn.seq > 1 => return Err("it sent a file without a ply".into()),
+            None => {}
+        }
+        let commit = match turn.commit {
+            Some(commit)
+                if commit.ply == self.ply
+                    && commit.squares.len() == SQUARES
+                    && crypto::valid(&commit.key) =>
+            {
+                commit
+            }
+            _ => {
+                return Err(format!(
+                    "it did not commit to its pieces at ply {}",
+                    self.ply
+                ))
+            }
+        };
+        self.transcript
+            .commits
+            .insert(commit.ply, (commit.key, commit.squares.clone()));
+        self.their_sender = Some(commit.key);
+        match self.asked.take() {
+            Some(asked) => {
+                let at = captured.map(|(_, at)| at);
+                self.known = self.open(&asked, &commit, &turn.answers, at)?;
+                self.visible = self.known.visibility(&self.player);
+            }
+            None if turn.answers.is_empty() => {}
+            None => return Err("it answered queries that were never made".into()),
+        }
+        if let Some((unit, at)) = captured {
+            let by = self.known.get(at).map(|piece| piece.unit);
+            self.notice(Report::Lost { unit, at, by });
+        }
+        // Only the invitation asks nothing, as there is nothing yet to ask
+        // against.
+        if turn.seq > 0 {
+            let count = lines().len();
+            if turn.queries.len() != count || !turn.queries.iter().all(crypto::valid) {
+                return Err(format!("its queries at ply {} are malformed", self.ply));
+            }
+            self.transcript
+                .queries
+                .insert(self.ply, (self.committed, turn.queries.clone()));
+            self.pending = Some(Pending {
+                ply: self.ply,
+                key: self.committed,
+                points: turn.queries,
+            });
+        }
+        Ok(())
+    }
+
+    // Check that the player may send a file now, in place of a ply.
+    fn to_move(&self) -> Result<(), String> {
+        if let Some((_, reason)) = &self.ending {
+            return Err(format!("the game is over: {}", reason));
+        }
+        if self.opponent.is_none() {
+            return Err("the invitation has not been accepted yet".into());
+        }
+        if self.turn != self.player {
+            return Err("it is not your turn".into());
+        }
+        Ok(())
+    }
+
+    // Move on to the next ply.
+    fn advance(&mut self) {
+        self.ply += 1;
+        self.turn = self.turn.opponent();
+        self.offer = None;
+    }
+
+    fn notice(&mut self, report: Report) {
+        self.notices.push(Notice {
+            ply: self.ply - 1,
+            player: self.player,
+            report,
+        });
+    }
+
+    fn reveal_now(&mut self) -> (Seed, Vec<Vec<Move>>) {
+        self.revealed = true;
+        (self.seed, self.moves.clone())
+    }
+
+    // A file from the player, with nothing in it yet.
+    fn turn(&self) -> Turn {
+        Turn {
+            game: self.game,
+            seq: self.files,
+            previous: self.last,
+            player: self.player,
+            name: self.name.clone(),
+            key: SigningKey::from_bytes(&self.signing)
+                .verifying_key()
+                .to_bytes(),
+            played: None,
+            commit: None,
+            answers: vec![],
+            queries: vec![],
+            offer: false,
+            resign: false,
+            reveal: None,
+        }
+    }
+
+    // Sign a file, counting it as sent.
+    fn sign(&mut self, turn: Turn) -> Vec<u8> {
+        let turn = bincode::serialize(&turn).expect("turns always encode");
+        let signature = SigningKey::from_bytes(&self.signing).sign(&turn);
+        let signed = Signed {
+            turn,
+            signature: signature.to_bytes().to_vec(),
+        };
+        let file = bincode::serialize(&signed).expect("files always encode");
+        self.files += 1;
+        self.last = crypto::hash(b"file", &[&file]);
+        file
+    }
+
+    fn opening(&self, index: usize) -> Opening {
+        let unit = self.own.get(square(index)).map(|piece| piece.unit);
+        crypto::opening(&self.seed, self.ply, index, unit)
+    }
+
+    // Commit to the player's pieces at the current ply.
+    fn commit(&mut self) -> Commit {
+        self.committed = self.ply;
+        Commit {
+            ply: self.ply,
+            squares: (0..SQUARES)
+                .map(|index| crypto::commit(self.ply, index, &self.opening(index)))
+                .collect(),
+            key: crypto::sender_key(&self.seed, self.ply).1,
+        }
+    }
+
+    // Ask what the player's pieces at the current ply can see, against the
+    // opponent's last key.
+    fn ask(&mut self) -> Result<Vec<[u8; 32]>, String> {
+        let sender = self.their_sender.ok_or("there is no key to ask against")?;
+        let points = lines()
+            .iter()
+            .enumerate()
+            .map(|(index, line)| {
+                let context = context(self.ply, index);
+                crypto::choose(&self.seed, &context, &sender, line.reach(&self.own))
+            })
+            .collect::<Result<_, _>>()?;
+        self.asked = Some(Asked {
+            ply: self.ply,
+            sender,
+            own: self.own.clone(),
+        });
+        Ok(points)
+    }
+
+    // Answer the opponent's last queries from the player's pieces at the
+    // current ply. Lines from the square just captured on are left empty,
+    // as the piece that looked along them is gone.
+    fn answer(&mut self, captured: Option<(i32, i32)>) -> Result<Vec<[u8; 32]>, String> {
+        let pending = match self.pending.take() {
+            Some(pending) => pending,
+            None => return Ok(vec![]),
+        };
+        let (secret, _) = crypto::sender_key(&self.seed, pending.key);
+        let mut answers = vec![];
+        for (index, (line, point)) in lines().iter().zip(pending.points.iter()).enumerate() {
+            if Some(line.from()) == captured {
+                answers.extend(vec![[0; 32]; line.size()]);
+                continue;
+            }
+            let context = context(pending.ply, index);
+            let keys = crypto::keys(&secret, &context, point, line.choices())?;
+            match line {
+                Line::Ray { squares, .. } => {
+                    let depths = depths(&self.seed, &context, squares.len());
+                    for (key, depth) in keys[1..].iter().zip(depths.iter()) {
+                        answers.push(crypto::xor(key, depth));
+                    }
+                    let mut gate = [0; 32];
+                    for (n, (at, depth)) in squares.iter().zip(depths.iter()).enumerate() {
+                        let index = (at.1 * 8 + at.0) as usize;
+                        let opening = self.opening(index);
+                        answers.push(crypto::xor(
+                            &opening,
+                            &crypto::hash(b"line", &[depth, &gate]),
+                        ));
+                        // Past one of the player's pieces, the way stays shut.
+                        gate = match self.own.get(*at) {
+                            None => opening,
+                            Some(_) => crypto::secret(&self.seed, b"gate", &[&context, &[n as u8]]),
+                        };
+                    }
+                }
+                Line::Jumps { squares, .. } => {
+                    for (n, at) in squares.iter().enumerate() {
+                        let index = (at.1 * 8 + at.0) as usize;
+                        let seal = crypto::hash(b"jump", &[&keys[1], &[n as u8]]);
+                        answers.push(crypto::xor(&self.opening(index), &seal));
+                    }
+                }
+            }
+        }
+        Ok(answers)
+    }
+
+    // Open the opponent's answers to the player's last queries, checking
+    // each opening against its commitment, and return the player's pieces
+    // with the enemy pieces they show.
+    fn open(
+        &self,
+        asked: &Asked,
+        commit: &Commit,
+        answers: &[[u8; 32]],
+        captured: Option<(i32, i32)>,
+    ) -> Result<Board, String> {
+        let mut known = self.own.clone();
+        let mut rest = answers;
+        for (index, line) in lines().iter().enumerate() {
+            if rest.len() < line.size() {
+                return Err(format!("its answers at ply {} are cut short", commit.ply));
+            }
+            let (answer, tail) = rest.split_at(line.size());
+            rest = tail;
+            let choice = line.reach(&asked.own);
+            if choice == 0 || Some(line.from()) == captured {
+                continue;
+            }
+            let context = context(asked.ply, index);
+            let key = crypto::receiver_key(&self.seed, &context, &asked.sender, choice)?;
+            let mut place = |at: (i32, i32), opening: &Opening| -> Result<Option<Unit>, String> {
+                let index = (at.1 * 8 + at.0) as usize;
+                if crypto::commit(commit.ply, index, opening) != commit.squares[index] {
+                    return Err(format!(
+                        "its opening of {} at ply {} is not what it committed to",
+                        notation(at),
+                        commit.ply
+                    ));
+                }
+                let unit = crypto::contents(opening)?;
+                if let Some(unit) = unit {
+                    if self.own.get(at).is_some() {
+                        return Err(format!("it claimed a piece on our square {}", notation(at)));
+                    }
+                    known.set(at, enemy(self.player, unit, at));
+                }
+                Ok(unit)
+            };
+            match line {
+                Line::Ray { squares, .. } => {
+                    let (tokens, sealed) = answer.split_at(squares.len());
+                    let mut depth = crypto::xor(&tokens[choice as usize - 1], &key);
+                    let mut depths = vec![depth];
+                    for _ in 1..choice {
+                        depth = crypto::hash(b"depth", &[&depth]);
+                        depths.push(depth);
+                    }
+                    depths.reverse();
+                    let mut gate = [0; 32];
+                    for ((at, sealed), depth) in squares.iter().zip(sealed).zip(depths.iter()) {
+                        let opening = crypto::xor(sealed, &crypto::hash(b"line", &[depth, &gate]));
+                        if place(*at, &opening)?.is_some() {
+                            break;
+                        }
+                        gate = opening;
+                    }
+                }
+                Line::Jumps { squares, .. } => {
+                    for (n, (at, sealed)) in squares.iter().zip(answer).enumerate() {
+                        let opening =
+                            crypto::xor(sealed, &crypto::hash(b"jump", &[&key, &[n as u8]]));
+                        place(*at, &opening)?;
+                    }
+                }
+            }
+        }
+        if !rest.is_empty() {
+            return Err(format!("its answers at ply {} run long", commit.ply));
+        }
+        Ok(known)
+    }
+
+    // Replay the finished game and check everything the opponent sent
+    // against it, down to how far along every line it chose to look.
+    fn verify(&self, their_seed: &Seed, theirs: &[Vec<Move>]) -> Result<(), String> {
+        let opponent = self.player.opponent();
+        let lines = lines();
+        let captures = &self.transcript.captures;
+        replay(self.player, &self.moves, theirs, captures, |ply, board| {
+            if let Some((key, squares)) = self.transcript.commits.get(&ply) {
+                committed(opponent, their_seed, ply, board, squares, key)?;
+            }
+            let (key, points) = match self.transcript.queries.get(&ply) {
+                Some(queries) => queries,
+                None => return Ok(()),
+            };
+            let own = mine(board, opponent);
+            let (_, ours) = crypto::sender_key(&self.seed, *key);
+            for (index, (line, point)) in lines.iter().zip(points.iter()).enumerate() {
+                let context = context(ply, index);
+                match crypto::chosen(their_seed, &context, &ours, point, line.choices()) {
+                    None => {
+                        return Err(format!(
+                            "its query for the line {} at ply {} is not from its seed",
+                            line, ply
+                        ))
+                    }
+                    Some(choice) if choice > line.reach(&own) => {
+                        return Err(format!(
+                            "it looked {} at ply {}, beyond its vision",
+                            line, ply
+                        ))
+                    }
+                    Some(_) => {}
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+// Read a file, checking it is signed by the key it names.
+fn unpack(file: &[u8]) -> Result<([u8; 32], Turn), String> {
+    let broken = || "not a move file".to_string();
+    let signed: Signed = bincode::deserialize(file).map_err(|_| broken())?;
+    let turn: Turn = bincode::deserialize(&signed.turn).map_err(|_| broken())?;
+    let key = VerifyingKey::from_bytes(&turn.key).map_err(|_| broken())?;
+    let signature = Signature::from_slice(&signed.signature).map_err(|_| broken())?;
+    key.verify_strict(&signed.turn, &signature)
+        .map_err(|_| "the file's signature does not match".to_string())?;
+    Ok((turn.key, turn))
+}
+
+// Write a new file that only its owner may read or write, and make sure it
+// reached the disk.
+fn write_private(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    file.write_all(bytes)?;
+    file.sync_all()
+}
+
+impl fmt::Display for Correspondence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let opponent = self.opponent.as_deref().unwrap_or("no one yet");
+        writeln!(
+            f,
+            "game {:016x}: {} ({:?}) against {}, ply {}",
+            self.game, self.name, self.player, opponent, self.ply
+        )?;
+        // The player's own side of the board is shown at the bottom.
+        let ranks: Vec<i32> = match self.player {
+            Player::White => (0..8).rev().collect(),
+            Player::Black => (0..8).collect(),
+        };
+        for y in ranks {
+            write!(f, "{} ", y + 1)?;
+            for x in 0..8 {
+                let symbol = match self.known.get((x, y)) {
+                    Some(piece) => {
+                        let letter = match piece.unit {
+                            Unit::Pawn => 'p',
+                            Unit::Rook => 'r',
+                            Unit::Knight => 'n',
+                            Unit::Bishop => 'b',
+                            Unit::Queen => 'q',
+                            Unit::King => 'k',
+                        };
+                        match piece.player {
+                            Player::White => letter.to_ascii_uppercase(),
+                            Player::Black => letter,
+                        }
+                    }
+                    None if self.visible[y as usize][x as usize] => '.',
+                    None => '#',
+                };
+                write!(f, " {}", symbol)?;
+            }
+            writeln!(f)?;
+        }
+        writeln!(f, "   a b c d e f g h")?;
+        for notice in self
+            .notices
+            .iter()
+            .filter(|notice| notice.ply + 2 >= self.ply)
+        {
+            writeln!(f, "{}", notice)?;
+        }
+        match &self.ending {
+            Some((winner, reason)) => {
+                let result = match winner {
+                    Some(winner) if *winner == self.player => "you won",
+                    Some(_) => "you lost",
+                    None => "drawn",
+                };
+                writeln!(f, "game over, {}: {}", result, reason)?;
+                if !self.revealed {
+                    writeln!(f, "reveal your game so your opponent can check it")?;
+                }
+                match self.verified {
+                    Some(true) => writeln!(f, "your opponent's game checks out"),
+                    Some(false) => Ok(()),
+                    None => writeln!(f, "waiting for your opponent to reveal their game"),
+                }
+            }
+            None if self.opponent.is_none() => {
+                writeln!(f, "waiting for the invitation to be accepted")
+            }
+            None => {
+                if self.offer == Some(self.player.opponent()) {
+                    writeln!(f, "{} offers a draw", opponent)?;
+                }
+                if self.turn == self.player {
+                    writeln!(f, "your move")
+                } else {
+                    writeln!(f, "waiting for {} to move", opponent)
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The squares a player's lines open on a board, with each ray stopped
+    // by the first enemy piece along it, and their own squares.
+    fn opened(board: &Board, player: Player) -> [[bool; 8]; 8] {
+        let own = mine(board, player);
+        let mut seen = [[false; 8]; 8];
+        let mut mark = |at: &(i32, i32)| seen[at.1 as usize][at.0 as usize] = true;
+        (0..SQUARES)
+            .map(square)
+            .filter(|at| own.get(*at).is_some())
+            .for_each(|at| mark(&at));
+        for line in lines() {
+            let reach = line.reach(&own) as usize;
+            match &line {
+                Line::Ray { squares, .. } => {
+                    for at in squares.iter().take(reach) {
+                        mark(at);
+                        if board.contains_enemy_of(player, *at) {
+                            break;
+                        }
+                    }
+                }
+                Line::Jumps { squares, .. } if reach == 1 => squares.iter().for_each(&mut mark),
+                Line::Jumps { .. } => {}
+            }
+        }
+        seen
+    }
+
+    // Plies of a short game, with a capture on each side.
+    fn plies() -> Vec<Vec<Move>> {
+        vec![
+            vec![((4, 1), (4, 3))],
+            vec![((3, 6), (3, 4))],
+            vec![((4, 3), (3, 4))],
+            vec![((3, 7), (3, 4))],
+            vec![((6, 0), (5, 2))],
+            vec![((3, 4), (0, 1))],
+        ]
+    }
+
+    #[test]
+    fn lines_open_exactly_what_can_be_seen() {
+        let mut game = Game::headless(Board::new(), false, 0);
+        for moves in plies() {
+            let player = game.state.turn;
+            assert!(game.apply(&Step { player, moves }));
+            for player in &[Player::White, Player::Black] {
+                let board = &game.state.board;
+                assert_eq!(opened(board, *player), board.visibility(player));
+            }
+        }
+    }
+
+    // Carry a file to a player, checking their view against the real game.
+    fn post(to: &mut Correspondence, file: &[u8], game: &Game) {
+        to.read(file).unwrap();
+        assert_eq!(to.ending, None);
+        let visible = game.state.board.visibility(&to.player);
+        assert_eq!(to.visible, visible);
+        for at in (0..SQUARES).map(square) {
+            let seen = visible[at.1 as usize][at.0 as usize];
+            let unit = |board: &Board| board.get(at).map(|piece| (piece.unit, piece.player));
+            let truth = unit(&game.state.board).filter(|_| seen);
+            assert_eq!(
+                unit(&to.known),
+                truth,
+                "{:?} at {}",
+                to.player,
+                notation(at)
+            );
+        }
+    }
+
+    // Play the plies by mail.
+    fn play(plies: Vec<Vec<Move>>) -> (Correspondence, Correspondence, Game) {
+        let (mut white, invitation) = Correspondence::new("white");
+        let (mut black, reply) = Correspondence::accept(&invitation, "black").unwrap();
+        let mut game = Game::headless(Board::new(), false, 0);
+        post(&mut white, &reply, &game);
+        for moves in plies {
+            let player = game.state.turn;
+            let (mover, other) = match player {
+                Player::White => (&mut white, &mut black),
+                Player::Black => (&mut black, &mut white),
+            };
+            let file = mover.play(moves.clone(), false).unwrap();
+            assert!(game.apply(&Step { player, moves }));
+            post(other, &file, &game);
+        }
+        (white, black, game)
+    }
+
+    #[test]
+    fn a_game_by_mail_is_verified() {
+        let (mut white, mut black, _) = play(plies());
+        assert!(black.play(vec![((4, 6), (4, 4))], false).is_err());
+        let resignation = white.resign().unwrap();
+        black.read(&resignation).unwrap();
+        let win = Some((Some(Player::Black), "resignation".to_string()));
+        assert_eq!(black.ending, win);
+        assert_eq!(black.verified, Some(true));
+        let reveal = black.reveal().unwrap();
+        white.read(&reveal).unwrap();
+        assert_eq!(white.ending, win);
+        assert_eq!(white.verified, Some(true));
+    }
+
+    #[test]
+    fn moves_that_are_not_ascii_are_refused() {
+        let (white, _) = Correspondence::new("white");
+        assert!(white.parse_move("a\u{e9}b").is_err());
+        assert!(white.parse_move("e2e4").is_ok());
+    }
+
+    #[test]
+    fn saves_replace_the_state_file_privately() {
+        let dir = std::env::temp_dir().join(format!("mail-save-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("game.fog");
+        let (mut white, _) = Correspondence::new("white");
+        white.save(&path, true).unwrap();
+        assert!(white.save(&path, true).is_err());
+        white.name = "renamed".into();
+        white.save(&path, false).unwrap();
+        assert_eq!(Correspondence::load(&path).unwrap().name, "renamed");
+        assert!(!path.with_extension("partial").exists());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn castling_by_mail_is_verified() {
+        let (mut white, invitation) = Correspondence::new("white");
+        let (mut black, reply) = Correspondence::accept(&invitation, "black").unwrap();
+        white.read(&reply).unwrap();
+        let plies = [
+            "g1f3", "a7a6", "e2e3", "a6a5", "f1e2", "a5a4", "e1h1", "a4a3",
+        ];
+        let mut game = Game::headless(Board::new(), false, 0);
+        for text in plies.iter() {
+            let player = game.state.turn;
+            let (mover, other) = match player {
+                Player::White => (&mut white, &mut black),
+                Player::Black => (&mut black, &mut white),
+            };
+            let moves = mover.parse_move(text).unwrap();
+            let file = mover.play(moves.clone(), false).unwrap();
+            assert!(game.apply(&Step { player, moves }));
+            post(other, &file, &game);
+        }
+        let unit = |at| game.state.board.get(at).map(|piece| piece.unit);
+        assert_eq!(unit((6, 0)), Some(Unit::King));
+        assert_eq!(unit((5, 0)), Some(Unit::Rook));
+        let resignation = white.resign().unwrap();
+        black.read(&resignation).unwrap();
+        assert_eq!(black.verified, Some(true));
+        let reveal = black.reveal().unwrap();
+        white.read(&reveal).unwrap();
+        assert_eq!(white.verified, Some(true));
+    }
+
+    #[test]
+    fn peeking_is_caught() {
+        let (mut white, black, _) = play(plies());
+        // Black asks along the line from e1, where it has no piece.
+        let (index, line) = lines()
+            .into_iter()
+            .enumerate()
+            .find(|(_, line)| line.from() == (4, 0))
+            .unwrap();
+        let (_, sender) = crypto::sender_key(&white.seed, 0);
+        let (_, queries) = white.transcript.queries.get_mut(&0).unwrap();
+        queries[index] = crypto::choose(&black.seed, &context(0, index), &sender, 1).unwrap();
+        let err = white.verify(&black.seed, &black.moves).unwrap_err();
+        assert_eq!(
+            err,
+            format!("it looked {} at ply 0, beyond its vision", line)
+        );
+    }
+
+    #[test]
+    fn files_out_of_place_are_refused() {
+        let (mut white, invitation) = Correspondence::new("white");
+        let (_, reply) = Correspondence::accept(&invitation, "black").unwrap();
+        let mut forged = reply.clone();
+        let last = forged.len() - 1;
+        forged[last] ^= 1;
+        assert!(white.read(&forged).is_err());
+        assert!(white.read(&invitation).is_err());
+        let (_, other) = Correspondence::accept(&invitation, "someone").unwrap();
+        white.read(&reply).unwrap();
+        assert!(white.read(&reply).is_err());
+        assert!(white.read(&other).is_err());
+        assert_eq!(white.files, 2);
+    }
+}
@@ -0,0 +1,197 @@
+//! Portable Game Notation import and export.
+
+use crate::{notation, Board, Step, Unit};
+
+/// San names a step in standard algebraic notation, for example `Nbd7`,
+/// `exd5` or `O-O-O`. The board is the position before the step. There is
+/// no check in fog of war, so moves never carry `+` or `#`.
+pub fn san(board: &Board, step: &Step) -> String {
+    let (from, to) = match step.moves.as_slice() {
+        [mv] => *mv,
+        moves => {
+            // Castling: name the side by which way the king travels.
+            let king = moves
+                .iter()
+                .find(|(from, _)| matches!(board.get(*from), Some(p) if p.unit == Unit::King));
+            return match king {
+                Some((from, to)) if to.0 < from.0 => "O-O-O".into(),
+                _ => "O-O".into(),
+            };
+        }
+    };
+    let piece = match board.get(from) {
+        Some(piece) => piece,
+        None => return format!("{}{}", notation(from), notation(to)),
+    };
+    let capture = board.get(to).is_some();
+    let target = notation(to);
+    let origin = notation(from);
+    if piece.unit == Unit::Pawn {
+        return if capture {
+            format!("{}x{}", &origin[..1], target)
+        } else {
+            target
+        };
+    }
+    // Disambiguate from any other unit of the same kind that could also
+    // reach the target.
+    let rivals: Vec<(i32, i32)> = board
+        .moves_for(&piece.player)
+        .into_iter()
+        .filter(|(other, dest)| {
+            *other != from
+                && *dest == to
+                && matches!(board.get(*other), Some(p) if p.unit == piece.unit)
+        })
+        .map(|(other, _)| other)
+        .collect();
+    let disambiguation = if rivals.is_empty() {
+        ""
+    } else if rivals.iter().all(|other| other.0 != from.0) {
+        &origin[..1]
+    } else if rivals.iter().all(|other| other.1 != from.1) {
+        &origin[1..]
+    } else {
+        &origin[..]
+    };
+    format!(
+        "{}{}{}{}",
+        letter(piece.unit),
+        disambiguation,
+        if capture { "x" } else { "" },
+        target
+    )
+}
+
+/// Export renders a game as PGN. Tags are written in the order given, and
+/// `comments` optionally annotates each step.
+pub fn export(
+    tags: &[(&str, String)],
+    initial: &Board,
+    history: &[Step],
+    comments: &[Option<String>],
+    result: &str,
+) -> String {
+    let mut out = String::new();
+    for (name, value) in tags {
+        out.push_str(&format!("[{} \"{}\"]\n", name, value.replace('"', "'")));
+    }
+    out.push('\n');
+    let mut board = initial.clone();
+    let mut tokens = vec![];
+    for (ii, step) in history.iter().enumerate() {
+        if ii % 2 == 0 {
+            tokens.push(format!("{}.", ii / 2 + 1));
+        } else if matches!(comments.get(ii - 1), Some(Some(_))) {
+            // Black's move needs its number again after a comment.
+            tokens.push(format!("{}...", ii / 2 + 1));
+        }
+        tokens.push(san(&board, step));
+        if let Some(Some(comment)) = comments.get(ii) {
+            tokens.push(format!("{{{}}}", comment.replace('}', ")")));
+        }
+        for (from, to) in step.moves.iter() {
+            board.move_piece(*from, *to);
+        }
+    }
+    tokens.push(result.into());
+    // Wrap movetext at 80 columns, as the PGN standard recommends.
+    let mut line = String::new();
+    for token in tokens {
+        if !line.is_empty() && line.len() + token.len() + 1 > 80 {
+            out.push_str(&line);
+            out.push('\n');
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(&token);
+    }
+    out.push_str(&line);
+    out.push_str("\n\n");
+    out
+}
+
+/// Import is a game read back from PGN.
+#[derive(Clone, Debug, Default)]
+pub struct Import {
+    pub tags: Vec<(String, String)>,
+    /// Each ply in standard algebraic notation.
+    pub moves: Vec<String>,
+    /// Comment following each ply, if any.
+    pub comments: Vec<Option<String>>,
+    pub result: String,
+}
+
+impl Import {
+    /// Tag looks up the value of the named tag.
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(tag, _)| tag == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Parse reads the first game from PGN text, as written by `export`. Moves
+/// are left in notation, to be played with `Game::play_san`.
+pub fn parse(text: &str) -> Result<Import, String> {
+    let mut import = Import::default();
+    let mut movetext = String::new();
+    for line in text.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            if !movetext.trim().is_empty() {
+                // The next game's tags.
+                break;
+            }
+            let inner = line.trim_start_matches('[').trim_end_matches(']');
+            let (name, value) =
+                inner.split_at(inner.find(' ').ok_or(format!("malformed tag {:?}", line))?);
+            import
+                .tags
+                .push((name.into(), value.trim().trim_matches('"').into()));
+        } else {
+            movetext.push_str(line);
+            movetext.push(' ');
+        }
+    }
+    let mut rest = movetext.as_str();
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            break;
+        }
+        if let Some(body) = rest.strip_prefix('{') {
+            let end = body.find('}').ok_or("unterminated comment")?;
+            if let Some(last) = import.comments.last_mut() {
+                *last = Some(body[..end].trim().into());
+            }
+            rest = &body[end + 1..];
+            continue;
+        }
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let token = &rest[..end];
+        rest = &rest[end..];
+        if matches!(token, "1-0" | "0-1" | "1/2-1/2" | "*") {
+            import.result = token.into();
+        } else if !token.starts_with(|c: char| c.is_ascii_digit()) {
+            import.moves.push(token.into());
+            import.comments.push(None);
+        }
+    }
+    Ok(import)
+}
+
+// Letter used for a unit in algebraic notation.
+fn letter(unit: Unit) -> &'static str {
+    match unit {
+        Unit::Pawn => "",
+        Unit::Knight => "N",
+        Unit::Bishop => "B",
+        Unit::Rook => "R",
+        Unit::Queen => "Q",
+        Unit::King => "K",
+    }
+}
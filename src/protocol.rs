@@ -0,0 +1,681 @@
+//! Wire protocol spoken between the server and its clients, and between the
+//! engine and bots running as `proto:` processes.
+//!
+//! # Framing
+//!
+//! Every message travels in a frame: a four byte big-endian length followed
+//! by that many bytes of payload. The payload is a `Message` encoded with
+//! bincode's default options (variable length integers, little-endian).
+//! Frames longer than `MAX_FRAME` are refused without being read, after
+//! which the stream cannot be trusted and is closed. A payload that fails to
+//! decode leaves the stream in step, so the receiver may reject it and carry
+//! on.
+//!
+//! # Version negotiation
+//!
+//! The side that opens the connection sends `Hello` with the range of
+//! versions it speaks. The other side answers with a `Hello` whose range is
+//! the single version both will use, the highest they have in common, or
+//! with `GameOver` and closes the connection when there is none. Nothing
+//! else may be sent before the handshake completes.
+//!
+//! Variants and fields are never reordered or removed. New variants are
+//! appended under a new version and only sent to peers that negotiated it.
+//!
+//! | Version | Changes                                      |
+//! |---------|----------------------------------------------|
+//! | 1       | Initial protocol.                            |
+//! | 2       | `Session` and `Resume` for reconnection.     |
+//! | 3       | Lobby: listing, creating and watching games. |
+//! | 4       | `Spectators` count.                          |
+//! | 5       | Serverless play between peers.               |
+//! | 6       | `ChatGames` and `CreateChatGame`.            |
+//!
+//! # Exchange
+//!
+//! After the handshake, a client sends `Join` without a side and the server
+//! answers with `Join` naming the side it was given. From then on the
+//! server sends an `Update` whenever the client's view changes and a `Clock`
+//! after every move in timed games. Clients send `Move`, which the server
+//! either plays or answers with `MoveRejected`, as well as `Chat`, `Resign`
+//! and `DrawOffer`. A draw offer is passed on to the opponent and accepted
+//! by offering back before the next move. The game ends with `GameOver`.
+//!
+//! Chat is passed on to both players with the sender's name filled in and
+//! the text cut to `MAX_CHAT` characters. The server may decline to pass it
+//! on, when chat is disabled or a player sends too much of it, and says so
+//! in a `Chat` with an empty name.
+//!
+//! From version 2 the server follows its `Join` with a `Session` token. A
+//! client that loses its connection can reconnect and send `Resume` with the
+//! token in place of `Join`, and is answered with the same `Join` followed by
+//! its current view and clocks.
+//!
+//! From version 3 the server hosts many games. A client may send
+//! `ListGames` any number of times before choosing one, and then, in place of
+//! `Join`, either `CreateGame`, `JoinGame` or `Spectate`. A plain `Join`
+//! takes a seat in the oldest game still waiting for a player, or in a new
+//! game with the server's default settings. Spectators are answered with
+//! their `Spectate` request and then sent a `Position` after every move. The
+//! server may hold positions back a number of plies, so that spectators
+//! cannot pass on what the players cannot see, and sends the final position
+//! when the game ends. A fog game cannot be watched unless positions are held
+//! back, nor from the address of one of its players.
+//!
+//! From version 4 players and spectators are sent `Spectators` whenever the
+//! number watching changes.
+//!
+//! From version 6 the server answers `ListGames` with `ChatGames`, which
+//! also says whether each game passes chat on, and clients may create a game
+//! with `CreateChatGame` to choose that. Games listed with `Games` or
+//! created with `CreateGame` pass chat on unless the server says otherwise.
+//!
+//! Version 5 is spoken between two peers playing without a server, as
+//! described in `crate::peer`. Neither holds the whole board, so in place
+//! of `Move` and `Update` they exchange `Commit`, `Query`, `Answer` and
+//! `Played` each ply, and `Reveal` once the game ends.
+//!
+//! Bot processes see the same exchange from the server's side: after the
+//! handshake the engine sends an `Update` each time the bot is to move and
+//! the bot answers with a `Move`, left empty when it has nothing to play.
+
+use crate::{Board, Move, Player, PlayerView};
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+/// Version of the protocol described here.
+pub const VERSION: u16 = 6;
+
+/// First version with `Session` and `Resume`.
+pub const RESUME_VERSION: u16 = 2;
+
+/// First version with the lobby messages.
+pub const LOBBY_VERSION: u16 = 3;
+
+/// First version with `Spectators`.
+pub const SPECTATOR_VERSION: u16 = 4;
+
+/// First version with the messages peers play by.
+pub const PEER_VERSION: u16 = 5;
+
+/// First version with `ChatGames` and `CreateChatGame`.
+pub const CHAT_VERSION: u16 = 6;
+
+/// Oldest version still understood.
+pub const MIN_VERSION: u16 = 1;
+
+/// Largest payload accepted in a single frame, in bytes.
+pub const MAX_FRAME: u32 = 1 << 20;
+
+/// Longest chat text the server passes on, in characters.
+pub const MAX_CHAT: usize = 280;
+
+/// Messages exchanged once a connection is open. The same set is used in
+/// both directions.
+#[derive(Clone, Deserialize, Serialize)]
+pub enum Message {
+    /// Opens the handshake with the versions the sender speaks, and answers
+    /// it with the single version chosen.
+    Hello {
+        min_version: u16,
+        max_version: u16,
+        agent: String,
+    },
+    /// Asks to take part in the game, and confirms the side assigned.
+    Join {
+        name: String,
+        player: Option<Player>,
+    },
+    /// A ply: a single move, or the king and rook moves of a castle.
+    Move { moves: Vec<Move> },
+    /// The ply was not played.
+    MoveRejected { moves: Vec<Move>, reason: String },
+    /// What the receiving player can currently see.
+    Update(Box<PlayerView>),
+    /// Time left on each clock, indexed by `Player::index`, and whose clock
+    /// is running.
+    Clock {
+        remaining_ms: [u64; 2],
+        running: Option<Player>,
+    },
+    /// A line of chat. The server fills in the sender's name.
+    Chat { from: String, text: String },
+    /// The sender concedes the game.
+    Resign,
+    /// Offers a draw, or accepts one already offered. The server fills in
+    /// the player making the offer.
+    DrawOffer { player: Player },
+    /// The game has ended, with the winner if there is one.
+    GameOver {
+        winner: Option<Player>,
+        reason: String,
+    },
+    /// Token the client can later resume its seat with.
+    Session { token: String },
+    /// Takes back the seat the token was issued for, in place of `Join`.
+    Resume { token: String },
+    /// Asks for the games the server is hosting.
+    ListGames,
+    /// The games the server is hosting.
+    Games { games: Vec<GameInfo> },
+    /// Creates a game and takes a seat in it, in place of `Join`.
+    CreateGame {
+        name: String,
+        settings: GameSettings,
+    },
+    /// Takes a seat in the game with the id, in place of `Join`.
+    JoinGame { game: u64, name: String },
+    /// Watches the game with the id, in place of `Join`.
+    Spectate { game: u64 },
+    /// The whole board, as shown to spectators.
+    Position {
+        ply: u32,
+        turn: Player,
+        board: Box<Board>,
+    },
+    /// How many spectators are watching the game.
+    Spectators { count: u32 },
+    /// Commitments to every square of the sender's side of the board after
+    /// `ply` plies, indexed by `y * 8 + x`, and the sender's oblivious
+    /// transfer key for the ply.
+    Commit {
+        ply: u32,
+        squares: Vec<[u8; 32]>,
+        key: [u8; 32],
+    },
+    /// One oblivious transfer query per square, choosing the squares the
+    /// sender can see.
+    Query {
+        ply: u32,
+        round: u8,
+        points: Vec<[u8; 32]>,
+    },
+    /// The opening of every square, sealed so that only those chosen by the
+    /// matching `Query` can be read.
+    Answer {
+        ply: u32,
+        round: u8,
+        sealed: Vec<[[u8; 32]; 2]>,
+    },
+    /// The sender played the ply, capturing on the square if anything.
+    Played {
+        ply: u32,
+        captured: Option<(i32, i32)>,
+    },
+    /// The sender's plies and the seed behind its commitments and queries,
+    /// once the game is over.
+    Reveal {
+        seed: [u8; 32],
+        moves: Vec<Vec<Move>>,
+    },
+    /// The games the server is hosting, as in `Games`, with whether each
+    /// passes chat on. Sent in place of `Games` from version 6.
+    ChatGames {
+        games: Vec<GameInfo>,
+        chat: Vec<bool>,
+    },
+    /// Creates a game as `CreateGame` does, choosing whether it passes chat
+    /// on.
+    CreateChatGame {
+        name: String,
+        settings: GameSettings,
+        chat: bool,
+    },
+}
+
+/// Time control for a timed game: the time each player starts with, and the
+/// time added to their clock after each of their moves.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct TimeControl {
+    pub initial: Duration,
+    pub increment: Duration,
+}
+
+impl TimeControl {
+    /// Parse reads a time control written as minutes plus increment
+    /// seconds, such as `5+3`.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let (minutes, seconds) = match text.find('+') {
+            Some(at) => (&text[..at], &text[at + 1..]),
+            None => (text, "0"),
+        };
+        let minutes: f64 = minutes
+            .parse()
+            .map_err(|_| format!("bad minutes in time control {:?}", text))?;
+        let seconds: u64 = seconds
+            .parse()
+            .map_err(|_| format!("bad increment in time control {:?}", text))?;
+        if !(minutes > 0.0 && minutes.is_finite()) {
+            return Err(format!("time control {:?} needs some time", text));
+        }
+        Ok(TimeControl {
+            initial: Duration::from_secs_f64(minutes * 60.0),
+            increment: Duration::from_secs(seconds),
+        })
+    }
+}
+
+impl fmt::Display for TimeControl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}+{}",
+            self.initial.as_secs_f64() / 60.0,
+            self.increment.as_secs()
+        )
+    }
+}
+
+/// Settings chosen when creating a game.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct GameSettings {
+    /// Starting position, as accepted by `Board::variant`.
+    pub variant: String,
+    /// Hides from each player what they cannot see.
+    pub fog: bool,
+    /// Plays the game on the clock when set.
+    pub time_control: Option<TimeControl>,
+    /// Passes chat between the players. Off for tournament games, where it
+    /// could be used for outside help. The settings were first encoded
+    /// without it, so it travels beside them in `ChatGames` and
+    /// `CreateChatGame` instead.
+    #[serde(skip, default = "enabled")]
+    pub chat: bool,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+impl Default for GameSettings {
+    fn default() -> Self {
+        GameSettings {
+            variant: "standard".into(),
+            fog: true,
+            time_control: None,
+            chat: true,
+        }
+    }
+}
+
+impl fmt::Display for GameSettings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}, {}",
+            self.variant,
+            if self.fog { "fog" } else { "no fog" }
+        )?;
+        match &self.time_control {
+            Some(control) => write!(f, ", {}", control)?,
+            None => write!(f, ", untimed")?,
+        }
+        if !self.chat {
+            write!(f, ", no chat")?;
+        }
+        Ok(())
+    }
+}
+
+/// A game hosted by the server, as listed in its lobby.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct GameInfo {
+    pub id: u64,
+    pub settings: GameSettings,
+    /// Names of the seated players, indexed by `Player::index`.
+    pub players: [Option<String>; 2],
+    /// Set once both seats have first been filled.
+    pub started: bool,
+}
+
+impl Message {
+    /// Hello opening a handshake with every supported version.
+    pub fn hello(agent: &str) -> Self {
+        Message::Hello {
+            min_version: MIN_VERSION,
+            max_version: VERSION,
+            agent: agent.into(),
+        }
+    }
+}
+
+/// Negotiate picks the highest version within both ranges, if any.
+pub fn negotiate(min_version: u16, max_version: u16) -> Option<u16> {
+    let version = max_version.min(VERSION);
+    if version >= min_version.max(MIN_VERSION) {
+        Some(version)
+    } else {
+        None
+    }
+}
+
+fn options() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_limit(u64::from(MAX_FRAME))
+        .reject_trailing_bytes()
+}
+
+/// Encode serializes a message into a payload, without the length prefix.
+pub fn encode(message: &Message) -> Result<Vec<u8>, String> {
+    options()
+        .serialize(message)
+        .map_err(|err| format!("encoding message: {}", err))
+}
+
+/// Decode parses a payload produced by `encode`.
+pub fn decode(payload: &[u8]) -> Result<Message, String> {
+    options()
+        .deserialize(payload)
+        .map_err(|err| format!("decoding message: {}", err))
+}
+
+/// Write frame sends a message as a single frame.
+pub fn write_frame(mut writer: impl Write, message: &Message) -> io::Result<()> {
+    let payload =
+        encode(message).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    if payload.len() > MAX_FRAME as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("message of {} bytes exceeds the frame limit", payload.len()),
+        ));
+    }
+    let mut frame = (payload.len() as u32).to_be_bytes().to_vec();
+    frame.extend(payload);
+    writer.write_all(&frame)?;
+    writer.flush()
+}
+
+/// Read frame receives the next message, or `None` if the stream closed
+/// cleanly between frames. A payload that does not decode is reported as
+/// `InvalidData` and may be skipped; any other error ends the stream.
+pub fn read_frame(mut reader: impl Read) -> io::Result<Option<Message>> {
+    let mut prefix = [0; 4];
+    let mut filled = 0;
+    while filled < prefix.len() {
+        match reader.read(&mut prefix[filled..]) {
+            Ok(0) if filled == 0 => return Ok(None),
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => filled += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    let len = u32::from_be_bytes(prefix);
+    if len > MAX_FRAME {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("frame of {} bytes exceeds the limit", len),
+        ));
+    }
+    let mut payload = vec![0; len as usize];
+    reader.read_exact(&mut payload)?;
+    decode(&payload)
+        .map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Board, Game};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::io::Cursor;
+
+    fn samples() -> Vec<Message> {
+        let mut game = Game::headless(Board::new(), false, 7);
+        game.apply(&crate::Step {
+            player: Player::White,
+            moves: vec![((4, 1), (4, 3))],
+        });
+        vec![
+            Message::hello("test"),
+            Message::Join {
+                name: "anna".into(),
+                player: None,
+            },
+            Message::Join {
+                name: "ben".into(),
+                player: Some(Player::Black),
+            },
+            Message::Move {
+                moves: vec![((4, 0), (6, 0)), ((7, 0), (5, 0))],
+            },
+            Message::MoveRejected {
+                moves: vec![((0, 0), (0, 7))],
+                reason: "illegal move".into(),
+            },
+            Message::Update(Box::new(game.view_for(&Player::White))),
+            Message::Update(Box::new(game.view_for(&Player::Black))),
+            Message::Clock {
+                remaining_ms: [300_000, 12],
+                running: Some(Player::White),
+            },
+            Message::Chat {
+                from: "anna".into(),
+                text: "good luck ♞".into(),
+            },
+            Message::Resign,
+            Message::DrawOffer {
+                player: Player::Black,
+            },
+            Message::GameOver {
+                winner: None,
+                reason: "agreement".into(),
+            },
+            Message::Session {
+                token: "00ff".into(),
+            },
+            Message::Resume {
+                token: "00ff".into(),
+            },
+            Message::ListGames,
+            Message::Games {
+                games: vec![GameInfo {
+                    id: 3,
+                    settings: GameSettings {
+                        variant: "castle".into(),
+                        fog: false,
+                        time_control: Some(TimeControl::parse("5+3").unwrap()),
+                        chat: false,
+                    },
+                    players: [Some("anna".into()), None],
+                    started: false,
+                }],
+            },
+            Message::CreateGame {
+                name: "anna".into(),
+                settings: GameSettings::default(),
+            },
+            Message::JoinGame {
+                game: 3,
+                name: "ben".into(),
+            },
+            Message::Spectate { game: 3 },
+            Message::Position {
+                ply: 1,
+                turn: Player::Black,
+                board: Box::new(game.state.board.clone()),
+            },
+            Message::Spectators { count: 12 },
+            Message::Commit {
+                ply: 4,
+                squares: vec![[7; 32]; 64],
+                key: [1; 32],
+            },
+            Message::Query {
+                ply: 4,
+                round: 6,
+                points: vec![[2; 32]; 64],
+            },
+            Message::Answer {
+                ply: 4,
+                round: 6,
+                sealed: vec![[[3; 32], [4; 32]]; 64],
+            },
+            Message::Played {
+                ply: 5,
+                captured: Some((4, 4)),
+            },
+            Message::Reveal {
+                seed: [5; 32],
+                moves: vec![
+                    vec![((4, 1), (4, 3))],
+                    vec![((4, 0), (6, 0)), ((7, 0), (5, 0))],
+                ],
+            },
+            Message::ChatGames {
+                games: vec![],
+                chat: vec![],
+            },
+            Message::CreateChatGame {
+                name: "anna".into(),
+                settings: GameSettings::default(),
+                chat: false,
+            },
+        ]
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        for message in samples() {
+            let payload = encode(&message).unwrap();
+            let decoded = decode(&payload).unwrap();
+            assert_eq!(encode(&decoded).unwrap(), payload);
+        }
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let mut stream = vec![];
+        for message in samples() {
+            write_frame(&mut stream, &message).unwrap();
+        }
+        let mut reader = Cursor::new(stream);
+        for message in samples() {
+            let read = read_frame(&mut reader).unwrap().unwrap();
+            assert_eq!(encode(&read).unwrap(), encode(&message).unwrap());
+        }
+        assert!(read_frame(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn views_survive_the_wire() {
+        let game = Game::headless(Board::new(), false, 3);
+        let view = game.view_for(&Player::Black);
+        let payload = encode(&Message::Update(Box::new(view.clone()))).unwrap();
+        match decode(&payload).unwrap() {
+            Message::Update(decoded) => {
+                assert_eq!(decoded.visible, view.visible);
+                assert_eq!(decoded.moves(), view.moves());
+            }
+            _ => panic!("decoded a different message"),
+        }
+    }
+
+    #[test]
+    fn negotiation() {
+        assert_eq!(negotiate(MIN_VERSION, VERSION), Some(VERSION));
+        assert_eq!(negotiate(0, u16::MAX), Some(VERSION));
+        assert_eq!(negotiate(VERSION + 1, u16::MAX), None);
+        assert_eq!(negotiate(0, MIN_VERSION - 1), None);
+        assert_eq!(negotiate(1, 1), Some(1));
+    }
+
+    #[test]
+    fn variants_keep_their_tags() {
+        assert_eq!(encode(&Message::Resign).unwrap(), vec![7]);
+        let over = Message::GameOver {
+            winner: None,
+            reason: String::new(),
+        };
+        assert_eq!(encode(&over).unwrap(), vec![9, 0, 0]);
+    }
+
+    #[test]
+    fn settings_keep_their_version_3_encoding() {
+        let create = Message::CreateGame {
+            name: "a".into(),
+            settings: GameSettings {
+                chat: false,
+                ..GameSettings::default()
+            },
+        };
+        let mut expected = vec![14, 1, b'a', 8];
+        expected.extend(b"standard");
+        expected.extend(&[1, 0]);
+        let payload = encode(&create).unwrap();
+        assert_eq!(payload, expected);
+        match decode(&payload).unwrap() {
+            Message::CreateGame { settings, .. } => assert!(settings.chat),
+            _ => panic!("decoded a different message"),
+        }
+    }
+
+    #[test]
+    fn truncated_frames_fail() {
+        let mut frame = vec![];
+        write_frame(&mut frame, &samples()[5]).unwrap();
+        for len in 1..frame.len() {
+            let result = read_frame(Cursor::new(&frame[..len]));
+            assert!(result.is_err(), "accepted a frame cut to {} bytes", len);
+        }
+    }
+
+    #[test]
+    fn oversized_frames_are_refused_unread() {
+        let mut frame = (MAX_FRAME + 1).to_be_bytes().to_vec();
+        frame.extend(vec![0; 16]);
+        let err = read_frame(Cursor::new(frame)).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn trailing_bytes_are_rejected() {
+        let mut payload = encode(&Message::Resign).unwrap();
+        payload.push(0);
+        assert!(decode(&payload).is_err());
+    }
+
+    #[test]
+    fn fuzz_random_payloads() {
+        let mut rng = StdRng::seed_from_u64(42);
+        for _ in 0..20_000 {
+            let len = rng.gen_range(0, 64);
+            let payload: Vec<u8> = (0..len).map(|_| rng.gen()).collect();
+            if let Ok(message) = decode(&payload) {
+                assert!(decode(&encode(&message).unwrap()).is_ok());
+            }
+        }
+    }
+
+    #[test]
+    fn fuzz_mutated_frames() {
+        let mut rng = StdRng::seed_from_u64(43);
+        let frames: Vec<Vec<u8>> = samples()
+            .iter()
+            .map(|message| {
+                let mut frame = vec![];
+                write_frame(&mut frame, message).unwrap();
+                frame
+            })
+            .collect();
+        for _ in 0..20_000 {
+            let mut frame = frames[rng.gen_range(0, frames.len())].clone();
+            for _ in 0..rng.gen_range(1, 4) {
+                let at = rng.gen_range(0, frame.len());
+                match rng.gen_range(0, 3) {
+                    0 => frame[at] = rng.gen(),
+                    1 => frame[at] ^= 1 << rng.gen_range(0, 8),
+                    _ => frame.truncate(at.max(1)),
+                }
+            }
+            let mut reader = Cursor::new(frame);
+            // Whatever the bytes, reading must finish without panicking.
+            while let Ok(Some(_)) = read_frame(&mut reader) {}
+        }
+    }
+}
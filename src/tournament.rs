@@ -0,0 +1,620 @@
+//! Tournaments play every bot in a field against the others, in round-robin
+//! or Swiss pairings. Progress is saved after every game so an interrupted
+//! tournament can pick up where it stopped.
+
+use crate::arena::{self, Outcome, Record};
+use crate::bot;
+use crate::{Board, Game, Player};
+use rand::Rng;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// Opponent name recorded for a bye.
+pub const BYE: &str = "-";
+
+/// How players are paired.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Format {
+    /// Every player meets every other once per cycle.
+    RoundRobin,
+    /// Players meet others on a similar score, without rematches.
+    Swiss,
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "round-robin" => Some(Format::RoundRobin),
+            "swiss" => Some(Format::Swiss),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Format::RoundRobin => "round-robin",
+            Format::Swiss => "swiss",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Settings for a tournament.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TournamentConfig {
+    pub format: Format,
+    /// Number of cycles in a round-robin, or of rounds in a Swiss.
+    pub rounds: u32,
+    /// Games still undecided after this many plies are drawn.
+    pub max_plies: u32,
+    pub no_fog: bool,
+    /// Seeds every game, making the tournament reproducible apart from any
+    /// process bots.
+    pub seed: Option<u64>,
+}
+
+/// Tournament holds the field and every game played so far.
+#[derive(Clone, Debug)]
+pub struct Tournament {
+    /// Bot specifications, as accepted by `bot::by_spec`, in seeding order.
+    pub players: Vec<String>,
+    pub config: TournamentConfig,
+    pub records: Vec<Record>,
+}
+
+// Pairing of two players by index, White first. A missing opponent is a bye.
+type Pairing = (usize, Option<usize>);
+
+impl Tournament {
+    pub fn new(players: Vec<String>, config: TournamentConfig) -> Result<Self, String> {
+        if players.len() < 2 {
+            return Err("a tournament needs at least two players".into());
+        }
+        for (ii, player) in players.iter().enumerate() {
+            if players[..ii].contains(player) {
+                return Err(format!("{:?} is entered twice", player));
+            }
+            if player.contains(',') || player.contains('\n') || player == BYE {
+                return Err(format!("{:?} is not a valid player name", player));
+            }
+            if !player.starts_with("cmd:")
+                && !player.starts_with("proto:")
+                && !bot::BOTS.contains(&player.as_str())
+            {
+                return Err(format!("unknown bot {:?}", player));
+            }
+        }
+        Ok(Tournament {
+            players,
+            config,
+            records: vec![],
+        })
+    }
+
+    /// Rounds is the total number of rounds in the tournament.
+    pub fn rounds(&self) -> u32 {
+        match self.config.format {
+            Format::RoundRobin => {
+                let seats = self.players.len() + self.players.len() % 2;
+                self.config.rounds * (seats as u32 - 1)
+            }
+            Format::Swiss => self.config.rounds,
+        }
+    }
+
+    /// Run plays every game not yet recorded, in order. Each record is passed
+    /// to `on_game` as soon as its game finishes.
+    pub fn run(&mut self, mut on_game: impl FnMut(&Tournament, &Record)) -> Result<(), String> {
+        for round in 1..=self.rounds() {
+            for (board, pairing) in self.pairings(round).into_iter().enumerate() {
+                if self.played(round, pairing) {
+                    continue;
+                }
+                let record = match pairing {
+                    (white, Some(black)) => self.play(round, board as u64, white, black)?,
+                    (player, None) => Record {
+                        round,
+                        white: self.players[player].clone(),
+                        black: BYE.into(),
+                        outcome: Outcome::Win(Player::White),
+                        plies: 0,
+                        termination: "bye".into(),
+                        pgn: String::new(),
+                    },
+                };
+                self.records.push(record);
+                on_game(self, self.records.last().expect("record was just pushed"));
+            }
+        }
+        Ok(())
+    }
+
+    fn play(&self, round: u32, board: u64, white: usize, black: usize) -> Result<Record, String> {
+        let seed = self.config.seed.map_or_else(rand::random, |seed| {
+            arena::mix(seed, (u64::from(round) << 16) + board)
+        });
+        let mut game = Game::headless(Board::new(), self.config.no_fog, seed);
+        let (white, black) = (&self.players[white], &self.players[black]);
+        let mut white_bot = bot::by_spec(white, Some(game.state.rng.gen()))?;
+        let mut black_bot = bot::by_spec(black, Some(game.state.rng.gen()))?;
+        let (game, outcome, termination) = arena::play(
+            white_bot.as_mut(),
+            black_bot.as_mut(),
+            game,
+            self.config.max_plies,
+        );
+        Ok(arena::record(
+            &game,
+            round,
+            white,
+            black,
+            outcome,
+            termination,
+        ))
+    }
+
+    // Played checks whether the pairing already has a result in the round.
+    fn played(&self, round: u32, (white, black): Pairing) -> bool {
+        let black = black.map_or(BYE, |black| self.players[black].as_str());
+        self.records
+            .iter()
+            .any(|r| r.round == round && r.white == self.players[white] && r.black == black)
+    }
+
+    // Pairings for a round. They depend only on the results of earlier
+    // rounds, so a resumed tournament pairs exactly as the original would
+    // have.
+    fn pairings(&self, round: u32) -> Vec<Pairing> {
+        match self.config.format {
+            Format::RoundRobin => self.round_robin(round),
+            Format::Swiss => self.swiss(round),
+        }
+    }
+
+    // Round-robin pairings by the circle method: the first seat stays put
+    // while the others rotate one place each round. Colours swap every cycle.
+    fn round_robin(&self, round: u32) -> Vec<Pairing> {
+        let n = self.players.len();
+        let seats = n + n % 2;
+        let per_cycle = seats as u32 - 1;
+        let (cycle, r) = ((round - 1) / per_cycle, ((round - 1) % per_cycle) as usize);
+        let seat = |ii: usize| -> Option<usize> {
+            let player = if ii == 0 {
+                0
+            } else {
+                (ii - 1 + r) % (seats - 1) + 1
+            };
+            if player < n {
+                Some(player)
+            } else {
+                None
+            }
+        };
+        let mut pairings = vec![];
+        for ii in 0..seats / 2 {
+            let (mut a, mut b) = (seat(ii), seat(seats - 1 - ii));
+            if (r + ii + cycle as usize) % 2 == 1 {
+                std::mem::swap(&mut a, &mut b);
+            }
+            match (a, b) {
+                (Some(a), b) => pairings.push((a, b)),
+                (None, Some(b)) => pairings.push((b, None)),
+                (None, None) => {}
+            }
+        }
+        pairings
+    }
+
+    // Swiss pairings: players are ranked by points, then seed, and paired
+    // top down with the nearest player they haven't met. The lowest ranked
+    // player without a bye sits out when the field is odd.
+    fn swiss(&self, round: u32) -> Vec<Pairing> {
+        let earlier: Vec<&Record> = self.records.iter().filter(|r| r.round < round).collect();
+        let index = |name: &str| self.players.iter().position(|p| p == name);
+        let mut points = vec![0.0; self.players.len()];
+        let mut colour = vec![0i32; self.players.len()];
+        let mut byes = HashSet::new();
+        let mut met = HashSet::new();
+        for record in earlier.iter() {
+            let white = match index(&record.white) {
+                Some(white) => white,
+                None => continue,
+            };
+            points[white] += record.outcome.score(&Player::White);
+            match index(&record.black) {
+                Some(black) => {
+                    points[black] += record.outcome.score(&Player::Black);
+                    colour[white] += 1;
+                    colour[black] -= 1;
+                    met.insert((white.min(black), white.max(black)));
+                }
+                None => {
+                    byes.insert(white);
+                }
+            }
+        }
+        let mut ranked: Vec<usize> = (0..self.players.len()).collect();
+        ranked.sort_by(|a, b| {
+            points[*b]
+                .partial_cmp(&points[*a])
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then(a.cmp(b))
+        });
+        let mut bye = None;
+        if ranked.len() % 2 == 1 {
+            let last = ranked
+                .iter()
+                .rposition(|p| !byes.contains(p))
+                .unwrap_or(ranked.len() - 1);
+            bye = Some(ranked.remove(last));
+        }
+        let pairs = pair(&ranked, &met).unwrap_or_else(|| {
+            // Everyone has met everyone: allow rematches between neighbours.
+            ranked.chunks(2).map(|c| (c[0], c[1])).collect()
+        });
+        let mut pairings: Vec<Pairing> = pairs
+            .into_iter()
+            .map(|(a, b)| {
+                // The player who has had White less often gets it, with the
+                // higher ranked player taking White in odd rounds on a tie.
+                let a_white = match colour[a].cmp(&colour[b]) {
+                    std::cmp::Ordering::Less => true,
+                    std::cmp::Ordering::Greater => false,
+                    std::cmp::Ordering::Equal => round % 2 == 1,
+                };
+                if a_white {
+                    (a, Some(b))
+                } else {
+                    (b, Some(a))
+                }
+            })
+            .collect();
+        if let Some(bye) = bye {
+            pairings.push((bye, None));
+        }
+        pairings
+    }
+
+    /// Standings computes each player's results, in seeding order. A bye
+    /// scores a point but is not a game won.
+    pub fn standings(&self) -> Vec<Standing> {
+        let ratings = ratings(self.players.len(), &self.games());
+        let (byes, games): (Vec<Record>, Vec<Record>) = self
+            .records
+            .iter()
+            .cloned()
+            .partition(|record| record.black == BYE);
+        self.players
+            .iter()
+            .zip(ratings)
+            .map(|(name, elo)| {
+                let score = arena::Score::tally(name, &games);
+                let byes = byes.iter().filter(|record| record.white == *name).count() as u32;
+                Standing {
+                    name: name.clone(),
+                    points: f64::from(score.wins) + f64::from(score.draws) / 2.0 + f64::from(byes),
+                    score,
+                    byes,
+                    elo,
+                }
+            })
+            .collect()
+    }
+
+    // Games as (white, black, white's score) by player index, without byes.
+    fn games(&self) -> Vec<(usize, usize, f64)> {
+        let index = |name: &str| self.players.iter().position(|p| p == name);
+        self.records
+            .iter()
+            .filter_map(|r| {
+                Some((
+                    index(&r.white)?,
+                    index(&r.black)?,
+                    r.outcome.score(&Player::White),
+                ))
+            })
+            .collect()
+    }
+
+    /// Crosstable renders the standings with each player's score against
+    /// every opponent, best first.
+    pub fn crosstable(&self) -> String {
+        let standings = self.standings();
+        let mut order: Vec<usize> = (0..self.players.len()).collect();
+        order.sort_by(|a, b| {
+            let (a, b) = (&standings[*a], &standings[*b]);
+            b.points
+                .partial_cmp(&a.points)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then(
+                    b.elo
+                        .partial_cmp(&a.elo)
+                        .unwrap_or(std::cmp::Ordering::Equal),
+                )
+        });
+        let width = self
+            .players
+            .iter()
+            .map(|p| p.len())
+            .max()
+            .unwrap_or(0)
+            .max(6);
+        let mut out = format!("{:>3}  {:<width$}", "#", "player", width = width);
+        for column in 1..=order.len() {
+            out.push_str(&format!(" {:>5}", column));
+        }
+        out.push_str("  points   +  =  - bye    elo\n");
+        let games = self.games();
+        for (rank, &row) in order.iter().enumerate() {
+            let standing = &standings[row];
+            out.push_str(&format!(
+                "{:>3}  {:<width$}",
+                rank + 1,
+                standing.name,
+                width = width
+            ));
+            for &column in order.iter() {
+                let mut cell: Option<f64> = None;
+                for &(white, black, score) in games.iter() {
+                    let score = if (white, black) == (row, column) {
+                        score
+                    } else if (white, black) == (column, row) {
+                        1.0 - score
+                    } else {
+                        continue;
+                    };
+                    *cell.get_or_insert(0.0) += score;
+                }
+                let cell = match cell {
+                    _ if row == column => "x".into(),
+                    Some(points) => format!("{}", points),
+                    None => ".".into(),
+                };
+                out.push_str(&format!(" {:>5}", cell));
+            }
+            out.push_str(&format!(
+                "  {:>6} {:>3}{:>3}{:>3}{:>4}  {:>5.0}\n",
+                standing.points,
+                standing.score.wins,
+                standing.score.draws,
+                standing.score.losses,
+                standing.byes,
+                standing.elo
+            ));
+        }
+        out
+    }
+
+    /// Save writes the tournament to a progress file, replacing it
+    /// atomically so an interruption never leaves a truncated file.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let mut out = String::from("# fog of chess tournament\n");
+        out.push_str(&format!("format {}\n", self.config.format));
+        out.push_str(&format!("rounds {}\n", self.config.rounds));
+        out.push_str(&format!("max-plies {}\n", self.config.max_plies));
+        out.push_str(&format!("no-fog {}\n", self.config.no_fog));
+        if let Some(seed) = self.config.seed {
+            out.push_str(&format!("seed {}\n", seed));
+        }
+        for player in self.players.iter() {
+            out.push_str(&format!("player {}\n", player));
+        }
+        for record in self.records.iter() {
+            out.push_str(&format!("game {}\n", record.csv()));
+        }
+        let partial = path.with_extension("partial");
+        fs::write(&partial, out)
+            .and_then(|_| fs::rename(&partial, path))
+            .map_err(|err| format!("saving {}: {}", path.display(), err))
+    }
+
+    /// Load reads a tournament back from a progress file written by `save`.
+    /// Saved games keep their results but not their moves.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|err| format!("reading {}: {}", path.display(), err))?;
+        let mut config = TournamentConfig {
+            format: Format::RoundRobin,
+            rounds: 1,
+            max_plies: 300,
+            no_fog: false,
+            seed: None,
+        };
+        let mut players = vec![];
+        let mut records = vec![];
+        for (ii, line) in text.lines().enumerate() {
+            let bad = || format!("{}:{}: malformed line {:?}", path.display(), ii + 1, line);
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line.split_at(line.find(' ').ok_or_else(bad)?);
+            let value = &value[1..];
+            match key {
+                "format" => config.format = Format::parse(value).ok_or_else(bad)?,
+                "rounds" => config.rounds = value.parse().map_err(|_| bad())?,
+                "max-plies" => config.max_plies = value.parse().map_err(|_| bad())?,
+                "no-fog" => config.no_fog = value.parse().map_err(|_| bad())?,
+                "seed" => config.seed = Some(value.parse().map_err(|_| bad())?),
+                "player" => players.push(value.to_string()),
+                "game" => records.push(parse_record(value).ok_or_else(bad)?),
+                _ => return Err(bad()),
+            }
+        }
+        let mut tournament = Tournament::new(players, config)?;
+        tournament.records = records;
+        Ok(tournament)
+    }
+}
+
+// Parse a record rendered by `Record::csv`.
+fn parse_record(line: &str) -> Option<Record> {
+    let fields: Vec<&str> = line.splitn(6, ',').collect();
+    if fields.len() != 6 {
+        return None;
+    }
+    let outcome = match fields[3] {
+        "1-0" => Outcome::Win(Player::White),
+        "0-1" => Outcome::Win(Player::Black),
+        "1/2-1/2" => Outcome::Draw,
+        _ => return None,
+    };
+    Some(Record {
+        round: fields[0].parse().ok()?,
+        white: fields[1].into(),
+        black: fields[2].into(),
+        outcome,
+        plies: fields[4].parse().ok()?,
+        termination: fields[5].into(),
+        pgn: String::new(),
+    })
+}
+
+// Pair players in order, each with the nearest player below them they have
+// not met, backtracking when that strands someone further down.
+fn pair(players: &[usize], met: &HashSet<(usize, usize)>) -> Option<Vec<(usize, usize)>> {
+    let (first, rest) = match players.split_first() {
+        Some(split) => split,
+        None => return Some(vec![]),
+    };
+    for (ii, &other) in rest.iter().enumerate() {
+        if met.contains(&((*first).min(other), (*first).max(other))) {
+            continue;
+        }
+        let mut remaining = rest.to_vec();
+        remaining.remove(ii);
+        if let Some(mut pairs) = pair(&remaining, met) {
+            pairs.insert(0, (*first, other));
+            return Some(pairs);
+        }
+    }
+    None
+}
+
+/// Standing summarises one player's tournament.
+#[derive(Clone, Debug)]
+pub struct Standing {
+    pub name: String,
+    pub points: f64,
+    /// Games won, drawn and lost, without byes.
+    pub score: arena::Score,
+    /// Rounds sat out, each worth a point.
+    pub byes: u32,
+    /// Estimated Elo rating, with the field averaging 1500.
+    pub elo: f64,
+}
+
+// Ratings estimates Elo ratings from game results by maximum likelihood.
+// Each player also draws one virtual game against an average opponent, which
+// keeps ratings finite for players who won or lost everything.
+fn ratings(players: usize, games: &[(usize, usize, f64)]) -> Vec<f64> {
+    let expected = |a: f64, b: f64| 1.0 / (1.0 + 10f64.powf((b - a) / 400.0));
+    let mut ratings = vec![0.0; players];
+    for _ in 0..1_000 {
+        let mut gradient = vec![0.0; players];
+        let mut count = vec![1.0; players];
+        for (ii, rating) in ratings.iter().enumerate() {
+            gradient[ii] += 0.5 - expected(*rating, 0.0);
+        }
+        for &(white, black, score) in games {
+            let e = expected(ratings[white], ratings[black]);
+            gradient[white] += score - e;
+            gradient[black] -= score - e;
+            count[white] += 1.0;
+            count[black] += 1.0;
+        }
+        let mut change: f64 = 0.0;
+        for ii in 0..players {
+            let step = 400.0 * gradient[ii] / count[ii];
+            ratings[ii] += step;
+            change = change.max(step.abs());
+        }
+        if change < 0.01 {
+            break;
+        }
+    }
+    let mean = ratings.iter().sum::<f64>() / players.max(1) as f64;
+    ratings.iter().map(|r| r - mean + 1500.0).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(round: u32, white: &str, black: &str, outcome: Outcome) -> Record {
+        Record {
+            round,
+            white: white.into(),
+            black: black.into(),
+            outcome,
+            plies: 0,
+            termination: String::new(),
+            pgn: String::new(),
+        }
+    }
+
+    #[test]
+    fn byes_score_points_but_not_wins() {
+        let players = vec!["random".into(), "ismcts".into(), "alphabeta".into()];
+        let config = TournamentConfig {
+            format: Format::RoundRobin,
+            rounds: 1,
+            max_plies: 300,
+            no_fog: false,
+            seed: Some(1),
+        };
+        let mut tournament = Tournament::new(players, config).unwrap();
+        // An odd field leaves one player out of every round, each once.
+        let mut sat_out = vec![];
+        for round in 1..=tournament.rounds() {
+            let pairings = tournament.pairings(round);
+            let byes: Vec<usize> = pairings
+                .iter()
+                .filter(|(_, black)| black.is_none())
+                .map(|(white, _)| *white)
+                .collect();
+            assert_eq!(byes.len(), 1);
+            sat_out.extend(byes);
+        }
+        sat_out.sort_unstable();
+        assert_eq!(sat_out, vec![0, 1, 2]);
+        let white = Outcome::Win(Player::White);
+        tournament.records = vec![
+            record(1, "random", "ismcts", white),
+            record(1, "alphabeta", BYE, white),
+            record(2, "alphabeta", "random", Outcome::Draw),
+            record(2, "ismcts", BYE, white),
+        ];
+        let standings = tournament.standings();
+        let summary: Vec<(f64, u32, u32, u32, u32)> = standings
+            .iter()
+            .map(|s| {
+                (
+                    s.points,
+                    s.score.wins,
+                    s.score.draws,
+                    s.score.losses,
+                    s.byes,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![(1.5, 1, 1, 0, 0), (1.0, 0, 0, 1, 1), (1.5, 0, 1, 0, 1)]
+        );
+        let crosstable = tournament.crosstable();
+        assert!(crosstable.contains(" bye "), "{}", crosstable);
+        let alphabeta = crosstable
+            .lines()
+            .find(|line| line.contains("alphabeta"))
+            .unwrap();
+        assert!(
+            alphabeta.contains("   1.5   0  1  0   1  "),
+            "{}",
+            alphabeta
+        );
+    }
+}